@@ -0,0 +1,96 @@
+use std::f64::consts::FRAC_PI_2;
+use std::time::Duration;
+use gstreamer::ClockTime;
+use crate::opensonic::types::Song;
+
+/// Longest crossfade that can be set
+pub const MAX_CROSSFADE: Duration = Duration::from_secs(12);
+/// Fades that would be shorter than this, because the song is almost over, are left out
+pub const MIN_CROSSFADE: Duration = Duration::from_secs(1);
+/// How long before the fade the next song starts loading.
+/// If loading takes longer, it joins the fade late, in time with the song fading out.
+pub const PREPARE_LEAD: Duration = Duration::from_secs(2);
+
+/// Whether going from `from` to `to` should be crossfaded. Consecutive tracks of an album
+/// aren't, they're often meant to run into each other.
+pub fn should_crossfade(from: &Song, to: &Song) -> bool {
+    let same_album = from.album_id.is_some() && from.album_id == to.album_id;
+    let same_disc = from.disc_number.unwrap_or(1) == to.disc_number.unwrap_or(1);
+    let consecutive = matches!((from.track, to.track), (Some(a), Some(b)) if b == a + 1);
+    !(same_album && same_disc && consecutive)
+}
+
+/// Volume of the song fading in at `progress` (0 to 1), the one fading out is at `fade_level(1 - progress)`.
+/// Equal power, so the loudness stays about the same during the fade.
+pub fn fade_level(progress: f64) -> f64 {
+    (progress.clamp(0.0, 1.0) * FRAC_PI_2).sin()
+}
+
+/// Fade over part of a song. It goes by the position in the song of the audio it's applied to,
+/// so it stays in step with the stream whatever the pipeline does.
+#[derive(Clone, Copy, Debug)]
+pub struct Fade {
+    pub start: ClockTime,
+    pub duration: ClockTime,
+    pub fading_in: bool,
+}
+
+impl Fade {
+    /// Volume at `position` in the song
+    pub fn level(&self, position: ClockTime) -> f64 {
+        let progress = position.saturating_sub(self.start).nseconds() as f64 / self.duration.nseconds().max(1) as f64;
+        fade_level(if self.fading_in { progress } else { 1.0 - progress })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(album: Option<&str>, disc: Option<u32>, track: Option<i32>) -> Song {
+        Song {
+            album_id: album.map(|a| a.to_string()),
+            disc_number: disc,
+            track,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn consecutive_album_tracks_are_not_crossfaded() {
+        assert!(!should_crossfade(&song(Some("a"), None, Some(3)), &song(Some("a"), None, Some(4))));
+        assert!(!should_crossfade(&song(Some("a"), Some(1), Some(3)), &song(Some("a"), None, Some(4))));
+        assert!(should_crossfade(&song(Some("a"), None, Some(4)), &song(Some("a"), None, Some(3))));
+        assert!(should_crossfade(&song(Some("a"), None, Some(3)), &song(Some("a"), None, Some(5))));
+        assert!(should_crossfade(&song(Some("a"), Some(1), Some(3)), &song(Some("a"), Some(2), Some(4))));
+        assert!(should_crossfade(&song(Some("a"), None, Some(3)), &song(Some("b"), None, Some(4))));
+        assert!(should_crossfade(&song(None, None, Some(3)), &song(None, None, Some(4))));
+        assert!(should_crossfade(&song(Some("a"), None, None), &song(Some("a"), None, None)));
+    }
+
+    #[test]
+    fn fade_keeps_power_constant() {
+        assert_eq!(fade_level(0.0), 0.0);
+        assert_eq!(fade_level(1.0), 1.0);
+        assert_eq!(fade_level(2.0), 1.0);
+        for i in 0..=10 {
+            let progress = i as f64 / 10.0;
+            let power = fade_level(progress).powi(2) + fade_level(1.0 - progress).powi(2);
+            assert!((power - 1.0).abs() < 1e-9, "{progress}: {power}");
+        }
+    }
+
+    #[test]
+    fn fades_follow_the_song_position() {
+        let fade_out = Fade { start: ClockTime::from_seconds(200), duration: ClockTime::from_seconds(10), fading_in: false };
+        assert_eq!(fade_out.level(ClockTime::from_seconds(100)), 1.0);
+        assert_eq!(fade_out.level(ClockTime::from_seconds(200)), 1.0);
+        assert_eq!(fade_out.level(ClockTime::from_seconds(210)), 0.0);
+        assert_eq!(fade_out.level(ClockTime::from_seconds(220)), 0.0);
+
+        let fade_in = Fade { start: ClockTime::ZERO, duration: ClockTime::from_seconds(10), fading_in: true };
+        assert_eq!(fade_in.level(ClockTime::ZERO), 0.0);
+        assert_eq!(fade_in.level(ClockTime::from_seconds(5)), fade_out.level(ClockTime::from_seconds(205)));
+        assert_eq!(fade_in.level(ClockTime::from_seconds(30)), 1.0);
+    }
+}
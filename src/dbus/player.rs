@@ -1,4 +1,4 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::error::Error;
 use crate::opensonic::client::OpenSubsonicClient;
 use crate::player::{PlayerInfo, SongEntry, TrackList, MAX_PLAYBACK_RATE, MIN_PLAYBACK_RATE};
@@ -8,6 +8,7 @@ use std::rc::Rc;
 use std::sync::Arc;
 use async_channel::Sender;
 use std::time::Duration;
+use uuid::Uuid;
 use gstreamer_play::PlayState;
 use mpris_server::{LocalPlayerInterface, Metadata, Time, TrackId, LoopStatus, PlaybackStatus, Property, LocalServer, TrackListSignal, Signal, zbus::fdo};
 use relm4::adw::gio::Settings;
@@ -33,6 +34,9 @@ pub struct MprisPlayer {
     pub song_cache: SongCache,
     pub album_cache: AlbumCache,
     pub settings: Settings,
+    /// Set once the queue saved by the last run is back, or there was none.
+    /// Until then an empty queue isn't saved over it.
+    pub queue_restored: Cell<bool>,
 }
 
 pub async fn get_song_metadata<'a>(song: Option<&SongEntry>, client: &'static OpenSubsonicClient) -> Metadata {
@@ -166,11 +170,11 @@ impl MprisPlayer {
         }
     }
 
-    pub async fn track_list_replaced(&self, songs: &Vec<SongEntry>, current_i: Option<usize>) -> Result<(), zbus::Error> {
+    pub async fn track_list_replaced(&self, track_list: &TrackList) -> Result<(), zbus::Error> {
         if let Some(server) = self.server.borrow().as_ref() {
             server.track_list_emit(TrackListSignal::TrackListReplaced {
-                tracks: songs.iter().map(|s| s.dbus_obj()).collect(),
-                current_track: if let Some(i) = current_i && let Some(current) = songs.get(i) {
+                tracks: track_list.ordered_entries().iter().map(|s| s.dbus_obj()).collect(),
+                current_track: if let Some(current) = track_list.current() {
                     current.dbus_obj()
                 } else {
                     TrackId::NO_TRACK
@@ -192,7 +196,13 @@ impl MprisPlayer {
     }
 
     pub fn reload_settings(&self) -> Result<(), Box<dyn Error>> {
-        self.player_ref.load_settings(&self.settings)
+        let shuffle_mode = self.track_list().borrow().shuffle_mode();
+        self.player_ref.load_settings(&self.settings)?;
+        // A new shuffle mode reshuffles the rest of the queue
+        if self.player_ref.shuffled() && self.track_list().borrow().shuffle_mode() != shuffle_mode {
+            self.send_tl_msg(TrackListMsg::TrackChanged);
+        }
+        Ok(())
     }
 
     pub async fn current_song_metadata(&self) -> Metadata {
@@ -243,7 +253,18 @@ impl MprisPlayer {
     }
 
     pub async fn stop(&self) {
+        let cleared = {
+            let mut guard = self.track_list().borrow_mut();
+            let cleared = !guard.empty();
+            if cleared {
+                guard.checkpoint();
+            }
+            cleared
+        };
         self.player_ref.stop();
+        if cleared {
+            self.send_app_msg(AppMsg::ShowUndoToast("Queue cleared".to_string()));
+        }
         self.send_cs_msg(CurrentSongMsg::SongUpdate(None));
         self.send_tl_msg(TrackListMsg::ReloadList);
         self.properties_changed([
@@ -251,6 +272,21 @@ impl MprisPlayer {
         ]).await;
     }
 
+    /// Catches the UI and MPRIS up once a gapless switch to the next song actually happens
+    pub async fn gapless_switch(&self, uuid: Uuid) {
+        let s = self.player_ref.advance_gapless(uuid).await;
+        self.send_cs_msg(CurrentSongMsg::SongUpdate(s));
+        self.send_tl_msg(TrackListMsg::TrackChanged);
+        self.properties_changed([
+            Property::Metadata(self.current_song_metadata().await),
+        ]).await;
+    }
+
+    /// Starts crossfading into the next song, the song change is announced like a gapless one
+    pub async fn crossfade(&self) {
+        self.player_ref.crossfade().await;
+    }
+
     pub fn set_loop_status(&self, loop_status: LoopStatus){
         self.player_ref.set_loop_status(loop_status);
         self.send_cs_msg(CurrentSongMsg::Update);
@@ -312,7 +348,7 @@ impl LocalPlayerInterface for MprisPlayer {
             }
         }
         self.send_cs_msg(CurrentSongMsg::SongUpdate(s));
-        self.send_tl_msg(TrackListMsg::TrackChanged(None));
+        self.send_tl_msg(TrackListMsg::TrackChanged);
         self.properties_changed([
             Property::Metadata(self.current_song_metadata().await),
         ]).await;
@@ -322,7 +358,7 @@ impl LocalPlayerInterface for MprisPlayer {
     async fn previous(&self) -> fdo::Result<()> {
         let s = self.player_ref.previous().await;
         self.send_cs_msg(CurrentSongMsg::SongUpdate(s));
-        self.send_tl_msg(TrackListMsg::TrackChanged(None));
+        self.send_tl_msg(TrackListMsg::TrackChanged);
         self.properties_changed([
             Property::Metadata(self.current_song_metadata().await),
         ]).await;
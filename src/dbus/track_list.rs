@@ -1,20 +1,28 @@
 use std::error::Error;
+use rand::seq::SliceRandom;
 use std::rc::Rc;
+use std::time::Duration;
 use crate::player::{SongEntry};
+use crate::queue_state::SavedQueue;
 use mpris_server::{zbus::fdo, LocalTrackListInterface, Metadata, Property, TrackId, TrackListSignal};
 use crate::dbus::player::{get_song_metadata, MprisPlayer};
 use crate::opensonic::types::Song;
+use crate::ui::app::AppMsg;
 use crate::ui::current_song::CurrentSongMsg;
 use crate::ui::track_list::{MoveDirection, TrackListMsg};
+use gstreamer_play::PlayState;
+use tracing::debug;
 
 impl MprisPlayer {
     pub async fn add_track_to_index(&self, uri: String, index: Option<usize>, set_as_current: bool) -> Result<(), Box<dyn Error>> {
         let mut track_list_guard = self.track_list().borrow_mut();
+        let checkpoint = track_list_guard.begin_edit();
         match track_list_guard
             .add_song_from_uri(&*uri, &self.song_cache, index)
             .await
         {
             None => {
+                track_list_guard.commit_edit(checkpoint);
                 let songs = track_list_guard.get_songs();
                 let new_i = index.unwrap_or(songs.len() - 1);
                 self.track_list_emit(TrackListSignal::TrackAdded {
@@ -42,10 +50,16 @@ impl MprisPlayer {
     }
 
     pub async fn set_song(&self, song: Rc<Song>) -> Result<(), Box<dyn Error>> {
-        {
+        let replaced = {
             let mut guard = self.track_list().borrow_mut();
+            let replaced = !guard.empty();
+            guard.checkpoint();
             guard.clear();
             guard.insert_song(song, None);
+            replaced
+        };
+        if replaced {
+            self.send_app_msg(AppMsg::ShowUndoToast("Queue replaced".to_string()));
         }
         let song = self.player_ref.start_current().await?;
         self.send_cs_msg(CurrentSongMsg::SongUpdate(song));
@@ -53,29 +67,36 @@ impl MprisPlayer {
             Property::Metadata(self.current_song_metadata().await),
         ]).await;
         let guard = self.track_list().borrow();
-        self.track_list_replaced(guard.get_songs(), guard.current_index()).await?;
+        self.track_list_replaced(&guard).await?;
         self.send_tl_msg(TrackListMsg::ReloadList);
         Ok(())
     }
 
     pub async fn queue_songs(&self, songs: Vec<Rc<Song>>, set_index: Option<usize>, clear_previous: bool) -> Result<(), Box<dyn Error>> {
         let song_changed;
+        let replaced;
         {
             let mut guard = self.track_list().borrow_mut();
+            replaced = clear_previous && !guard.empty();
+            guard.checkpoint();
             let len = if clear_previous {
                 guard.clear();
                 0
             } else {
                 guard.get_songs().len()
             };
+            let idle = guard.current().is_none();
             guard.add_songs(songs);
             if let Some(index) = set_index {
                 guard.set_current(len+index);
                 song_changed = true;
             } else {
-                song_changed = len==0;
+                song_changed = idle;
             }
         }
+        if replaced {
+            self.send_app_msg(AppMsg::ShowUndoToast("Queue replaced".to_string()));
+        }
         if song_changed {
             let song = self.player_ref.start_current().await?;
             self.send_cs_msg(CurrentSongMsg::SongUpdate(song));
@@ -84,14 +105,35 @@ impl MprisPlayer {
             ]).await;
         }
         let guard = self.track_list().borrow();
-        self.track_list_replaced(guard.get_songs(), guard.current_index()).await?;
+        self.track_list_replaced(&guard).await?;
+        self.send_tl_msg(TrackListMsg::ReloadList);
+        Ok(())
+    }
+
+    /// Adds songs to the up next queue, which plays before the rest of the queue
+    pub async fn play_next(&self, songs: Vec<Rc<Song>>) -> Result<(), Box<dyn Error>> {
+        let started = {
+            let mut guard = self.track_list().borrow_mut();
+            guard.checkpoint();
+            guard.add_up_next(songs);
+            guard.start_up_next()
+        };
+        if started {
+            let song = self.player_ref.start_current().await?;
+            self.send_cs_msg(CurrentSongMsg::SongUpdate(song));
+            self.properties_changed([
+                Property::Metadata(self.current_song_metadata().await),
+            ]).await;
+        }
+        let guard = self.track_list().borrow();
+        self.track_list_replaced(&guard).await?;
         self.send_tl_msg(TrackListMsg::ReloadList);
         Ok(())
     }
 
     pub async fn queue_random(&self, size: u32, genre: Option<String>, from_year: Option<u32>, to_year: Option<u32>, clear_previous: bool) -> Result<(), Box<dyn Error>> {
         let songs = self.song_cache.get_random_songs(Some(size), genre.as_deref(), from_year, to_year, None).await?;
-        println!("Added {} random songs", songs.len());
+        debug!("Added {} random songs", songs.len());
         self.queue_songs(songs, None, clear_previous).await
     }
 
@@ -108,9 +150,92 @@ impl MprisPlayer {
         Ok(())
     }
 
+    pub async fn album_songs(&self, id: &str) -> Result<Vec<Rc<Song>>, Box<dyn Error>> {
+        let album = self.album_cache.get_album(id).await?;
+        Ok(match album.get_songs() {
+            Some(songs) => self.song_cache.add_songs(songs).await,
+            None => Vec::new(),
+        })
+    }
+
+    /// Songs of all the artist's albums, in album order
+    pub async fn artist_songs(&self, id: &str) -> Result<Vec<Rc<Song>>, Box<dyn Error>> {
+        let artist = self.client.get_artist(id).await?;
+        let mut songs = Vec::new();
+        for album in artist.albums.unwrap_or_default() {
+            songs.extend(self.album_songs(&album.id).await?);
+        }
+        Ok(songs)
+    }
+
+    /// Songs behind a `sanic://song/<id>`, `sanic://album/<id>` or `sanic://artist/<id>` URI
+    pub async fn songs_from_uri(&self, uri: &str) -> Result<Vec<Rc<Song>>, Box<dyn Error>> {
+        let (uri_type, id) = uri.strip_prefix("sanic://")
+            .and_then(|rest| rest.split_once('/'))
+            .ok_or_else(|| format!("Invalid URI: {}", uri))?;
+        match uri_type {
+            "song" => Ok(vec![self.song_cache.get_song(id).await?]),
+            "album" => self.album_songs(id).await,
+            "artist" => self.artist_songs(id).await,
+            _ => Err(format!("Unsupported URI: {}", uri).into()),
+        }
+    }
+
+    /// Inserts songs at `index` in the queue, or at the end, starting playback if nothing was playing
+    pub async fn insert_songs(&self, songs: Vec<Rc<Song>>, index: Option<usize>) -> Result<(), Box<dyn Error>> {
+        let idle = {
+            let mut guard = self.track_list().borrow_mut();
+            let idle = guard.current().is_none();
+            guard.checkpoint();
+            guard.insert_songs(songs, index);
+            idle
+        };
+        if idle {
+            let song = self.player_ref.start_current().await?;
+            self.send_cs_msg(CurrentSongMsg::SongUpdate(song));
+            self.properties_changed([
+                Property::Metadata(self.current_song_metadata().await),
+            ]).await;
+        }
+        let guard = self.track_list().borrow();
+        self.track_list_replaced(&guard).await?;
+        self.send_tl_msg(TrackListMsg::ReloadList);
+        Ok(())
+    }
+
+    pub async fn insert_uris(&self, uris: Vec<String>, index: Option<usize>) -> Result<(), Box<dyn Error>> {
+        let mut songs = Vec::new();
+        for uri in uris {
+            songs.extend(self.songs_from_uri(&uri).await?);
+        }
+        self.insert_songs(songs, index).await
+    }
+
+    /// Moves queue entries so they're together before the entry at `to`
+    pub async fn move_items(&self, indexes: Vec<usize>, to: usize) -> Result<(), Box<dyn Error>> {
+        {
+            let mut guard = self.track_list().borrow_mut();
+            let checkpoint = guard.begin_edit();
+            guard.move_songs(&indexes, to).ok_or("No songs to move")?;
+            guard.commit_edit(checkpoint);
+        }
+        let guard = self.track_list().borrow();
+        self.track_list_replaced(&guard).await?;
+        self.send_tl_msg(TrackListMsg::ReloadList);
+        Ok(())
+    }
+
+    pub async fn queue_artist(&self, id: &str, shuffle: bool, clear_previous: bool) -> Result<(), Box<dyn Error>> {
+        let mut songs = self.artist_songs(id).await?;
+        if shuffle {
+            songs.shuffle(&mut rand::rng());
+        }
+        self.queue_songs(songs, None, clear_previous).await
+    }
+
     pub async fn goto(&self, i: usize) -> Result<(), Box<dyn Error>>{
         let song = self.player_ref.goto(i).await?;
-        self.send_tl_msg(TrackListMsg::TrackChanged(Some(i)));
+        self.send_tl_msg(TrackListMsg::TrackChanged);
         self.send_cs_msg(CurrentSongMsg::SongUpdate(song));
         self.properties_changed([
             Property::Metadata(self.current_song_metadata().await),
@@ -118,8 +243,54 @@ impl MprisPlayer {
         Ok(())
     }
 
+    pub async fn goto_up_next(&self, i: usize) -> Result<(), Box<dyn Error>> {
+        let song = self.player_ref.goto_up_next(i).await?;
+        self.send_tl_msg(TrackListMsg::TrackChanged);
+        self.send_cs_msg(CurrentSongMsg::SongUpdate(song));
+        self.properties_changed([
+            Property::Metadata(self.current_song_metadata().await),
+        ]).await;
+        Ok(())
+    }
+
+    pub fn remove_up_next(&self, i: usize) -> Result<(), Box<dyn Error>> {
+        let e = {
+            let mut guard = self.track_list().borrow_mut();
+            let checkpoint = guard.begin_edit();
+            let e = guard.remove_up_next(i).ok_or("No up next song at index")?;
+            guard.commit_edit(checkpoint);
+            e
+        };
+        self.track_list_emit(TrackListSignal::TrackRemoved {
+            track_id: e.dbus_obj()
+        });
+        self.send_tl_msg(TrackListMsg::ReloadList);
+        Ok(())
+    }
+
+    pub async fn clear_up_next(&self) -> Result<(), Box<dyn Error>> {
+        {
+            let mut guard = self.track_list().borrow_mut();
+            guard.checkpoint();
+            guard.clear_up_next();
+        }
+        self.send_app_msg(AppMsg::ShowUndoToast("Up next cleared".to_string()));
+        let guard = self.track_list().borrow();
+        self.track_list_replaced(&guard).await?;
+        self.send_tl_msg(TrackListMsg::ReloadList);
+        Ok(())
+    }
+
     pub async fn remove(&self, i: usize) -> Result<(), Box<dyn Error>> {
+        {
+            let mut guard = self.track_list().borrow_mut();
+            if i >= guard.get_songs().len() {
+                return Err("No song at index".into());
+            }
+            guard.checkpoint();
+        }
         let e = self.player_ref.remove_song(i).await?;
+        self.send_app_msg(AppMsg::ShowUndoToast(format!("Removed {}", e.song.title)));
         self.send_tl_msg(TrackListMsg::ReloadList);
         self.track_list_emit(TrackListSignal::TrackRemoved {
             track_id: e.dbus_obj()
@@ -131,24 +302,160 @@ impl MprisPlayer {
         Ok(())
     }
 
+    pub async fn remove_many(&self, mut indexes: Vec<usize>) -> Result<(), Box<dyn Error>> {
+        indexes.sort_unstable();
+        indexes.dedup();
+        {
+            let mut guard = self.track_list().borrow_mut();
+            if indexes.last().is_some_and(|i| *i >= guard.get_songs().len()) {
+                return Err("No song at index".into());
+            }
+            guard.checkpoint();
+        }
+        let count = indexes.len();
+        // Going from the back keeps the remaining indexes valid
+        for i in indexes.into_iter().rev() {
+            let e = self.player_ref.remove_song(i).await?;
+            self.track_list_emit(TrackListSignal::TrackRemoved {
+                track_id: e.dbus_obj()
+            });
+        }
+        self.send_app_msg(AppMsg::ShowUndoToast(format!("Removed {} songs", count)));
+        self.send_tl_msg(TrackListMsg::ReloadList);
+        self.send_cs_msg(CurrentSongMsg::SongUpdate(self.track_list().borrow().current().cloned()));
+        self.properties_changed([
+            Property::Metadata(self.current_song_metadata().await),
+        ]).await;
+        Ok(())
+    }
+
     pub async fn move_item(&self, index: usize, direction: MoveDirection) -> Result<(), Box<dyn Error>> {
         let mut guard = self.track_list().borrow_mut();
+        let checkpoint = guard.begin_edit();
         let new_i = guard.move_song(index, direction);
         if let Some(new_i) = new_i {
+            guard.commit_edit(checkpoint);
             let moved = guard.song_at_index(new_i).ok_or("No song found at moved index")?;
+            let ordered = guard.ordered_entries();
+            let after_track = ordered.iter()
+                .position(|e| e.uuid == moved.uuid)
+                .and_then(|i| i.checked_sub(1))
+                .map(|i| ordered[i].dbus_obj())
+                .unwrap_or(TrackId::NO_TRACK);
             self.track_list_emit(TrackListSignal::TrackRemoved {
                 track_id: moved.dbus_obj(),
             });
             self.track_list_emit(TrackListSignal::TrackAdded {
                 metadata: get_song_metadata(Some(moved), self.client).await,
-                after_track: if index != 0 && let Some(prev) = guard.song_at_index(index-1) {
-                    prev.dbus_obj()
-                } else {
-                    TrackId::NO_TRACK
-                },
+                after_track,
             });
-            self.send_tl_msg(TrackListMsg::TrackChanged(None));
+            self.send_tl_msg(TrackListMsg::TrackChanged);
+        }
+        Ok(())
+    }
+
+    /// Undoes the last queue edit, returns false if there was nothing to undo
+    pub async fn undo(&self) -> Result<bool, Box<dyn Error>> {
+        self.step_history(true).await
+    }
+
+    /// Redoes the last undone queue edit, returns false if there was nothing to redo
+    pub async fn redo(&self) -> Result<bool, Box<dyn Error>> {
+        self.step_history(false).await
+    }
+
+    async fn step_history(&self, undo: bool) -> Result<bool, Box<dyn Error>> {
+        let (stepped, song_changed) = {
+            let mut guard = self.track_list().borrow_mut();
+            let before = guard.current().map(|e| e.uuid);
+            let stepped = if undo { guard.undo() } else { guard.redo() };
+            (stepped, guard.current().map(|e| e.uuid) != before)
+        };
+        if !stepped {
+            return Ok(false);
+        }
+        if song_changed {
+            let current = self.track_list().borrow().current().cloned();
+            // Only keep playing if something was playing, restoring a cleared queue leaves it stopped
+            if current.is_some() && self.player_ref.playback_status() == PlayState::Playing {
+                self.player_ref.start_current().await?;
+            } else {
+                self.player_ref.stop_playback();
+            }
+            self.send_cs_msg(CurrentSongMsg::SongUpdate(current));
+            self.properties_changed([
+                Property::Metadata(self.current_song_metadata().await),
+            ]).await;
+        }
+        self.send_cs_msg(CurrentSongMsg::Update);
+        self.properties_changed([
+            Property::Shuffle(self.player_ref.shuffled()),
+            Property::LoopStatus(self.player_ref.loop_status()),
+        ]).await;
+        let guard = self.track_list().borrow();
+        self.track_list_replaced(&guard).await?;
+        self.send_tl_msg(TrackListMsg::ReloadList);
+        Ok(true)
+    }
+
+    /// Saves the queue and the position in the current song for the next launch
+    pub async fn save_queue(&self) -> Result<(), Box<dyn Error>> {
+        let mut saved = {
+            let guard = self.track_list().borrow();
+            // Quitting before the last queue came back, or while the server couldn't be reached
+            if !self.queue_restored.get() && guard.empty() {
+                debug!("Queue not restored yet, keeping the saved one");
+                return Ok(());
+            }
+            guard.to_saved()
+        };
+        saved.position = (self.player_ref.position() / 1000).max(0) as u64;
+        saved.save().await
+    }
+
+    /// Restores the queue saved by the last run, paused where it was left
+    pub async fn restore_queue(&self) -> Result<(), Box<dyn Error>> {
+        let Some(saved) = SavedQueue::load().await? else {
+            self.queue_restored.set(true);
+            return Ok(());
+        };
+        if saved.is_empty() {
+            self.queue_restored.set(true);
+            return Ok(());
+        }
+        // Only queues saved by older versions are missing songs
+        let missing = saved.missing_song_ids();
+        let mut songs = saved.saved_songs();
+        if !missing.is_empty() {
+            let fetched = self.song_cache.get_songs(&missing).await;
+            if fetched.len() < missing.len() {
+                // Left in place to try again on the next launch
+                return Err("Couldn't load all songs of the saved queue".into());
+            }
+            songs.extend(fetched);
         }
+        let position = Duration::from_millis(saved.position);
+        {
+            let mut guard = self.track_list().borrow_mut();
+            self.queue_restored.set(true);
+            // Something got queued while the songs were loading
+            if !guard.empty() {
+                return Ok(());
+            }
+            debug!(songs = saved.songs.len(), "Restoring queue");
+            guard.restore_saved(saved, &songs);
+        }
+        let current = self.player_ref.load_current_paused(position);
+        self.send_cs_msg(CurrentSongMsg::SongUpdate(current));
+        self.send_cs_msg(CurrentSongMsg::Update);
+        self.properties_changed([
+            Property::Metadata(self.current_song_metadata().await),
+            Property::Shuffle(self.player_ref.shuffled()),
+            Property::LoopStatus(self.player_ref.loop_status()),
+        ]).await;
+        let guard = self.track_list().borrow();
+        self.track_list_replaced(&guard).await?;
+        self.send_tl_msg(TrackListMsg::ReloadList);
         Ok(())
     }
 
@@ -186,7 +493,7 @@ impl LocalTrackListInterface for MprisPlayer{
     ) -> fdo::Result<Vec<Metadata>> {
         let track_list = self.track_list().borrow();
         let mut songs_refs: Vec<&SongEntry> = Vec::new();
-        let loaded_songs = track_list.get_songs();
+        let loaded_songs = track_list.ordered_entries();
         for x in tracks_in {
             let song = loaded_songs.iter().find(|x1| x1.dbus_path() == x.as_str());
             match song {
@@ -220,35 +527,41 @@ impl LocalTrackListInterface for MprisPlayer{
     }
 
     async fn remove_track(&self, track_id: TrackId) -> fdo::Result<()> {
-        let index: usize = {
+        let (index, up_next_index) = {
             let track_list = self.track_list().borrow();
-            track_list
-                .get_songs()
-                .iter()
-                .position(|x| x.dbus_path() == track_id.as_str())
-                .ok_or(fdo::Error::Failed("Track not found".to_string()))?
+            (
+                track_list.get_songs().iter().position(|x| x.dbus_path() == track_id.as_str()),
+                track_list.up_next().iter().position(|x| x.dbus_path() == track_id.as_str()),
+            )
         };
-        self.remove(index).await.map_err(|e| fdo::Error::Failed(e.to_string()))?;
+        match (index, up_next_index) {
+            (Some(index), _) => self.remove(index).await,
+            (None, Some(index)) => self.remove_up_next(index),
+            (None, None) => return Err(fdo::Error::Failed("Track not found".to_string())),
+        }.map_err(|e| fdo::Error::Failed(e.to_string()))?;
         Ok(())
     }
 
     async fn go_to(&self, track_id: TrackId) -> fdo::Result<()> {
-        let index: usize = {
+        let (index, up_next_index) = {
             let track_list = self.track_list().borrow();
-            track_list
-                .get_songs()
-                .iter()
-                .position(|x| x.dbus_path() == track_id.as_str())
-                .ok_or(fdo::Error::Failed("Track not found".to_string()))?
+            (
+                track_list.get_songs().iter().position(|x| x.dbus_path() == track_id.as_str()),
+                track_list.up_next().iter().position(|x| x.dbus_path() == track_id.as_str()),
+            )
         };
-        self.goto(index).await.map_err(|e| fdo::Error::Failed(e.to_string()))?;
+        match (index, up_next_index) {
+            (Some(index), _) => self.goto(index).await,
+            (None, Some(index)) => self.goto_up_next(index).await,
+            (None, None) => return Err(fdo::Error::Failed("Track not found".to_string())),
+        }.map_err(|e| fdo::Error::Failed(e.to_string()))?;
         Ok(())
     }
 
     async fn tracks(&self) -> fdo::Result<Vec<TrackId>> {
         let track_list = self.track_list().borrow();
         Ok(track_list
-            .get_songs()
+            .ordered_entries()
             .iter()
             .map(|song| song.dbus_obj())
             .collect())
@@ -0,0 +1,213 @@
+use std::error::Error;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tracing::warn;
+use crate::opensonic::client;
+use crate::opensonic::types::Song;
+
+const HISTORY_FILE: &str = "history.jsonl";
+/// ListenBrainz counts a song as listened to after this long, or half of it
+const LISTEN_MIN_PLAYED: Duration = Duration::from_secs(4 * 60);
+
+/// A played song in the local listening history, one JSON object per line in the data dir
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    /// Unix time in seconds when the song started playing
+    pub timestamp: u64,
+    pub song_id: String,
+    pub title: String,
+    pub artist: Option<String>,
+    pub artist_id: Option<String>,
+    pub album: Option<String>,
+    pub album_id: Option<String>,
+    pub track: Option<i32>,
+    /// Length of the song in milliseconds
+    pub duration: Option<u64>,
+    /// How long it was actually played, in milliseconds
+    pub played: u64,
+    /// Whether playback moved on before the song ended
+    pub skipped: bool,
+    pub music_brainz_id: Option<String>,
+    pub isrc: Option<String>,
+}
+
+fn history_path() -> Option<PathBuf> {
+    client::get_default_data_dir().map(|d| PathBuf::from(d).join(HISTORY_FILE))
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl HistoryEntry {
+    pub fn new(song: &Song, timestamp: u64, played: Duration, skipped: bool) -> Self {
+        Self {
+            timestamp,
+            song_id: song.id.clone(),
+            title: song.title.clone(),
+            artist: song.display_artists.clone().or_else(|| song.artist.clone()),
+            artist_id: song.artist_id.clone(),
+            album: song.album.clone(),
+            album_id: song.album_id.clone(),
+            track: song.track,
+            duration: song.duration.map(|d| d.as_millis() as u64),
+            played: played.as_millis() as u64,
+            skipped,
+            music_brainz_id: song.music_brainz_id.clone().filter(|id| !id.is_empty()),
+            isrc: song.isrc.as_ref().and_then(|isrc| isrc.first().cloned()),
+        }
+    }
+
+    /// Whether ListenBrainz would count this as a listen
+    pub fn counts_as_listen(&self) -> bool {
+        let played = Duration::from_millis(self.played);
+        played >= LISTEN_MIN_PLAYED || self.duration.is_some_and(|d| played >= Duration::from_millis(d) / 2)
+    }
+
+    pub async fn append(&self) -> Result<(), Box<dyn Error>> {
+        let path = history_path().ok_or("No data directory to save the history in")?;
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let mut line = serde_json::to_string(self)?;
+        line.push('\n');
+        let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(&path).await?;
+        file.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// All of the history, oldest first
+    pub async fn load_all() -> Result<Vec<HistoryEntry>, Box<dyn Error>> {
+        let Some(path) = history_path() else {
+            return Ok(Vec::new());
+        };
+        let text = match tokio::fs::read_to_string(&path).await {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut entries: Vec<HistoryEntry> = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(entry) => Some(entry),
+                Err(err) => {
+                    // A line cut off by a crash shouldn't lose the rest
+                    warn!(%err, "Skipping invalid history line");
+                    None
+                }
+            })
+            .collect();
+        entries.sort_by_key(|e| e.timestamp);
+        Ok(entries)
+    }
+}
+
+/// Listens in the format of a ListenBrainz export, which it can import again.
+/// Only entries that count as a listen are included.
+pub fn to_listenbrainz_json(entries: &[HistoryEntry]) -> Result<String, Box<dyn Error>> {
+    let listens: Vec<serde_json::Value> = entries
+        .iter()
+        .filter(|e| e.counts_as_listen())
+        .map(|e| {
+            let mut additional_info = json!({
+                "submission_client": "Sanic-rs",
+                "submission_client_version": env!("CARGO_PKG_VERSION"),
+            });
+            if let Some(duration) = e.duration {
+                additional_info["duration_ms"] = json!(duration);
+            }
+            if let Some(track) = e.track {
+                additional_info["tracknumber"] = json!(track);
+            }
+            if let Some(mbid) = &e.music_brainz_id {
+                additional_info["recording_mbid"] = json!(mbid);
+            }
+            if let Some(isrc) = &e.isrc {
+                additional_info["isrc"] = json!(isrc);
+            }
+            json!({
+                "listened_at": e.timestamp,
+                "track_metadata": {
+                    "artist_name": e.artist.as_deref().unwrap_or_default(),
+                    "track_name": e.title,
+                    "release_name": e.album,
+                    "additional_info": additional_info,
+                }
+            })
+        })
+        .collect();
+    Ok(serde_json::to_string_pretty(&listens)?)
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// The whole history as CSV, with ListenBrainz field names where there's one
+pub fn to_csv(entries: &[HistoryEntry]) -> String {
+    let mut csv = String::from("listened_at,artist_name,track_name,release_name,duration_ms,played_ms,skipped,recording_mbid,isrc,song_id\r\n");
+    for e in entries {
+        let fields = [
+            e.timestamp.to_string(),
+            e.artist.clone().unwrap_or_default(),
+            e.title.clone(),
+            e.album.clone().unwrap_or_default(),
+            e.duration.map(|d| d.to_string()).unwrap_or_default(),
+            e.played.to_string(),
+            e.skipped.to_string(),
+            e.music_brainz_id.clone().unwrap_or_default(),
+            e.isrc.clone().unwrap_or_default(),
+            e.song_id.clone(),
+        ];
+        csv.push_str(&fields.iter().map(|f| csv_field(f)).collect::<Vec<_>>().join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(title: &str, duration_secs: u64, played_secs: u64) -> HistoryEntry {
+        let song = Song {
+            id: "id".to_string(),
+            title: title.to_string(),
+            artist: Some("Artist".to_string()),
+            duration: Some(Duration::from_secs(duration_secs)),
+            ..Default::default()
+        };
+        HistoryEntry::new(&song, 1700000000, Duration::from_secs(played_secs), played_secs < duration_secs)
+    }
+
+    #[test]
+    fn listens_follow_the_listenbrainz_rule() {
+        assert!(entry("a", 200, 100).counts_as_listen());
+        assert!(!entry("a", 200, 99).counts_as_listen());
+        assert!(entry("a", 3600, 240).counts_as_listen());
+
+        let json: serde_json::Value = serde_json::from_str(&to_listenbrainz_json(&[entry("a", 200, 10), entry("b", 200, 200)]).unwrap()).unwrap();
+        let listens = json.as_array().unwrap();
+        assert_eq!(listens.len(), 1);
+        assert_eq!(listens[0]["listened_at"], 1700000000);
+        assert_eq!(listens[0]["track_metadata"]["track_name"], "b");
+        assert_eq!(listens[0]["track_metadata"]["additional_info"]["duration_ms"], 200000);
+    }
+
+    #[test]
+    fn csv_quotes_fields_when_needed() {
+        let csv = to_csv(&[entry("Hello, \"World\"", 200, 200)]);
+        let row = csv.lines().nth(1).unwrap();
+        assert_eq!(row, "1700000000,Artist,\"Hello, \"\"World\"\"\",,200000,200000,false,,,id");
+    }
+}
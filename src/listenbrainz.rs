@@ -0,0 +1,468 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
+use libsecret::{password_lookup_sync, Schema, SchemaAttributeType, SchemaFlags};
+use relm4::gtk::gio::prelude::SettingsExt;
+use relm4::gtk::gio::{Cancellable, Settings};
+use reqwest::header::AUTHORIZATION;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use crate::opensonic::types::Song;
+use crate::scrobbler::{load_pending, pending_path, save_pending};
+use crate::APP_ID;
+
+pub const DEFAULT_URL: &str = "https://api.listenbrainz.org";
+const PENDING_FILE: &str = "listenbrainz.json";
+/// Listens sent in one request when catching up, well below the API's limit
+const BATCH_SIZE: usize = 100;
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Secret schema the ListenBrainz user token is stored under, with the API URL as `url`
+pub fn token_schema() -> Schema {
+    Schema::new(format!("{}.ListenBrainz", APP_ID).as_str(), SchemaFlags::NONE, HashMap::from([("url", SchemaAttributeType::String)]))
+}
+
+/// API URL without surrounding whitespace or a trailing slash, the default one if it's empty.
+/// Tokens are stored under the normalized URL.
+pub fn normalize_url(url: &str) -> String {
+    let url = url.trim().trim_end_matches('/');
+    if url.is_empty() { DEFAULT_URL.to_string() } else { url.to_string() }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Listen {
+    /// Unix time in seconds, left out for playing now
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listened_at: Option<u64>,
+    pub track_metadata: TrackMetadata,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TrackMetadata {
+    pub artist_name: String,
+    pub track_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_name: Option<String>,
+    pub additional_info: AdditionalInfo,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AdditionalInfo {
+    pub submission_client: String,
+    pub submission_client_version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tracknumber: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recording_mbid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub isrc: Option<String>,
+}
+
+impl Listen {
+    pub fn new(song: &Song, listened_at: Option<u64>) -> Self {
+        Self {
+            listened_at,
+            track_metadata: TrackMetadata {
+                artist_name: song.display_artists.clone().or_else(|| song.artist.clone()).unwrap_or_default(),
+                track_name: song.title.clone(),
+                release_name: song.album.clone(),
+                additional_info: AdditionalInfo {
+                    submission_client: "Sanic-rs".to_string(),
+                    submission_client_version: env!("CARGO_PKG_VERSION").to_string(),
+                    duration_ms: song.duration.map(|d| d.as_millis() as u64),
+                    tracknumber: song.track,
+                    recording_mbid: song.music_brainz_id.clone().filter(|id| !id.is_empty()),
+                    isrc: song.isrc.as_ref().and_then(|isrc| isrc.first().cloned()),
+                },
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Submission<'a> {
+    listen_type: &'a str,
+    payload: &'a [Listen],
+}
+
+/// Where listens are sent, ListenBrainz itself or anything with a compatible API
+#[derive(Debug, Clone, PartialEq)]
+pub struct Endpoint {
+    pub base_url: String,
+    pub token: String,
+}
+
+impl Endpoint {
+    /// The configured endpoint, `None` if submitting to ListenBrainz is turned off or there's no token
+    pub fn from_settings(settings: &Settings) -> Option<Self> {
+        if !settings.boolean("listenbrainz-enabled") {
+            return None;
+        }
+        let base_url = normalize_url(&settings.string("listenbrainz-url"));
+        let token = password_lookup_sync(Some(&token_schema()), HashMap::from([("url", base_url.as_str())]), Cancellable::NONE)
+            .inspect_err(|err| warn!(%err, "Couldn't look up the ListenBrainz token"))
+            .ok()
+            .flatten()?
+            .to_string();
+        Some(Self { base_url, token })
+    }
+}
+
+/// Submits listens straight to a ListenBrainz API, independently of the server's scrobbling.
+/// Listens that couldn't be sent are kept in the data dir and sent later.
+pub struct ListenBrainz {
+    http: reqwest::Client,
+    endpoint: RefCell<Option<Endpoint>>,
+    pending: RefCell<Vec<Listen>>,
+    pending_file: Option<PathBuf>,
+    flushing: Cell<bool>,
+    /// Set when the API refused the token, nothing is sent until the endpoint is set again
+    token_rejected: Cell<bool>,
+}
+
+impl ListenBrainz {
+    pub fn new() -> Self {
+        Self::with_pending_file(pending_path(PENDING_FILE))
+    }
+
+    pub fn with_pending_file(pending_file: Option<PathBuf>) -> Self {
+        Self {
+            http: reqwest::Client::builder().timeout(TIMEOUT).build().unwrap_or_default(),
+            endpoint: RefCell::new(None),
+            pending: RefCell::new(Vec::new()),
+            pending_file,
+            flushing: Cell::new(false),
+            token_rejected: Cell::new(false),
+        }
+    }
+
+    pub fn set_endpoint(&self, endpoint: Option<Endpoint>) {
+        self.endpoint.replace(endpoint);
+        self.token_rejected.set(false);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.endpoint.borrow().is_some()
+    }
+
+    pub fn pending(&self) -> Vec<Listen> {
+        self.pending.borrow().clone()
+    }
+
+    /// Reads listens left over from previous runs
+    pub async fn load(&self) -> Result<(), Box<dyn Error>> {
+        let Some(path) = self.pending_file.as_ref() else {
+            return Ok(());
+        };
+        let saved: Vec<Listen> = load_pending(path).await?;
+        if !saved.is_empty() {
+            info!(count = saved.len(), "Loaded pending ListenBrainz listens");
+        }
+        let mut pending = self.pending.borrow_mut();
+        let newer = std::mem::replace(&mut *pending, saved);
+        pending.extend(newer);
+        Ok(())
+    }
+
+    async fn save(&self) {
+        let Some(path) = self.pending_file.as_ref() else {
+            return;
+        };
+        let pending = self.pending.borrow().clone();
+        if let Err(err) = save_pending(path, &pending).await {
+            warn!(%err, "Couldn't save pending ListenBrainz listens");
+        }
+    }
+
+    async fn post(&self, listen_type: &str, listens: &[Listen]) -> Result<(), reqwest::Error> {
+        let Some(endpoint) = self.endpoint.borrow().clone() else {
+            return Ok(());
+        };
+        self.http
+            .post(format!("{}/1/submit-listens", endpoint.base_url))
+            .header(AUTHORIZATION, format!("Token {}", endpoint.token))
+            .json(&Submission { listen_type, payload: listens })
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Tells ListenBrainz what's playing, this isn't retried if it fails
+    pub async fn playing_now(&self, song: &Song) {
+        if !self.is_enabled() {
+            return;
+        }
+        if let Err(err) = self.post("playing_now", &[Listen::new(song, None)]).await {
+            debug!(%err, "Couldn't send playing now to ListenBrainz");
+        }
+    }
+
+    pub async fn submit(&self, listen: Listen) -> Result<(), Box<dyn Error>> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        self.pending.borrow_mut().push(listen);
+        self.save().await;
+        self.flush().await
+    }
+
+    /// Sends pending listens oldest first. Listens the API refuses as invalid are dropped,
+    /// anything else (no connection, rate limits) keeps them for later.
+    /// Fails when the token is refused, which needs the user to change it.
+    pub async fn flush(&self) -> Result<(), Box<dyn Error>> {
+        if !self.is_enabled() || self.token_rejected.get() || self.flushing.replace(true) {
+            return Ok(());
+        }
+        let mut changed = false;
+        let mut result = Ok(());
+        // Listens of a refused batch that are left to send one by one
+        let mut singles = 0;
+        loop {
+            let batch_size = if singles > 0 { 1 } else { BATCH_SIZE };
+            let batch: Vec<Listen> = self.pending.borrow().iter().take(batch_size).cloned().collect();
+            if batch.is_empty() {
+                break;
+            }
+            let listen_type = if batch.len() == 1 { "single" } else { "import" };
+            match self.post(listen_type, &batch).await {
+                Ok(()) => {}
+                Err(err) if err.status() == Some(StatusCode::BAD_REQUEST) && batch.len() > 1 => {
+                    // A single invalid listen fails the whole batch, so only the ones refused on their own are dropped
+                    debug!(%err, count = batch.len(), "ListenBrainz rejected a batch, sending its listens one by one");
+                    singles = batch.len();
+                    continue;
+                }
+                Err(err) if err.status() == Some(StatusCode::BAD_REQUEST) => {
+                    warn!(%err, "ListenBrainz rejected a listen, dropping it");
+                }
+                Err(err) if err.status() == Some(StatusCode::UNAUTHORIZED) => {
+                    warn!(%err, "ListenBrainz refused the user token, keeping listens until it's changed");
+                    self.token_rejected.set(true);
+                    result = Err("ListenBrainz refused the user token, check it in the preferences".into());
+                    break;
+                }
+                Err(err) => {
+                    debug!(%err, "Couldn't reach ListenBrainz, keeping listens for later");
+                    break;
+                }
+            }
+            self.pending.borrow_mut().drain(..batch.len());
+            singles = singles.saturating_sub(1);
+            changed = true;
+        }
+        if changed {
+            self.save().await;
+        }
+        self.flushing.set(false);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    struct Request {
+        path: String,
+        authorization: Option<String>,
+        body: serde_json::Value,
+    }
+
+    /// Minimal HTTP server answering every request with the next of `statuses`, or 200 once they run out
+    fn stand_in_server(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<Request>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        thread::spawn(move || {
+            let mut statuses = statuses.into_iter();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let path = line.split_whitespace().nth(1).unwrap_or_default().to_string();
+                let mut length = 0;
+                let mut authorization = None;
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    let header = line.trim_end();
+                    if header.is_empty() {
+                        break;
+                    }
+                    let (name, value) = header.split_once(':').unwrap();
+                    match name.to_ascii_lowercase().as_str() {
+                        "content-length" => length = value.trim().parse().unwrap(),
+                        "authorization" => authorization = Some(value.trim().to_string()),
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                received.lock().unwrap().push(Request {
+                    path,
+                    authorization,
+                    body: serde_json::from_slice(&body).unwrap(),
+                });
+                let status = statuses.next().unwrap_or(200);
+                let response_body = r#"{"status": "ok"}"#;
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Status\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status, response_body.len(), response_body
+                ).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    fn listenbrainz(base_url: &str) -> ListenBrainz {
+        let listenbrainz = ListenBrainz::with_pending_file(None);
+        listenbrainz.set_endpoint(Some(Endpoint { base_url: base_url.to_string(), token: "secret-token".to_string() }));
+        listenbrainz
+    }
+
+    fn song(title: &str) -> Song {
+        Song {
+            id: title.to_string(),
+            title: title.to_string(),
+            artist: Some("Artist".to_string()),
+            album: Some("Album".to_string()),
+            duration: Some(Duration::from_secs(180)),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn submits_single_listens_with_the_token() {
+        let (url, requests) = stand_in_server(vec![]);
+        let lb = listenbrainz(&url);
+        lb.submit(Listen::new(&song("First"), Some(1700000000))).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "/1/submit-listens");
+        assert_eq!(requests[0].authorization.as_deref(), Some("Token secret-token"));
+        let body = &requests[0].body;
+        assert_eq!(body["listen_type"], "single");
+        assert_eq!(body["payload"][0]["listened_at"], 1700000000);
+        assert_eq!(body["payload"][0]["track_metadata"]["track_name"], "First");
+        assert_eq!(body["payload"][0]["track_metadata"]["artist_name"], "Artist");
+        assert_eq!(body["payload"][0]["track_metadata"]["additional_info"]["duration_ms"], 180000);
+        assert!(lb.pending().is_empty());
+    }
+
+    #[tokio::test]
+    async fn playing_now_has_no_timestamp() {
+        let (url, requests) = stand_in_server(vec![]);
+        let lb = listenbrainz(&url);
+        lb.playing_now(&song("Now")).await;
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0].body["listen_type"], "playing_now");
+        assert!(requests[0].body["payload"][0].get("listened_at").is_none());
+    }
+
+    #[tokio::test]
+    async fn keeps_listens_until_the_server_accepts_them() {
+        let (url, requests) = stand_in_server(vec![503]);
+        let lb = listenbrainz(&url);
+        lb.submit(Listen::new(&song("First"), Some(1))).await.unwrap();
+        assert_eq!(lb.pending().len(), 1);
+
+        lb.submit(Listen::new(&song("Second"), Some(2))).await.unwrap();
+        assert!(lb.pending().is_empty());
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        // Catching up sends everything that's pending at once
+        assert_eq!(requests[1].body["listen_type"], "import");
+        assert_eq!(requests[1].body["payload"].as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn keeps_listens_when_unreachable() {
+        // Nothing listens on a port that was just freed
+        let url = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        let lb = listenbrainz(&url);
+        lb.submit(Listen::new(&song("First"), Some(1))).await.unwrap();
+        assert_eq!(lb.pending().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn drops_listens_the_server_calls_invalid() {
+        let (url, _requests) = stand_in_server(vec![400]);
+        let lb = listenbrainz(&url);
+        lb.submit(Listen::new(&song("Bad"), Some(1))).await.unwrap();
+        assert!(lb.pending().is_empty());
+    }
+
+    #[tokio::test]
+    async fn drops_only_the_invalid_listens_of_a_refused_batch() {
+        let (url, requests) = stand_in_server(vec![503, 503, 400, 200, 400]);
+        let lb = listenbrainz(&url);
+        lb.submit(Listen::new(&song("First"), Some(1))).await.unwrap();
+        lb.submit(Listen::new(&song("Second"), Some(2))).await.unwrap();
+        lb.submit(Listen::new(&song("Third"), Some(3))).await.unwrap();
+        assert!(lb.pending().is_empty());
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 6);
+        assert_eq!(requests[2].body["payload"].as_array().unwrap().len(), 3);
+        let singles: Vec<&serde_json::Value> = requests[3..].iter()
+            .inspect(|r| assert_eq!(r.body["listen_type"], "single"))
+            .map(|r| &r.body["payload"][0]["track_metadata"]["track_name"])
+            .collect();
+        assert_eq!(singles, ["First", "Second", "Third"]);
+    }
+
+    #[tokio::test]
+    async fn stops_sending_when_the_token_is_refused() {
+        let (url, requests) = stand_in_server(vec![401]);
+        let lb = listenbrainz(&url);
+        assert!(lb.submit(Listen::new(&song("First"), Some(1))).await.is_err());
+        lb.submit(Listen::new(&song("Second"), Some(2))).await.unwrap();
+        assert_eq!(lb.pending().len(), 2);
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        // Setting the token again tries with it
+        lb.set_endpoint(Some(Endpoint { base_url: url, token: "new-token".to_string() }));
+        lb.flush().await.unwrap();
+        assert!(lb.pending().is_empty());
+        assert_eq!(requests.lock().unwrap()[1].authorization.as_deref(), Some("Token new-token"));
+    }
+
+    #[tokio::test]
+    async fn saves_pending_listens_between_runs() {
+        let path = std::env::temp_dir().join(format!("sanicrs-listenbrainz-{}.json", uuid::Uuid::new_v4()));
+        let (url, _requests) = stand_in_server(vec![503]);
+        let lb = ListenBrainz::with_pending_file(Some(path.clone()));
+        lb.set_endpoint(Some(Endpoint { base_url: url, token: "token".to_string() }));
+        lb.submit(Listen::new(&song("Offline"), Some(1))).await.unwrap();
+
+        let restarted = ListenBrainz::with_pending_file(Some(path.clone()));
+        restarted.load().await.unwrap();
+        assert_eq!(restarted.pending(), lb.pending());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn does_nothing_when_disabled() {
+        let lb = ListenBrainz::with_pending_file(None);
+        lb.submit(Listen::new(&song("Ignored"), Some(1))).await.unwrap();
+        assert!(lb.pending().is_empty());
+    }
+}
@@ -0,0 +1,81 @@
+use std::error::Error;
+use std::path::Path;
+use relm4::gtk::gio::prelude::SettingsExt;
+use relm4::gtk::gio::Settings;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, EnvFilter};
+use crate::opensonic::client;
+
+/// Environment variable holding the log filter, e.g. `SANICRS_LOG=sanicrs=debug`
+pub const LOG_ENV: &str = "SANICRS_LOG";
+const DEFAULT_FILTER: &str = "warn,sanicrs=info";
+const MAX_LOG_FILES: usize = 5;
+
+/// Query parameters that carry credentials
+const SENSITIVE_PARAMS: [&str; 5] = ["p", "s", "t", "apiKey", "password"];
+const REDACTED: &str = "[redacted]";
+
+pub fn log_dir() -> Option<String> {
+    client::get_default_data_dir()
+        .and_then(|d| Path::new(&d).join("logs").to_str().map(|s| s.to_string()))
+}
+
+/// Sets up logging to stderr and, if enabled in settings, to a daily rotated file in the data dir
+pub fn init(settings: &Settings) -> Result<(), Box<dyn Error>> {
+    let filter = EnvFilter::try_from_env(LOG_ENV).unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+
+    let file_layer = match log_dir() {
+        Some(dir) if settings.boolean("log-to-file") => {
+            let appender = RollingFileAppender::builder()
+                .rotation(Rotation::DAILY)
+                .filename_prefix("sanicrs")
+                .filename_suffix("log")
+                .max_log_files(MAX_LOG_FILES)
+                .build(dir)?;
+            Some(fmt::layer().with_ansi(false).with_writer(appender))
+        },
+        _ => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_writer(std::io::stderr))
+        .with(file_layer)
+        .try_init()?;
+    Ok(())
+}
+
+pub fn is_sensitive(key: &str) -> bool {
+    SENSITIVE_PARAMS.contains(&key)
+}
+
+/// Copy of request params with credential values replaced, for logging
+pub fn redact_params<'a>(params: &[(&'a str, &'a str)]) -> Vec<(&'a str, &'a str)> {
+    params.iter()
+        .map(|(k, v)| if is_sensitive(k) { (*k, REDACTED) } else { (*k, *v) })
+        .collect()
+}
+
+/// Replaces credential values in any query string found in `text`. Works on URLs as well as
+/// error messages that contain them.
+pub fn redact(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find(['?', '&']) {
+        result.push_str(&rest[..=i]);
+        rest = &rest[i + 1..];
+        let key_end = rest.find(['=', '&', '?']).unwrap_or(rest.len());
+        if rest[key_end..].starts_with('=') && is_sensitive(&rest[..key_end]) {
+            result.push_str(&rest[..=key_end]);
+            result.push_str(REDACTED);
+            let value_end = rest[key_end..]
+                .find(|c: char| c == '&' || c == '"' || c == '\'' || c == ')' || c.is_whitespace())
+                .map(|e| e + key_end)
+                .unwrap_or(rest.len());
+            rest = &rest[value_end..];
+        }
+    }
+    result.push_str(rest);
+    result
+}
@@ -1,6 +1,8 @@
 use crate::dbus::player::MprisPlayer;
 use crate::opensonic::cache::{AlbumCache, ArtistCache, CoverCache, LyricsCache, SongCache, SuperCache};
 use crate::opensonic::client::{self, Credentials, OpenSubsonicClient};
+use crate::opensonic::http::{self, HttpOptions};
+use crate::opensonic::tls::TlsOptions;
 use crate::player::{PlayerInfo, TrackList};
 use crate::ui::app::{AppMsg, Model, StartInit};
 use crate::ui::setup::{SetupMsg, SetupOut, SetupWidget};
@@ -14,7 +16,7 @@ use relm4::component::{AsyncComponentBuilder, AsyncComponentController};
 use relm4::gtk::gio::prelude::{ApplicationExt, SettingsExt};
 use relm4::gtk::gio::{ApplicationFlags, Cancellable, Settings};
 use relm4::RelmApp;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::error::Error;
 use std::os::unix::process::CommandExt;
@@ -25,16 +27,27 @@ use std::io;
 use gstreamer_play::PlayState;
 use relm4::prelude::AsyncController;
 use tokio::runtime::Handle;
+use tracing::{debug, error, info};
+use uuid::Uuid;
 use zbus::blocking;
 use crate::ui::current_song::CurrentSongMsg;
 
+mod crossfade;
 mod dbus;
+mod history;
+mod listenbrainz;
+mod logging;
+mod mixer;
 mod opensonic;
 mod player;
+mod queue_state;
+mod scrobbler;
+mod shuffle;
 mod ui;
 
 const APP_ID: &'static str = "me.quartzy.sanicrs";
 const VERSION_STR: &'static str = "0.0.0";
+const SCROBBLE_RETRY_SECONDS: u32 = 5 * 60;
 const DBUS_NAME_PREFIX: &'static str = "org.mpris.MediaPlayer2.";
 
 mod icon_names {
@@ -45,6 +58,8 @@ pub enum PlayerCommand {
     Quit(bool),
     Raise,
     TrackOver,
+    /// Playback went on to the lined up next song by itself
+    GaplessSwitch(Uuid),
     Close,
     Error(String, String),
     PositionUpdate(f64),
@@ -78,12 +93,20 @@ fn make_client_from_saved(settings: &Settings, secret_schema: &Schema) -> Result
             password: password_str
         }
     };
+    let basic_auth_password = match HttpOptions::load(settings, None).basic_auth {
+        Some((username, _)) => password_lookup_sync(Some(&http::basic_auth_schema()), http::basic_auth_attributes(&host, &username), Cancellable::NONE)
+            .map_err(|e| format!("{:?}", e))?
+            .map(|p| p.to_string()),
+        None => None,
+    };
     let client = OpenSubsonicClient::new(
         host.as_str(),
         credentials,
         "Sanic-rs",
         if settings.boolean("should-cache-covers") { client::get_default_cache_dir() } else { None },
-    );
+        TlsOptions::load(settings),
+        HttpOptions::load(settings, basic_auth_password),
+    )?;
     let rt = tokio::runtime::Runtime::new().expect("Error creating temporary Tokio runtime");
     rt.block_on(client.init())?;
     Ok(client)
@@ -123,6 +146,8 @@ pub fn run_async(payload: StartInit, controller_cell: Rc<RefCell<Option<AsyncCon
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
+    logging::init(&Settings::new(APP_ID))?;
+
     // First check if app is already running
     {
         let session = blocking::Connection::session()?;
@@ -130,7 +155,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         let reply = session
             .call_method(Some(DBUS_NAME_PREFIX.to_owned() + APP_ID), "/org/mpris/MediaPlayer2", Some("org.mpris.MediaPlayer2"), "Raise", &());
         if reply.is_ok() {
-            println!("An instance is already running. Raised.");
+            info!("An instance is already running. Raised.");
             return Ok(());
         }
     }
@@ -142,7 +167,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         let secret_schema = Schema::new(APP_ID, SchemaFlags::NONE, HashMap::new());
 
         static CLIENT: LazyLock<OpenSubsonicClient> = LazyLock::new(|| {
-            println!("initializing");
+            debug!("Initializing client");
             let settings = Settings::new(APP_ID);
 
             let secret_schema = Schema::new(APP_ID, SchemaFlags::NONE, HashMap::new());
@@ -152,7 +177,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                 match make_client_from_saved(&settings, &secret_schema) {
                     Ok(c) => c,
                     Err(e) => {
-                        eprintln!("Error when trying to make client: {}", e);
+                        error!("Error when trying to make client: {}", logging::redact(&e.to_string()));
                         do_setup(&settings, &secret_schema, Some(format!("{}", e)))
                     }
                 }
@@ -161,7 +186,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         let song_cache = SongCache::new(&CLIENT);
         let album_cache = AlbumCache::new(&CLIENT);
         let cover_cache = CoverCache::new(&CLIENT);
-        let lyrics_cache = LyricsCache::new(&CLIENT);
+        let lyrics_cache = LyricsCache::new(&CLIENT, settings.clone());
         let artist_cache = ArtistCache::new(&CLIENT);
         let super_cache = SuperCache::new(&album_cache, &song_cache, &artist_cache, &CLIENT);
 
@@ -193,6 +218,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             song_cache: song_cache.clone(),
             album_cache: album_cache.clone(),
             settings: settings.clone(),
+            queue_restored: Cell::new(false),
         };
 
 
@@ -261,6 +287,35 @@ async fn app_main(
     mpris_send.send(server.clone()).await?;
     server.imp().server.replace(Some(server.clone()));
     let _h = relm4::main_application().hold();
+    relm4::spawn_local(clone!(
+        #[strong]
+        server,
+        async move {
+            server.imp().send_res(server.imp().restore_queue().await);
+        }
+    ));
+    relm4::spawn_local(clone!(
+        #[strong]
+        server,
+        async move {
+            let scrobbler = server.imp().info().scrobbler();
+            let listenbrainz = server.imp().info().listenbrainz();
+            if let Err(err) = scrobbler.load().await {
+                error!(%err, "Couldn't load pending scrobbles");
+            }
+            if let Err(err) = listenbrainz.load().await {
+                error!(%err, "Couldn't load pending ListenBrainz listens");
+            }
+            // Retried now and then, so they get sent once the server is back even if nothing new is played
+            loop {
+                scrobbler.flush().await;
+                if let Err(err) = listenbrainz.flush().await {
+                    server.imp().send_error(err);
+                }
+                glib::timeout_future_seconds(SCROBBLE_RETRY_SECONDS).await;
+            }
+        }
+    ));
     let task = server.run();
 
     tokio::select! {
@@ -282,7 +337,15 @@ async fn handle_command(
 ) -> bool {
     loop {
         match command_recv.recv().await.expect("Error receiving message from command_recv") {
-            PlayerCommand::Quit(should_restart) => return should_restart,
+            PlayerCommand::Quit(should_restart) => {
+                if let Some(entry) = server.imp().info().take_history_entry() && let Err(err) = entry.append().await {
+                    error!(%err, "Couldn't save to the listening history");
+                }
+                if let Err(err) = server.imp().save_queue().await {
+                    error!(%err, "Couldn't save the queue");
+                }
+                return should_restart;
+            },
             PlayerCommand::Close => {
                 let old = controller_cell.replace(None);
                 drop(old);
@@ -297,8 +360,30 @@ async fn handle_command(
                 }
             },
             PlayerCommand::TrackOver => server.imp().send_res_fdo(server.imp().next().await),
+            PlayerCommand::GaplessSwitch(uuid) => server.imp().gapless_switch(uuid).await,
             PlayerCommand::Error(error, description) => server.imp().send_app_msg(AppMsg::ShowError(error, description)),
-            PlayerCommand::PositionUpdate(pos) => server.imp().send_cs_msg(CurrentSongMsg::ProgressUpdateSync(pos)),
+            PlayerCommand::PositionUpdate(pos) => {
+                server.imp().send_cs_msg(CurrentSongMsg::ProgressUpdateSync(pos));
+                server.imp().info().prepare_next();
+                if server.imp().info().crossfade_due() {
+                    relm4::spawn_local(clone!(
+                        #[strong]
+                        server,
+                        async move {
+                            server.imp().crossfade().await;
+                        }
+                    ));
+                }
+                if let Some((song, started)) = server.imp().info().due_scrobble() {
+                    relm4::spawn_local(clone!(
+                        #[strong]
+                        server,
+                        async move {
+                            server.imp().send_res(server.imp().info().submit_scrobble(&song, started).await);
+                        }
+                    ));
+                }
+            },
             PlayerCommand::PlayStateUpdate(state) => server.imp().update_playstate(state).await,
         }
     }
@@ -0,0 +1,667 @@
+use std::cell::{Cell, RefCell};
+use std::error::Error;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use async_channel::{Receiver, Sender};
+use gstreamer::glib::{self, clone, ControlFlow};
+use gstreamer::prelude::*;
+use gstreamer::{ClockTime, EventView, MessageView, PadProbeData, PadProbeId, PadProbeReturn, PadProbeType, SeekFlags, SeekType, State};
+use gstreamer_play::PlayState;
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+use crate::crossfade::Fade;
+use crate::opensonic::client::OpenSubsonicClient;
+use crate::{logging, player, PlayerCommand};
+
+const POSITION_INTERVAL: Duration = Duration::from_millis(250);
+/// How long before the current song ends the next one is loaded, to follow on without a gap
+const GAPLESS_LEAD: ClockTime = ClockTime::from_seconds(10);
+/// Names of deck bins start with this, followed by the deck's number
+const DECK_PREFIX: &str = "deck";
+
+/// One song being played, decoded in its own branch that feeds the mixer
+struct Deck {
+    id: u64,
+    /// Queue entry of the song, for songs lined up to follow the current one
+    uuid: Option<Uuid>,
+    bin: gstreamer::Bin,
+    src: gstreamer::Pad,
+    /// Volume element the deck's fades are applied with
+    fader: gstreamer::Element,
+    rg_volume: Option<gstreamer::Element>,
+    fade: Arc<Mutex<Option<Fade>>>,
+    /// Running time at which the last buffer so far ends, not counting `offset`
+    end: Arc<AtomicU64>,
+    finished: Arc<AtomicBool>,
+    /// Holds the song back until it's mixed in
+    block: Option<PadProbeId>,
+    /// Gets a message once the held back song has audio waiting
+    ready: Receiver<()>,
+    mixer_pad: Option<gstreamer::Pad>,
+    /// Running time of the mixer output at which the deck plays from `origin`
+    offset: ClockTime,
+    /// Position in the song the deck started playing from
+    origin: ClockTime,
+}
+
+impl Deck {
+    /// Links the deck to the mixer, starting it at `offset` in the mixer's running time
+    fn mix_in(&mut self, mixer: &gstreamer::Element, offset: ClockTime) -> Result<(), Box<dyn Error>> {
+        let pad = mixer.request_pad_simple("sink_%u").ok_or("No free mixer pad")?;
+        self.src.set_offset(offset.nseconds() as i64);
+        self.src.link(&pad)?;
+        self.offset = offset;
+        self.mixer_pad = Some(pad);
+        if let Some(block) = self.block.take() {
+            self.src.remove_probe(block);
+        }
+        Ok(())
+    }
+}
+
+/// Decks of the pipeline, shared with GStreamer's threads
+struct Decks {
+    current: Option<Deck>,
+    /// Next song, mixed in at a later running time, that becomes current once playback gets there
+    incoming: Option<Deck>,
+    /// Next song, loaded and held back until it's mixed in
+    next: Option<Deck>,
+    /// Songs that were current before, still playing out their end
+    leaving: Vec<Deck>,
+    /// Position the mixer output was last seeked to
+    seek_base: ClockTime,
+    rate: f64,
+}
+
+impl Default for Decks {
+    fn default() -> Self {
+        Self { current: None, incoming: None, next: None, leaving: Vec::new(), seek_base: ClockTime::ZERO, rate: 1.0 }
+    }
+}
+
+impl Decks {
+    /// Running time of the mixer output at output position `output`
+    fn running_time(&self, output: ClockTime) -> ClockTime {
+        scale(output.saturating_sub(self.seek_base), 1.0 / self.rate)
+    }
+
+    /// Running time at which `deck` gets to `position` in its song
+    fn running_time_at(&self, deck: &Deck, position: ClockTime) -> ClockTime {
+        deck.offset + scale(position.saturating_sub(deck.origin), 1.0 / self.rate)
+    }
+
+    /// Position in the song of `deck` at `running_time`
+    fn position(&self, deck: &Deck, running_time: ClockTime) -> ClockTime {
+        deck.origin + scale(running_time.saturating_sub(deck.offset), self.rate)
+    }
+
+    /// Takes out a deck other than the current one
+    fn take(&mut self, id: u64) -> Option<Deck> {
+        if self.next.as_ref().is_some_and(|d| d.id == id) {
+            return self.next.take();
+        }
+        if self.incoming.as_ref().is_some_and(|d| d.id == id) {
+            return self.incoming.take();
+        }
+        let index = self.leaving.iter().position(|d| d.id == id)?;
+        Some(self.leaving.remove(index))
+    }
+
+    fn take_all(&mut self) -> Vec<Deck> {
+        let mut decks: Vec<Deck> = self.leaving.drain(..).collect();
+        decks.extend(self.current.take());
+        decks.extend(self.incoming.take());
+        decks.extend(self.next.take());
+        decks
+    }
+
+    /// Mixes in the next song right where the current one, deck `id`, ended
+    fn follow_on(&mut self, id: u64, mixer: &gstreamer::Element) {
+        let Some(current) = self.current.as_ref().filter(|d| d.id == id) else {
+            return;
+        };
+        if self.incoming.is_some() {
+            return;
+        }
+        let Some(mut next) = self.next.take() else {
+            return;
+        };
+        let offset = current.offset + ClockTime::from_nseconds(current.end.load(Ordering::Relaxed));
+        match next.mix_in(mixer, offset) {
+            Ok(()) => {
+                debug!(%offset, "Mixing in the next song without a gap");
+                self.incoming = Some(next);
+            }
+            Err(err) => {
+                warn!(%err, "Couldn't mix in the next song");
+                self.next = Some(next);
+            }
+        }
+    }
+}
+
+fn scale(time: ClockTime, factor: f64) -> ClockTime {
+    ClockTime::from_nseconds((time.nseconds() as f64 * factor) as u64)
+}
+
+/// Number of the deck an element is part of
+fn deck_id(mut object: Option<gstreamer::Object>) -> Option<u64> {
+    while let Some(o) = object {
+        if let Some(id) = o.name().strip_prefix(DECK_PREFIX).and_then(|id| id.parse().ok()) {
+            return Some(id);
+        }
+        object = o.parent();
+    }
+    None
+}
+
+/// Unlinks a deck and throws it away. Not to be called from the deck's own streaming thread.
+fn tear_down(pipeline: &gstreamer::Pipeline, mixer: &gstreamer::Element, deck: Deck) {
+    // Whatever it still pushes is dropped instead of failing as not linked
+    deck.src.add_probe(PadProbeType::DATA_DOWNSTREAM, |_, _| PadProbeReturn::Drop);
+    if let Some(pad) = deck.mixer_pad {
+        mixer.release_request_pad(&pad);
+    }
+    let _ = deck.bin.set_state(State::Null);
+    let _ = pipeline.remove(&deck.bin);
+}
+
+/// Plays songs on decks, each a `uridecodebin` branch feeding one `audiomixer`.
+///
+/// The song after the current one is loaded ahead on a deck of its own and held back. It's mixed in
+/// at the running time where the current song ends for gapless playback, or where its last seconds
+/// start for a crossfade, so both songs play in the same pipeline on the same clock.
+pub struct Mixer {
+    client: &'static OpenSubsonicClient,
+    pipeline: gstreamer::Pipeline,
+    mixer: gstreamer::Element,
+    volume: gstreamer::Element,
+    decks: Arc<Mutex<Decks>>,
+    deck_count: Cell<u64>,
+    /// Entry and stream URL of the song after the current one
+    lined_up: RefCell<Option<(Uuid, String)>>,
+    /// Album mode of replay gain, `None` when it's off
+    replay_gain: Cell<Option<bool>>,
+    rate: Cell<f64>,
+    /// Seek waiting for the pipeline to finish changing state
+    pending_seek: Cell<Option<ClockTime>>,
+    cmd_channel: Arc<Sender<PlayerCommand>>,
+}
+
+impl Mixer {
+    pub fn new(client: &'static OpenSubsonicClient, cmd_channel: Arc<Sender<PlayerCommand>>) -> Result<Rc<Self>, Box<dyn Error>> {
+        let pipeline = gstreamer::Pipeline::builder().name("player").build();
+        let mixer = gstreamer::ElementFactory::make("audiomixer").name("mixer").build()?;
+        let convert = gstreamer::ElementFactory::make("audioconvert").build()?;
+        let volume = gstreamer::ElementFactory::make("volume").name("volume").build()?;
+        let sink = gstreamer::ElementFactory::make("autoaudiosink").build()?;
+        pipeline.add_many([&mixer, &convert, &volume, &sink])?;
+        gstreamer::Element::link_many([&mixer, &convert, &volume, &sink])?;
+
+        let decks = Arc::new(Mutex::new(Decks::default()));
+        let bus = pipeline.bus().ok_or("Pipeline has no bus")?;
+        let weak_pipeline = pipeline.downgrade();
+        let weak_mixer = mixer.downgrade();
+        bus.set_sync_handler(clone!(
+            #[strong]
+            decks,
+            #[strong]
+            cmd_channel,
+            move |_bus, msg| {
+                match msg.view() {
+                    MessageView::Error(err) => {
+                        // The error can contain the stream URL, which includes credentials
+                        let err_str = logging::redact(&format!("{} ({:?})", err.error(), err.debug()));
+                        let mut decks = decks.lock().unwrap();
+                        match deck_id(msg.src().cloned()) {
+                            Some(id) if decks.current.as_ref().is_none_or(|d| d.id != id) => {
+                                // A song that isn't playing yet or anymore, playback goes on without it
+                                warn!("GStreamer error from another song: {}", err_str);
+                                if let Some(deck) = decks.take(id)
+                                    && let (Some(pipeline), Some(mixer)) = (weak_pipeline.upgrade(), weak_mixer.upgrade()) {
+                                    glib::MainContext::default().invoke(move || tear_down(&pipeline, &mixer, deck));
+                                }
+                            }
+                            _ => {
+                                error!("GStreamer error: {}", err_str);
+                                if let Err(_) = cmd_channel.send_blocking(PlayerCommand::Error("Error from GStreamer".to_string(), err_str)) {
+                                    error!("Error sending error string to main");
+                                }
+                            }
+                        }
+                    }
+                    MessageView::Warning(warning) => {
+                        warn!("GStreamer warning: {}", logging::redact(&format!("{} ({:?})", warning.error(), warning.debug())));
+                    }
+                    MessageView::Eos(_) => {
+                        if let Err(e) = cmd_channel.send_blocking(PlayerCommand::TrackOver) {
+                            error!("Failed to send TrackOver: {e}");
+                        }
+                    }
+                    MessageView::StateChanged(changed) if changed.pending() == State::VoidPending
+                        && msg.src().is_some_and(|src| weak_pipeline.upgrade().is_some_and(|p| src == p.upcast_ref::<gstreamer::Object>())) => {
+                        let state = match changed.current() {
+                            State::Playing => PlayState::Playing,
+                            State::Paused => PlayState::Paused,
+                            _ => PlayState::Stopped,
+                        };
+                        if let Err(e) = cmd_channel.send_blocking(PlayerCommand::PlayStateUpdate(state)) {
+                            error!("Failed to send PlayStateUpdate: {e}");
+                        }
+                    }
+                    _ => {}
+                }
+                gstreamer::BusSyncReply::Drop
+            }
+        ));
+
+        let this = Rc::new(Self {
+            client,
+            pipeline,
+            mixer,
+            volume,
+            decks,
+            deck_count: Cell::new(0),
+            lined_up: RefCell::new(None),
+            replay_gain: Cell::new(None),
+            rate: Cell::new(1.0),
+            pending_seek: Cell::new(None),
+            cmd_channel,
+        });
+        glib::timeout_add_local(POSITION_INTERVAL, clone!(
+            #[weak]
+            this,
+            #[upgrade_or]
+            ControlFlow::Break,
+            move || {
+                this.tick();
+                ControlFlow::Continue
+            }
+        ));
+        Ok(this)
+    }
+
+    /// Builds a deck playing `uri` and adds it to the pipeline. `hold` keeps it from playing until it's mixed in.
+    fn add_deck(&self, uri: &str, uuid: Option<Uuid>, hold: bool) -> Result<Deck, Box<dyn Error>> {
+        let id = self.deck_count.get();
+        self.deck_count.set(id + 1);
+        let bin = gstreamer::Bin::builder().name(format!("{DECK_PREFIX}{id}")).build();
+        let source = gstreamer::ElementFactory::make("uridecodebin").property("uri", uri).build()?;
+        player::configure_source_setup(self.client, &source);
+        let convert = gstreamer::ElementFactory::make("audioconvert").build()?;
+        let resample = gstreamer::ElementFactory::make("audioresample").build()?;
+        let fader = gstreamer::ElementFactory::make("volume").build()?;
+        bin.add_many([&source, &convert, &resample, &fader])?;
+
+        let rg_volume = match self.replay_gain.get() {
+            Some(album_mode) => {
+                let (filter, rg_volume) = player::replay_gain_bin()?;
+                rg_volume.set_property("album-mode", album_mode);
+                bin.add(&filter)?;
+                gstreamer::Element::link_many([&convert, &resample, &filter, &fader])?;
+                Some(rg_volume)
+            }
+            None => {
+                gstreamer::Element::link_many([&convert, &resample, &fader])?;
+                None
+            }
+        };
+
+        let convert_sink = convert.static_pad("sink").ok_or("audioconvert has no sink pad")?;
+        source.connect_pad_added(move |_, pad| {
+            let is_audio = pad.current_caps()
+                .and_then(|caps| caps.structure(0).map(|s| s.name().starts_with("audio/")))
+                .unwrap_or(false);
+            if is_audio && !convert_sink.is_linked() && let Err(err) = pad.link(&convert_sink) {
+                warn!(?err, "Couldn't link decoded stream");
+            }
+        });
+
+        let src = gstreamer::GhostPad::with_target(&fader.static_pad("src").ok_or("volume has no src pad")?)?;
+        bin.add_pad(&src)?;
+        let src: gstreamer::Pad = src.upcast();
+
+        let fade = Arc::new(Mutex::new(None::<Fade>));
+        let end = Arc::new(AtomicU64::new(0));
+        let finished = Arc::new(AtomicBool::new(false));
+        self.watch_deck(id, &fader, &fade, &end, &finished)?;
+
+        let (ready_send, ready) = async_channel::bounded(1);
+        let block = if hold {
+            src.add_probe(PadProbeType::BLOCK | PadProbeType::BUFFER, move |_, _| {
+                let _ = ready_send.try_send(());
+                PadProbeReturn::Ok
+            })
+        } else {
+            None
+        };
+
+        self.pipeline.add(&bin)?;
+        bin.sync_state_with_parent()?;
+        Ok(Deck {
+            id,
+            uuid,
+            bin,
+            src,
+            fader,
+            rg_volume,
+            fade,
+            end,
+            finished,
+            block,
+            ready,
+            mixer_pad: None,
+            offset: ClockTime::ZERO,
+            origin: ClockTime::ZERO,
+        })
+    }
+
+    /// Follows the audio going into a deck's fader: applies its fade by the position of each buffer,
+    /// keeps track of where the song ends, and mixes in the next song when it does
+    fn watch_deck(&self, id: u64, fader: &gstreamer::Element, fade: &Arc<Mutex<Option<Fade>>>, end: &Arc<AtomicU64>, finished: &Arc<AtomicBool>) -> Result<(), Box<dyn Error>> {
+        let pad = fader.static_pad("sink").ok_or("volume has no sink pad")?;
+        let fader = fader.downgrade();
+        let mixer = self.mixer.downgrade();
+        let decks: Weak<Mutex<Decks>> = Arc::downgrade(&self.decks);
+        let fade = fade.clone();
+        let end = end.clone();
+        let finished = finished.clone();
+        let segment = Mutex::new(None::<gstreamer::FormattedSegment<ClockTime>>);
+        pad.add_probe(PadProbeType::BUFFER | PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+            match &info.data {
+                Some(PadProbeData::Buffer(buffer)) => {
+                    if let Some(segment) = segment.lock().unwrap().as_ref() && let Some(pts) = buffer.pts() {
+                        if let Some(running_time) = segment.to_running_time(pts + buffer.duration().unwrap_or(ClockTime::ZERO)) {
+                            end.store(running_time.nseconds(), Ordering::Relaxed);
+                        }
+                        if let Some(fade) = *fade.lock().unwrap()
+                            && let Some(position) = segment.to_stream_time(pts)
+                            && let Some(fader) = fader.upgrade() {
+                            fader.set_property("volume", fade.level(position));
+                        }
+                    }
+                }
+                Some(PadProbeData::Event(event)) => match event.view() {
+                    EventView::Segment(s) => *segment.lock().unwrap() = s.segment().downcast_ref::<ClockTime>().cloned(),
+                    EventView::Eos(_) => {
+                        finished.store(true, Ordering::Relaxed);
+                        if let (Some(decks), Some(mixer)) = (decks.upgrade(), mixer.upgrade()) {
+                            decks.lock().unwrap().follow_on(id, &mixer);
+                        }
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+            PadProbeReturn::Ok
+        }).ok_or("Couldn't watch the deck")?;
+        Ok(())
+    }
+
+    /// Runs every `POSITION_INTERVAL`
+    fn tick(&self) {
+        let (_, state, pending) = self.pipeline.state(ClockTime::ZERO);
+        let settled = pending == State::VoidPending && matches!(state, State::Paused | State::Playing);
+        if settled && let Some(position) = self.pending_seek.take() {
+            self.seek(position);
+        }
+        let finished: Vec<Deck> = {
+            let mut decks = self.decks.lock().unwrap();
+            let (finished, leaving) = std::mem::take(&mut decks.leaving).into_iter()
+                .partition(|d| d.finished.load(Ordering::Relaxed));
+            decks.leaving = leaving;
+            finished
+        };
+        for deck in finished {
+            tear_down(&self.pipeline, &self.mixer, deck);
+        }
+        if state != State::Playing || pending != State::VoidPending {
+            return;
+        }
+
+        if let Some(uuid) = self.switch_decks()
+            && let Err(e) = self.cmd_channel.send_blocking(PlayerCommand::GaplessSwitch(uuid)) {
+            error!("Failed to send GaplessSwitch: {e}");
+        }
+        if let Some(position) = self.position() {
+            if let Err(e) = self.cmd_channel.send_blocking(PlayerCommand::PositionUpdate(position.seconds_f64())) {
+                error!("Failed to send PositionUpdate: {e}");
+            }
+            self.prepare_gapless(position);
+        }
+    }
+
+    /// Makes the incoming song current once playback gets to it
+    fn switch_decks(&self) -> Option<Uuid> {
+        let output = self.pipeline.query_position::<ClockTime>()?;
+        let mut decks = self.decks.lock().unwrap();
+        if decks.incoming.as_ref()?.offset > decks.running_time(output) {
+            return None;
+        }
+        let incoming = decks.incoming.take()?;
+        let uuid = incoming.uuid;
+        if let Some(old) = decks.current.replace(incoming) {
+            decks.leaving.push(old);
+        }
+        uuid
+    }
+
+    /// Loads the lined up song once the current one is close to its end
+    fn prepare_gapless(&self, position: ClockTime) {
+        if self.lined_up.borrow().is_none() {
+            return;
+        }
+        let current = {
+            let decks = self.decks.lock().unwrap();
+            if decks.next.is_some() || decks.incoming.is_some() {
+                return;
+            }
+            let Some(current) = decks.current.as_ref() else {
+                return;
+            };
+            current.src.clone()
+        };
+        let remaining = current.query_duration::<ClockTime>().map(|d| d.saturating_sub(position));
+        if remaining.is_none_or(|r| r <= GAPLESS_LEAD) && let Err(err) = self.hold_next() {
+            warn!(%err, "Couldn't load the next song");
+        }
+    }
+
+    /// Makes sure the lined up song is loaded on a held back deck, gives its number and when it's ready
+    fn hold_next(&self) -> Result<(u64, Receiver<()>), Box<dyn Error>> {
+        let (uuid, uri) = self.lined_up.borrow().clone().ok_or("No song lined up")?;
+        {
+            let decks = self.decks.lock().unwrap();
+            if decks.incoming.is_some() {
+                return Err("The next song is already mixed in".into());
+            }
+            if let Some(next) = decks.next.as_ref().filter(|d| d.uuid == Some(uuid)) {
+                return Ok((next.id, next.ready.clone()));
+            }
+        }
+        debug!(uri = %logging::redact(&uri), "Loading next stream");
+        let deck = self.add_deck(&uri, Some(uuid), true)?;
+        let held = (deck.id, deck.ready.clone());
+        let old = self.decks.lock().unwrap().next.replace(deck);
+        if let Some(old) = old {
+            tear_down(&self.pipeline, &self.mixer, old);
+        }
+        Ok(held)
+    }
+
+    /// Sets the song to play after the current one
+    pub fn line_up(&self, next: Option<(Uuid, String)>) {
+        let uuid = next.as_ref().map(|(uuid, _)| *uuid);
+        self.lined_up.replace(next);
+        let stale = {
+            let mut decks = self.decks.lock().unwrap();
+            if decks.next.as_ref().is_some_and(|d| d.uuid != uuid) { decks.next.take() } else { None }
+        };
+        if let Some(stale) = stale {
+            tear_down(&self.pipeline, &self.mixer, stale);
+        }
+    }
+
+    pub fn lined_up(&self) -> Option<Uuid> {
+        self.lined_up.borrow().as_ref().map(|(uuid, _)| *uuid)
+    }
+
+    /// Fades the lined up song in over the last `duration` of the current one, fading that out.
+    /// The queue moves on with a `GaplessSwitch` once the fade starts playing.
+    pub async fn crossfade(&self, duration: Duration) -> Result<(), Box<dyn Error>> {
+        let current_id = self.decks.lock().unwrap().current.as_ref().ok_or("Nothing is playing")?.id;
+        let (next_id, ready) = self.hold_next()?;
+        // Only fails if the deck is thrown away, which the checks below catch
+        let _ = ready.recv().await;
+
+        let src = {
+            let decks = self.decks.lock().unwrap();
+            decks.current.as_ref().filter(|d| d.id == current_id).ok_or("The songs changed while loading")?.src.clone()
+        };
+        let song_duration = src.query_duration::<ClockTime>().ok_or("Song length unknown")?;
+        let fade_duration = ClockTime::from_nseconds(duration.as_nanos() as u64);
+        let start = song_duration.saturating_sub(fade_duration);
+
+        let mut decks = self.decks.lock().unwrap();
+        let unchanged = decks.next.as_ref().is_some_and(|d| d.id == next_id) && decks.incoming.is_none();
+        let Some(current) = decks.current.as_ref().filter(|d| unchanged && d.id == current_id) else {
+            return Err("The songs changed while loading".into());
+        };
+        let offset = decks.running_time_at(current, start);
+        let fade_out = current.fade.clone();
+        let Some(mut next) = decks.next.take() else {
+            return Err("The songs changed while loading".into());
+        };
+        debug!(%start, %offset, "Mixing in the next song for a crossfade");
+        *next.fade.lock().unwrap() = Some(Fade { start: ClockTime::ZERO, duration: fade_duration, fading_in: true });
+        if let Err(err) = next.mix_in(&self.mixer, offset) {
+            decks.next = Some(next);
+            return Err(err);
+        }
+        *fade_out.lock().unwrap() = Some(Fade { start, duration: fade_duration, fading_in: false });
+        decks.incoming = Some(next);
+        Ok(())
+    }
+
+    /// Replaces whatever is playing with `uri`, stopped
+    pub fn set_uri(&self, uri: &str) -> Result<(), Box<dyn Error>> {
+        self.lined_up.replace(None);
+        self.pending_seek.set(None);
+        let _ = self.pipeline.set_state(State::Ready);
+        let old = {
+            let mut decks = self.decks.lock().unwrap();
+            decks.seek_base = ClockTime::ZERO;
+            decks.rate = 1.0;
+            decks.take_all()
+        };
+        for deck in old {
+            tear_down(&self.pipeline, &self.mixer, deck);
+        }
+
+        let mut deck = self.add_deck(uri, None, false)?;
+        deck.mix_in(&self.mixer, ClockTime::ZERO)?;
+        self.decks.lock().unwrap().current = Some(deck);
+        if self.rate.get() != 1.0 {
+            self.pending_seek.set(Some(ClockTime::ZERO));
+        }
+        Ok(())
+    }
+
+    pub fn play(&self) {
+        if let Err(err) = self.pipeline.set_state(State::Playing) {
+            error!(%err, "Couldn't start playing");
+        }
+    }
+
+    pub fn pause(&self) {
+        if let Err(err) = self.pipeline.set_state(State::Paused) {
+            error!(%err, "Couldn't pause");
+        }
+    }
+
+    pub fn stop(&self) {
+        self.lined_up.replace(None);
+        self.pending_seek.set(None);
+        let _ = self.pipeline.set_state(State::Ready);
+        let old = self.decks.lock().unwrap().take_all();
+        for deck in old {
+            tear_down(&self.pipeline, &self.mixer, deck);
+        }
+    }
+
+    /// Position in the current song
+    pub fn position(&self) -> Option<ClockTime> {
+        if let Some(position) = self.pending_seek.get() {
+            return Some(position);
+        }
+        let output = self.pipeline.query_position::<ClockTime>()?;
+        let decks = self.decks.lock().unwrap();
+        let current = decks.current.as_ref()?;
+        Some(decks.position(current, decks.running_time(output)))
+    }
+
+    /// Seeks in the current song. Songs mixed in with it are dropped, the next one is mixed in again from there.
+    pub fn seek(&self, position: ClockTime) {
+        let (_, state, pending) = self.pipeline.state(ClockTime::ZERO);
+        if pending != State::VoidPending || !matches!(state, State::Paused | State::Playing) {
+            self.pending_seek.set(Some(position));
+            return;
+        }
+        let rate = self.rate.get();
+        let dropped = {
+            let mut decks = self.decks.lock().unwrap();
+            let Some(mut current) = decks.current.take() else {
+                return;
+            };
+            let dropped = decks.take_all();
+            current.fade.lock().unwrap().take();
+            current.fader.set_property("volume", 1.0);
+            current.src.set_offset(0);
+            current.offset = ClockTime::ZERO;
+            current.origin = position;
+            current.end.store(0, Ordering::Relaxed);
+            decks.current = Some(current);
+            decks.seek_base = position;
+            decks.rate = rate;
+            dropped
+        };
+        for deck in dropped {
+            tear_down(&self.pipeline, &self.mixer, deck);
+        }
+        if let Err(err) = self.pipeline.seek(rate, SeekFlags::FLUSH | SeekFlags::ACCURATE, SeekType::Set, position, SeekType::None, ClockTime::NONE) {
+            warn!(%err, "Couldn't seek");
+        }
+        if let Err(e) = self.cmd_channel.send_blocking(PlayerCommand::PositionUpdate(position.seconds_f64())) {
+            error!("Failed to send PositionUpdate: {e}");
+        }
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate.get()
+    }
+
+    pub fn set_rate(&self, rate: f64) {
+        self.rate.set(rate);
+        if let Some(position) = self.position() {
+            self.seek(position);
+        }
+    }
+
+    pub fn set_volume(&self, volume: f64) {
+        self.volume.set_property("volume", volume);
+    }
+
+    /// Turns replay gain on in track or album mode, or off with `None`.
+    /// Turning it on or off applies from the next song, the mode applies right away.
+    pub fn set_replay_gain(&self, album_mode: Option<bool>) {
+        self.replay_gain.set(album_mode);
+        if let Some(album_mode) = album_mode {
+            let decks = self.decks.lock().unwrap();
+            for rg_volume in decks.current.iter().chain(decks.incoming.iter()).filter_map(|d| d.rg_volume.as_ref()) {
+                rg_volume.set_property("album-mode", album_mode);
+            }
+        }
+    }
+}
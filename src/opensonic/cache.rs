@@ -1,8 +1,11 @@
-use crate::opensonic::client::OpenSubsonicClient;
-use crate::opensonic::types::{Album, AlbumListType, Artist, LyricsList, Song};
+use crate::opensonic::client::{self, OpenSubsonicClient};
+use crate::opensonic::lrc;
+use crate::opensonic::types::{Album, AlbumListType, Artist, LyricsLines, LyricsList, Song};
 use crate::ui::album_object::AlbumObject;
 use crate::ui::artist_object::ArtistObject;
+use std::collections::HashMap;
 use std::error::Error;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::Arc;
 use color_thief::{Color, ColorFormat};
@@ -10,8 +13,16 @@ use evicting_cache_map::EvictingCacheMap;
 use relm4::adw::gdk::{MemoryFormat, Texture, TextureDownloader};
 use relm4::adw::{gio, glib};
 use relm4::adw::gio::Cancellable;
+use relm4::adw::gio::Settings;
+use relm4::adw::gio::prelude::SettingsExt;
 use relm4::gtk::gdk_pixbuf;
+use futures_util::{stream, StreamExt};
 use tokio::sync::RwLock;
+use tracing::warn;
+
+const SONG_FETCH_CONCURRENCY: usize = 8;
+/// Subdirectory of the lyrics directory imported files are kept in
+const IMPORTED_LYRICS_DIR: &str = "imported";
 
 #[derive(Clone, Debug)]
 pub struct SuperCache {
@@ -55,6 +66,21 @@ impl SuperCache {
         };
         Ok((songs, albums, artist))
     }
+
+    /// Searches songs, albums and artists with a single request
+    pub async fn search(
+        &self,
+        query: &str,
+        artist_count: u32,
+        album_count: u32,
+        song_count: u32
+    ) -> Result<(Vec<Rc<Song>>, Vec<AlbumObject>, Vec<ArtistObject>), Box<dyn Error>> {
+        let res = self.client.search3(query, Some(artist_count), None, Some(album_count), None, Some(song_count), None, None).await?;
+        let songs = self.song_cache.add_songs(res.song.unwrap_or_default()).await;
+        let albums = self.album_cache.add_albums(res.album.unwrap_or_default()).await;
+        let artists = self.artist_cache.add_artist(res.artist.unwrap_or_default()).await;
+        Ok((songs, albums, artists))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -84,6 +110,43 @@ impl SongCache {
         Ok(song)
     }
 
+    /// Gets many songs at once, fetching the uncached ones a few at a time.
+    /// Songs that fail to load are left out.
+    pub async fn get_songs(&self, ids: &[String]) -> HashMap<String, Rc<Song>> {
+        let mut songs = HashMap::new();
+        let mut missing = Vec::new();
+        {
+            let cache_r = self.cache.read().await;
+            for id in ids {
+                match cache_r.get_no_promote(id) {
+                    Some(song) => {songs.insert(id.clone(), song.clone());}
+                    None => missing.push(id.clone()),
+                }
+            }
+        }
+
+        let fetched: Vec<(String, Result<Rc<Song>, Box<dyn Error>>)> = stream::iter(missing)
+            .map(|id| async move {
+                let res = self.client.get_song(&id).await;
+                (id, res)
+            })
+            .buffer_unordered(SONG_FETCH_CONCURRENCY)
+            .collect()
+            .await;
+
+        let mut cache_w = self.cache.write().await;
+        for (id, res) in fetched {
+            match res {
+                Ok(song) => {
+                    cache_w.insert(id.clone(), song.clone());
+                    songs.insert(id, song);
+                }
+                Err(err) => warn!(%id, %err, "Couldn't load song"),
+            }
+        }
+        songs
+    }
+
     pub async fn add_songs(&self, songs: Vec<Song>) -> Vec<Rc<Song>> {
         let mut cache_w = self.cache.write().await;
 
@@ -118,11 +181,8 @@ impl SongCache {
 
     pub async fn search(&self, query: &str, count: u32, offset: Option<u32>) -> Result<Vec<Rc<Song>>, Box<dyn Error>> {
         let res = self.client.search3(query, Some(0), None, Some(0), None, Some(count), offset, None).await?;
-        if let Some(songs) = res.song {
-            Ok(self.add_songs(songs).await)
-        } else {
-            Err("No songs found".into())
-        }
+        // Paging past the last result gives nothing, which isn't an error
+        Ok(self.add_songs(res.song.unwrap_or_default()).await)
     }
 
     pub async fn toggle_starred(&self, song: &Rc<Song>) -> Result<(), Box<dyn Error>> {
@@ -227,11 +287,7 @@ impl AlbumCache {
 
     pub async fn search(&self, query: &str, count: u32, offset: Option<u32>) -> Result<Vec<AlbumObject>, Box<dyn Error>> {
         let res = self.client.search3(query, Some(0), None, Some(count), offset, Some(0), None, None).await?;
-        if let Some(albums) = res.album {
-            Ok(self.add_albums(albums).await)
-        } else {
-            Err("No albums found".into())
-        }
+        Ok(self.add_albums(res.album.unwrap_or_default()).await)
     }
 
     pub async fn toggle_starred(&self, album: &AlbumObject) -> Result<(), Box<dyn Error>> {
@@ -292,28 +348,115 @@ impl CoverCache {
 pub struct LyricsCache {
     cache: Rc<RwLock<EvictingCacheMap<String, Arc<Vec<LyricsList>>, 10, fn(String, Arc<Vec<LyricsList>>)>>>,
     client: &'static OpenSubsonicClient,
+    settings: Settings,
 }
 
 impl LyricsCache {
-    pub fn new(client: &'static OpenSubsonicClient) -> Self {
+    pub fn new(client: &'static OpenSubsonicClient, settings: Settings) -> Self {
         Self {
             client,
             cache: Rc::new(RwLock::new(EvictingCacheMap::new())),
+            settings,
         }
     }
 
-    pub async fn get_lyrics(&self, id: &str) -> Result<Arc<Vec<LyricsList>>, Box<dyn Error>> {
+    pub async fn get_lyrics(&self, song: &Song) -> Result<Arc<Vec<LyricsList>>, Box<dyn Error>> {
         {
             let cache_r = self.cache.read().await;
-            if let Some(lyrics) = cache_r.get_no_promote(id) {
+            if let Some(lyrics) = cache_r.get_no_promote(song.id.as_str()) {
                 return Ok(lyrics.clone());
             }
         }
-        let lyrics = Arc::new(self.client.get_lyrics(id).await?);
+        let imported = match self.imported_path(song) {
+            Some(path) => Self::read_lyrics_file(&path).await,
+            None => None,
+        };
+        let lyrics = if let Some(imported) = imported {
+            vec![imported]
+        } else if self.settings.boolean("prefer-local-lyrics") {
+            match self.get_local_lyrics(song).await {
+                Some(local) => vec![local],
+                None => self.client.get_lyrics(song).await?,
+            }
+        } else {
+            match self.client.get_lyrics(song).await {
+                Ok(l) if !l.is_empty() => l,
+                other => match self.get_local_lyrics(song).await {
+                    Some(local) => vec![local],
+                    None => other?,
+                },
+            }
+        };
+        let lyrics = Arc::new(lyrics);
+        let mut cache_w = self.cache.write().await;
+        cache_w.insert(song.id.clone(), lyrics.clone());
+        Ok(lyrics)
+    }
+
+    /// Copies a lyrics file into the lyrics directory. It's kept apart from the other local files
+    /// and used for this song from now on, over the server's lyrics too.
+    pub async fn import_lyrics(&self, song: &Song, path: &Path) -> Result<Arc<Vec<LyricsList>>, Box<dyn Error>> {
+        let path_to = self.imported_path(song).ok_or("No lyrics directory set")?;
+        let text = tokio::fs::read_to_string(path).await?;
+        let list = lrc::parse_lrc(&text).into_lyrics_list();
+        if let LyricsLines::None = list.lines {
+            return Err("No lyrics found in file".into());
+        }
+        if let Some(dir) = path_to.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::write(path_to, text).await?;
+
+        let lyrics = Arc::new(vec![list]);
         let mut cache_w = self.cache.write().await;
-        cache_w.insert(id.to_string(), lyrics.clone());
+        cache_w.insert(song.id.clone(), lyrics.clone());
         Ok(lyrics)
     }
+
+    /// Where the lyrics imported for a song go, named after its id since that's what they were imported for
+    fn imported_path(&self, song: &Song) -> Option<PathBuf> {
+        let name = format!("{}.lrc", song.id).replace(['/', '\0'], "_");
+        Some(self.lyrics_dir()?.join(IMPORTED_LYRICS_DIR).join(name))
+    }
+
+    fn lyrics_dir(&self) -> Option<PathBuf> {
+        let dir = self.settings.value("lyrics-directory")
+            .as_maybe()
+            .and_then(|dir| dir.get::<String>())
+            .filter(|dir| !dir.is_empty());
+        match dir {
+            Some(dir) => Some(PathBuf::from(dir)),
+            None => client::get_default_data_dir().map(|d| Path::new(&d).join("lyrics")),
+        }
+    }
+
+    /// File names that are checked in order, MusicBrainz ID first since it's unambiguous
+    fn local_file_names(song: &Song) -> Vec<String> {
+        let mut names = Vec::with_capacity(2);
+        if let Some(mbid) = &song.music_brainz_id && !mbid.is_empty() {
+            names.push(format!("{}.lrc", mbid));
+        }
+        if let Some(artist) = &song.artist {
+            names.push(format!("{} - {}.lrc", artist, song.title).replace(['/', '\0'], "_"));
+        }
+        names
+    }
+
+    async fn get_local_lyrics(&self, song: &Song) -> Option<LyricsList> {
+        let dir = self.lyrics_dir()?;
+        for name in Self::local_file_names(song) {
+            if let Some(list) = Self::read_lyrics_file(&dir.join(name)).await {
+                return Some(list);
+            }
+        }
+        None
+    }
+
+    async fn read_lyrics_file(path: &Path) -> Option<LyricsList> {
+        let text = tokio::fs::read_to_string(path).await.ok()?;
+        let list = lrc::parse_lrc(&text).into_lyrics_list();
+        (!matches!(list.lines, LyricsLines::None)).then_some(list)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -373,11 +516,7 @@ impl ArtistCache {
 
     pub async fn search(&self, query: &str, count: u32, offset: Option<u32>) -> Result<Vec<ArtistObject>, Box<dyn Error>> {
         let res = self.client.search3(query, Some(count), offset, Some(0), None, Some(0), None, None).await?;
-        if let Some(artists) = res.artist {
-            Ok(self.add_artist(artists).await)
-        } else {
-            Err("No artists found".into())
-        }
+        Ok(self.add_artist(res.artist.unwrap_or_default()).await)
     }
 
     pub async fn toggle_starred(&self, artist: &ArtistObject) -> Result<(), Box<dyn Error>> {
@@ -1,9 +1,12 @@
 use std::collections::HashSet;
-use crate::opensonic::types::{Album, AlbumListType, Artist, Extension, GenericResponse, InvalidResponseError, License, LyricsLine, LyricsLines, LyricsList, OpenSubsonicResponse, OpenSubsonicResponseEmpty, Search3Results, Song, Songs, Starred, SubsonicError, SupportedExtensions};
-use format_url::FormatUrl;
+use crate::logging;
+use crate::opensonic::lrc;
+use crate::opensonic::http::HttpOptions;
+use crate::opensonic::tls::TlsOptions;
+use crate::opensonic::types::{Album, AlbumListType, Artist, Extension, GenericResponse, InvalidResponseError, License, LyricsLine, LyricsLines, LyricsList, OpenSubsonicResponse, OpenSubsonicResponseEmpty, Playlist, Search3Results, Song, Songs, Starred, SubsonicError, SupportedExtensions};
 use rand::distr::{Alphanumeric, SampleString};
 use reqwest;
-use reqwest::{Client, ClientBuilder, Response};
+use reqwest::{Client, ClientBuilder, Response, Url};
 use serde_json::Value;
 use std::env;
 use std::error::Error;
@@ -13,6 +16,7 @@ use std::rc::Rc;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
+use tracing::{debug, info, warn};
 
 #[derive(Debug, Clone)]
 pub enum Credentials {
@@ -27,12 +31,14 @@ pub enum Credentials {
 
 #[derive(Debug)]
 pub struct OpenSubsonicClient {
-    host: String,
+    base_url: Url,
     credentials: Credentials,
     client_name: String,
     client: Client,
     version: String,
     cover_cache: Option<String>,
+    tls: TlsOptions,
+    http: HttpOptions,
 
     extensions: RwLock<HashSet<SupportedExtensions>>,
 }
@@ -49,6 +55,18 @@ pub fn get_default_cache_dir() -> Option<String> {
     }
 }
 
+pub fn get_default_data_dir() -> Option<String> {
+    match env::var("XDG_DATA_HOME") {
+        Ok(p) => Path::new(p.as_str()).join("sanicrs").to_str().and_then(|s| Some(s.to_string())),
+        Err(_) => {
+            match env::var("HOME") {
+                Ok(p) => Path::new(p.as_str()).join(".local/share/sanicrs").to_str().and_then(|s| Some(s.to_string())),
+                Err(_) => None,
+            }
+        },
+    }
+}
+
 impl OpenSubsonicClient {
 
     pub fn new(
@@ -56,7 +74,15 @@ impl OpenSubsonicClient {
         credentials: Credentials,
         client_name: &str,
         cover_cache: Option<String>,
-    ) -> Self {
+        tls: TlsOptions,
+        http: HttpOptions,
+    ) -> Result<Self, Box<dyn Error>> {
+        // Servers can be hosted under a sub-path (e.g. behind a reverse proxy), which has to be kept
+        let base_url = Url::parse(host.trim())?;
+        if base_url.cannot_be_a_base() {
+            return Err(format!("'{}' can't be used as a server URL", host).into());
+        }
+
         // Validate cache dir
         let cover_cache_real = if let Some(cover_cache) = &cover_cache {
             let path = Path::new(cover_cache);
@@ -64,36 +90,46 @@ impl OpenSubsonicClient {
             if result.is_ok() {
                 let result = std::fs::exists(path);
                 if result.is_err() {
-                    eprintln!(
+                    warn!(
                         "Can't read cache dir/ ({}): {}",
                         cover_cache,
                         result.err().unwrap()
                     );
                     None
                 } else if !result.ok().unwrap() {
-                    println!("Cache dir not found: {}", cover_cache);
+                    warn!("Cache dir not found: {}", cover_cache);
                     None
                 } else {
                     Some(cover_cache)
                 }
             } else {
-                eprintln!("Error creating cache directory '{}': {:?}", cover_cache, result.err().unwrap());
+                warn!("Error creating cache directory '{}': {:?}", cover_cache, result.err().unwrap());
                 None
             }
         } else {
-            println!("No cache dir set.");
+            info!("No cache dir set.");
             None
         };
 
-        OpenSubsonicClient {
-            host: String::from(host),
+        Ok(OpenSubsonicClient {
+            base_url,
             credentials,
             client_name: String::from(client_name),
-            client: ClientBuilder::new().build().unwrap(),
+            client: http.apply(tls.apply(ClientBuilder::new())?)?.build()?,
             version: String::from("1.15"),
             cover_cache: cover_cache_real.cloned(),
+            tls,
+            http,
             extensions: RwLock::new(HashSet::new()),
-        }
+        })
+    }
+
+    pub fn tls_options(&self) -> &TlsOptions {
+        &self.tls
+    }
+
+    pub fn http_options(&self) -> &HttpOptions {
+        &self.http
     }
 
     pub async fn init(&self) -> Result<(), Box<dyn Error>> {
@@ -104,12 +140,12 @@ impl OpenSubsonicClient {
                 Ok(e) => {
                     guard.insert(e);
                 },
-                Err(_) => println!("Unused extension '{}' supported by server", ext.name)
+                Err(_) => debug!("Unused extension '{}' supported by server", ext.name)
             };
         }
         drop(guard);
         let guard = self.extensions.read().await;
-        println!("Supported extensions present: {:?}", guard);
+        info!("Supported extensions present: {:?}", guard);
         if let Credentials::ApiKey {..} = &self.credentials && !guard.contains(&SupportedExtensions::ApiKeyAuthentication) {
             return Err("API Key authentication not supported by server".into());
         }
@@ -159,11 +195,20 @@ impl OpenSubsonicClient {
             let buf = Path::new(cover_cache).join(Path::new(id));
             let r = tokio::fs::write(buf, data).await;
             if let Err(err) = r {
-                println!("Error when trying to write cache: {:?}", err);
+                warn!("Error when trying to write cache: {:?}", err);
             }
         }
     }
 
+    fn action_url(&self, action: &str) -> Url {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .expect("Base URL checked when creating client")
+            .pop_if_empty()
+            .extend(["rest", action]);
+        url
+    }
+
     fn get_action_request_get_url(
         &self,
         action: &str,
@@ -172,10 +217,9 @@ impl OpenSubsonicClient {
         let params = self.get_auth_params();
         let mut params: Vec<(&str, &str)> = params.iter().map(|(k, v)| (*k, v.as_str())).collect();
         params.extend(extra_params);
-        let url = FormatUrl::new(&self.host)
-            .with_path_template("/rest/:action")
-            .with_substitutes(vec![("action", action)]);
-        url.with_query_params(params).format_url()
+        let mut url = self.action_url(action);
+        url.query_pairs_mut().extend_pairs(params);
+        url.to_string()
     }
 
     async fn get_action_request(
@@ -186,18 +230,17 @@ impl OpenSubsonicClient {
         let params = self.get_auth_params();
         let mut params: Vec<(&str, &str)> = params.iter().map(|(k, v)| (*k, v.as_str())).collect();
         params.extend(extra_params);
-        let url = FormatUrl::new(&self.host)
-            .with_path_template("/rest/:action")
-            .with_substitutes(vec![("action", action)]);
-        println!("Making request to '{}' with params: {:?}", action, params);
-        if self.extensions.read().await.contains(&SupportedExtensions::FormPost) {
-            let builder = self.client.post(url.format_url()).form(&params);
-            builder.send().await.map_err(|e| e.into())
+        let mut url = self.action_url(action);
+        debug!(action, params = ?logging::redact_params(&params), "Making request");
+        let response = if self.extensions.read().await.contains(&SupportedExtensions::FormPost) {
+            let builder = self.client.post(url).form(&params);
+            builder.send().await?
         } else {
-            let builder = self.client
-                .get(url.with_query_params(params).format_url());
-            builder.send().await.map_err(|e| e.into())
-        }
+            url.query_pairs_mut().extend_pairs(params);
+            let builder = self.client.get(url);
+            builder.send().await?
+        };
+        Ok(response)
     }
 
     async fn make_action_request<T: DeserializeOwned>(&self, action: &str, extra_params: Vec<(&str, &str)>) -> Result<T, Box<dyn Error>> {
@@ -439,7 +482,7 @@ impl OpenSubsonicClient {
         music_folder_id: Option<String>
     ) -> Result<Vec<Album>, Box<dyn Error>> {
         let size = size.unwrap_or(10).to_string();
-        let offset = offset.unwrap_or(10).to_string();
+        let offset = offset.unwrap_or(0).to_string();
         let from_year = from_year.and_then(|x| Some(x.to_string()));
         let to_year = to_year.and_then(|x| Some(x.to_string()));
         let mut params: Vec<(&str, &str)> = vec![
@@ -558,13 +601,13 @@ impl OpenSubsonicClient {
 
     pub async fn get_lyrics(
         &self,
-        id: &str
+        song: &Song
     ) -> Result<Vec<LyricsList>, Box<dyn Error>> {
         if !self.extensions.read().await.contains(&SupportedExtensions::SongLyrics) {
-            return Ok(Vec::new());
+            return self.get_lyrics_legacy(song).await;
         }
 
-        let params = vec![("id", id)];
+        let params = vec![("id", song.id.as_str())];
 
         let body = self
             .get_action_request("getLyricsBySongId", params)
@@ -613,7 +656,7 @@ impl OpenSubsonicClient {
             match r {
                 Ok(v) => Some(v),
                 Err(e) => {
-                    eprintln!("Error when parsing lyrics: {}", e);
+                    warn!("Error when parsing lyrics: {}", e);
                     None
                 },
             }
@@ -623,16 +666,64 @@ impl OpenSubsonicClient {
         Ok(resp)
     }
 
+    async fn get_lyrics_legacy(
+        &self,
+        song: &Song
+    ) -> Result<Vec<LyricsList>, Box<dyn Error>> {
+        let mut params = vec![("title", song.title.as_str())];
+        if let Some(artist) = &song.artist {
+            params.push(("artist", artist.as_str()));
+        }
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Lyrics {
+            pub artist: Option<String>,
+            pub title: Option<String>,
+            pub value: Option<String>,
+        }
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Wrapper {
+            pub lyrics: Option<Lyrics>,
+        }
+
+        let lyrics = match self.make_action_request::<Wrapper>("getLyrics", params).await?.lyrics {
+            Some(l) => l,
+            None => return Ok(Vec::new()),
+        };
+        let lines = match lyrics.value.as_deref() {
+            Some(value) => lrc::parse(value),
+            None => LyricsLines::None,
+        };
+        if let LyricsLines::None = lines {
+            return Ok(Vec::new());
+        }
+
+        Ok(vec![LyricsList {
+            display_artist: lyrics.artist,
+            display_title: lyrics.title,
+            lang: "xxx".to_string(),
+            offset: None,
+            synced: matches!(lines, LyricsLines::Synced(_)),
+            lines,
+        }])
+    }
+
     pub async fn scrobble(
         &self,
         id: &str,
-        submission: Option<bool>
+        submission: Option<bool>,
+        time: Option<u64>
     ) -> Result<(), Box<dyn Error>> {
         let submission = submission.unwrap_or(true).to_string();
-        let params = vec![
+        let time = time.map(|t| t.to_string());
+        let mut params = vec![
             ("id", id),
             ("submission", submission.as_str())
         ];
+        if let Some(time) = time.as_ref() {
+            params.push(("time", time.as_str()));
+        }
 
         self.make_action_request_empty("scrobble", params).await
     }
@@ -711,4 +802,47 @@ impl OpenSubsonicClient {
             .make_action_request::<Wrapper>("getStarred2", vec![])
             .await?.starred2)
     }
+
+    pub async fn get_playlists(
+        &self,
+    ) -> Result<Vec<Playlist>, Box<dyn Error>> {
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Playlists {
+            pub playlist: Option<Vec<Playlist>>,
+        }
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Wrapper {
+            pub playlists: Playlists,
+        }
+
+        Ok(self
+            .make_action_request::<Wrapper>("getPlaylists", vec![])
+            .await?.playlists.playlist.unwrap_or_default())
+    }
+
+    pub async fn create_playlist(
+        &self,
+        name: &str,
+        song_ids: Vec<&str>
+    ) -> Result<(), Box<dyn Error>> {
+        let mut params = vec![("name", name)];
+        for id in song_ids {
+            params.push(("songId", id));
+        }
+
+        self.make_action_request_empty("createPlaylist", params).await
+    }
+
+    pub async fn update_playlist(
+        &self,
+        id: &str,
+        song_ids_to_add: Vec<&str>
+    ) -> Result<(), Box<dyn Error>> {
+        let mut params = vec![("playlistId", id)];
+        for id in song_ids_to_add {
+            params.push(("songIdToAdd", id));
+        }
+
+        self.make_action_request_empty("updatePlaylist", params).await
+    }
 }
\ No newline at end of file
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use libsecret::{Schema, SchemaAttributeType, SchemaFlags};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest::ClientBuilder;
+use relm4::gtk::gio::prelude::SettingsExt;
+use relm4::gtk::gio::Settings;
+use relm4::gtk::glib;
+use relm4::gtk::glib::prelude::ToVariant;
+use gstreamer::prelude::ObjectExt;
+use crate::APP_ID;
+
+/// Options for servers behind a reverse proxy
+#[derive(Clone, Default)]
+pub struct HttpOptions {
+    /// Headers added to every API and stream request
+    pub extra_headers: Vec<(String, String)>,
+    pub basic_auth: Option<(String, String)>,
+}
+
+impl fmt::Debug for HttpOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpOptions")
+            .field("extra_headers", &self.extra_headers)
+            .field("basic_auth", &self.basic_auth.as_ref().map(|(username, _)| (username, "<redacted>")))
+            .finish()
+    }
+}
+
+/// Secret schema the basic auth password is stored under, separate from the server password
+pub fn basic_auth_schema() -> Schema {
+    Schema::new(
+        format!("{}.BasicAuth", APP_ID).as_str(),
+        SchemaFlags::NONE,
+        HashMap::from([("server", SchemaAttributeType::String), ("username", SchemaAttributeType::String)]),
+    )
+}
+
+/// Attributes the basic auth password for `username` in front of `server` is stored under
+pub fn basic_auth_attributes<'a>(server: &'a str, username: &'a str) -> HashMap<&'a str, &'a str> {
+    HashMap::from([("server", server), ("username", username)])
+}
+
+/// Parses headers written as `Name: value; Other-Name: value`
+pub fn parse_headers(text: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    text.split(';')
+        .map(|h| h.trim())
+        .filter(|h| !h.is_empty())
+        .map(|h| {
+            let (name, value) = h.split_once(':').ok_or(format!("Header '{}' is missing a ':'", h))?;
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+pub fn format_headers(headers: &[(String, String)]) -> String {
+    headers.iter()
+        .map(|(name, value)| format!("{}: {}", name, value))
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+impl HttpOptions {
+    /// Loads the options from settings. The basic auth password is kept in the secret store, so it's passed separately.
+    pub fn load(settings: &Settings, basic_auth_password: Option<String>) -> Self {
+        let extra_headers = settings.value("extra-headers")
+            .get::<Vec<(String, String)>>()
+            .unwrap_or_default();
+        let username = settings.value("basic-auth-username")
+            .as_maybe()
+            .and_then(|v| v.get::<String>())
+            .filter(|s| !s.is_empty());
+        Self {
+            extra_headers,
+            basic_auth: username.map(|u| (u, basic_auth_password.unwrap_or_default())),
+        }
+    }
+
+    pub fn save(&self, settings: &Settings) -> Result<(), Box<dyn Error>> {
+        settings.set_value("extra-headers", &self.extra_headers.to_variant())?;
+        let username = match &self.basic_auth {
+            Some((username, _)) => glib::Variant::from_some(&glib::Variant::from(username.as_str())),
+            None => glib::Variant::from_none(glib::VariantTy::STRING),
+        };
+        settings.set_value("basic-auth-username", &username)?;
+        Ok(())
+    }
+
+    /// All headers that should be sent with a request, including the basic auth header
+    pub fn headers(&self) -> Vec<(String, String)> {
+        let mut headers = self.extra_headers.clone();
+        if let Some((username, password)) = &self.basic_auth {
+            let credentials = glib::base64_encode(format!("{}:{}", username, password).as_bytes());
+            headers.push((AUTHORIZATION.to_string(), format!("Basic {}", credentials)));
+        }
+        headers
+    }
+
+    pub fn apply(&self, builder: ClientBuilder) -> Result<ClientBuilder, Box<dyn Error>> {
+        let mut map = HeaderMap::new();
+        for (name, value) in self.headers() {
+            let mut value = HeaderValue::from_str(&value)?;
+            if name.eq_ignore_ascii_case(AUTHORIZATION.as_str()) {
+                value.set_sensitive(true);
+            }
+            map.append(HeaderName::from_bytes(name.as_bytes())?, value);
+        }
+        Ok(builder.default_headers(map))
+    }
+
+    /// Applies the headers to the HTTP source element GStreamer uses for streaming
+    pub fn configure_source(&self, source: &gstreamer::Element) {
+        if source.find_property("extra-headers").is_none() {
+            return;
+        }
+        let headers = self.headers();
+        if headers.is_empty() {
+            return;
+        }
+        let mut builder = gstreamer::Structure::builder("extra-headers");
+        for (name, value) in headers {
+            builder = builder.field(name, value);
+        }
+        source.set_property("extra-headers", builder.build());
+    }
+}
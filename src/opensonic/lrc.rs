@@ -0,0 +1,203 @@
+use crate::opensonic::types::{LyricsLine, LyricsLines, LyricsList};
+
+#[derive(Debug, Default)]
+pub struct Lrc {
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub author: Option<String>,
+    pub length: Option<String>,
+    pub lang: Option<String>,
+    /// Offset in milliseconds, positive values make the lyrics appear sooner
+    pub offset: i64,
+    pub lines: LyricsLines,
+}
+
+impl Lrc {
+    /// Converts into a lyrics list. The offset is already applied to the timestamps.
+    pub fn into_lyrics_list(self) -> LyricsList {
+        LyricsList {
+            display_artist: self.artist,
+            display_title: self.title,
+            lang: self.lang.unwrap_or("xxx".to_string()),
+            offset: None,
+            synced: matches!(self.lines, LyricsLines::Synced(_)),
+            lines: self.lines,
+        }
+    }
+}
+
+/// Parses a timestamp tag such as `01:23.45` into milliseconds
+fn parse_timestamp(tag: &str) -> Option<u32> {
+    let (min, sec) = tag.split_once(':')?;
+    let min: u32 = min.trim().parse().ok()?;
+    let (sec, frac) = match sec.split_once(['.', ':']) {
+        Some((s, f)) => (s, f),
+        None => (sec, "0"),
+    };
+    let sec: u32 = sec.trim().parse().ok()?;
+    // Fraction can be given in tenths, hundredths or thousandths
+    let frac = frac.trim();
+    if !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let frac = &frac[..frac.len().min(3)];
+    let frac_ms = if frac.is_empty() {
+        0
+    } else {
+        frac.parse::<u32>().ok()? * 10u32.pow(3 - frac.len() as u32)
+    };
+    min.checked_mul(60_000)?.checked_add(sec.checked_mul(1000)?)?.checked_add(frac_ms)
+}
+
+/// Removes word-level timestamps (`<01:23.45>`) used by enhanced LRC
+fn strip_word_timestamps(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        match rest[start..].find('>') {
+            Some(end) if parse_timestamp(&rest[start + 1..start + end]).is_some() => {
+                out.push_str(&rest[..start]);
+                rest = &rest[start + end + 1..];
+            }
+            _ => {
+                out.push_str(&rest[..start + 1]);
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Keys of the LRC header tags, anything else in brackets is part of the lyrics
+const LRC_TAGS: [&str; 12] = ["ar", "ti", "al", "by", "au", "length", "la", "lang", "offset", "re", "ve", "#"];
+
+pub fn parse_lrc(text: &str) -> Lrc {
+    let mut lrc = Lrc::default();
+    let mut synced: Vec<LyricsLine> = Vec::new();
+    let mut plain: Vec<String> = Vec::new();
+
+    for line in text.lines() {
+        let mut rest = line.trim();
+        let mut timestamps: Vec<u32> = Vec::new();
+        let mut had_tag = false;
+
+        // A line can start with any number of tags, e.g. `[00:12.00][01:30.50]Text`
+        while rest.starts_with('[') && let Some(end) = rest.find(']') {
+            let tag = &rest[1..end];
+            if let Some(ts) = parse_timestamp(tag) {
+                timestamps.push(ts);
+            } else if let Some((key, value)) = tag.split_once(':') && LRC_TAGS.contains(&key.trim().to_lowercase().as_str()) {
+                let value = value.trim().to_string();
+                match key.trim().to_lowercase().as_str() {
+                    "ar" => lrc.artist = Some(value),
+                    "ti" => lrc.title = Some(value),
+                    "al" => lrc.album = Some(value),
+                    "by" | "au" => lrc.author = Some(value),
+                    "length" => lrc.length = Some(value),
+                    "la" | "lang" => lrc.lang = Some(value),
+                    "offset" => lrc.offset = value.trim_start_matches('+').parse().unwrap_or(0),
+                    _ => {} // Other tags (re, ve, #) aren't used
+                }
+            } else {
+                // Not a tag, treat as part of the text. Plain lyrics often have
+                // section headers such as `[Verse 1: Artist]`.
+                break;
+            }
+            had_tag = true;
+            rest = rest[end + 1..].trim_start();
+        }
+
+        if !timestamps.is_empty() {
+            let value = strip_word_timestamps(rest);
+            for start in timestamps {
+                synced.push(LyricsLine {
+                    start,
+                    value: value.clone(),
+                });
+            }
+        } else if !had_tag {
+            plain.push(rest.to_string());
+        }
+    }
+
+    lrc.lines = if !synced.is_empty() {
+        // Lines with multiple timestamps are repeated, so order is restored by sorting
+        synced.sort_by_key(|l| l.start);
+        for line in synced.iter_mut() {
+            line.start = (line.start as i64 - lrc.offset).max(0) as u32;
+        }
+        LyricsLines::Synced(synced)
+    } else if plain.iter().any(|l| !l.is_empty()) {
+        // Trim leading and trailing blank lines
+        let first = plain.iter().position(|l| !l.is_empty()).unwrap_or(0);
+        let last = plain.iter().rposition(|l| !l.is_empty()).unwrap_or(0);
+        LyricsLines::NotSynced(plain[first..=last].to_vec())
+    } else {
+        LyricsLines::None
+    };
+    lrc
+}
+
+/// Parses plain text lyrics, returning synced lines if LRC timestamps are present
+pub fn parse(text: &str) -> LyricsLines {
+    parse_lrc(text).lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn synced(lines: &LyricsLines) -> Vec<(u32, &str)> {
+        match lines {
+            LyricsLines::Synced(lines) => lines.iter().map(|l| (l.start, l.value.as_str())).collect(),
+            _ => panic!("Lyrics aren't synced"),
+        }
+    }
+
+    #[test]
+    fn reads_header_tags_and_applies_offset() {
+        let lrc = parse_lrc("[ar: Artist]\n[ti:Title]\n[la:eng]\n[offset:+500]\n[00:01.00]One\n[00:00.20]Zero");
+        assert_eq!(lrc.artist.as_deref(), Some("Artist"));
+        assert_eq!(lrc.title.as_deref(), Some("Title"));
+        assert_eq!(lrc.lang.as_deref(), Some("eng"));
+        assert_eq!(lrc.offset, 500);
+        assert_eq!(synced(&lrc.lines), vec![(0, "Zero"), (500, "One")]);
+    }
+
+    #[test]
+    fn repeats_lines_with_several_timestamps() {
+        let lrc = parse_lrc("[00:10.5][01:02.25]Chorus\n[00:30.123]Verse");
+        assert_eq!(synced(&lrc.lines), vec![(10_500, "Chorus"), (30_123, "Verse"), (62_250, "Chorus")]);
+    }
+
+    #[test]
+    fn strips_word_timestamps() {
+        let lrc = parse_lrc("[00:01.00]<00:01.00> Hello <00:01.50> world <b>");
+        assert_eq!(synced(&lrc.lines), vec![(1000, "Hello world <b>")]);
+    }
+
+    #[test]
+    fn rejects_malformed_timestamps() {
+        assert_eq!(parse_timestamp("00:12.12é"), None);
+        assert_eq!(parse_timestamp("é:12"), None);
+        assert_eq!(parse_timestamp("00:12.1x"), None);
+        assert_eq!(parse_timestamp("4294967295:00"), None);
+        assert_eq!(parse_timestamp("00:4294967295"), None);
+        assert_eq!(parse_timestamp("00:12.3456"), Some(12_345));
+        assert_eq!(parse_timestamp("01:02"), Some(62_000));
+
+        assert_eq!(strip_word_timestamps("a <00:12.1é> b"), "a <00:12.1é> b");
+        let lrc = parse_lrc("[00:12.12é]Not a timestamp\nPlain");
+        assert!(matches!(lrc.lines, LyricsLines::NotSynced(ref lines) if lines == &["[00:12.12é]Not a timestamp", "Plain"]));
+    }
+
+    #[test]
+    fn keeps_section_headers_in_plain_lyrics() {
+        let lrc = parse_lrc("[ar:Artist]\n[Verse 1: Name]\nFirst line\n[Chorus: x2]\n[#: comment]\nSecond line");
+        assert_eq!(lrc.artist.as_deref(), Some("Artist"));
+        assert!(matches!(lrc.lines, LyricsLines::NotSynced(ref lines)
+            if lines == &["[Verse 1: Name]", "First line", "[Chorus: x2]", "Second line"]));
+    }
+}
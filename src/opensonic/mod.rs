@@ -1,3 +1,6 @@
 pub mod client;
 pub mod types;
-pub mod cache;
\ No newline at end of file
+pub mod cache;
+pub mod lrc;
+pub mod http;
+pub mod tls;
@@ -0,0 +1,348 @@
+use std::error::Error;
+use std::sync::Arc;
+use reqwest::tls::TlsInfo;
+use reqwest::{Certificate, ClientBuilder, Identity};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{CertificateError, DigitallySignedStruct, SignatureScheme};
+use relm4::gtk::gio::prelude::SettingsExt;
+use relm4::gtk::gio::Settings;
+use relm4::gtk::glib::{self, ChecksumType};
+use relm4::gtk::glib::prelude::*;
+use relm4::gtk::glib::subclass::prelude::{IsSubclassable, IsSubclassableExt, ObjectSubclassIsExt};
+use relm4::gtk::gio;
+use tracing::warn;
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    /// PEM file with extra CA certificates to trust
+    pub ca_file: Option<String>,
+    /// SHA-256 fingerprint of a (self-signed) server certificate that is trusted regardless of CA
+    pub pinned_fingerprint: Option<String>,
+    /// PEM client certificate, may also contain the private key
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+}
+
+fn maybe_string(settings: &Settings, key: &str) -> Option<String> {
+    settings.value(key)
+        .as_maybe()
+        .and_then(|v| v.get::<String>())
+        .filter(|s| !s.is_empty())
+}
+
+/// Formats the SHA-256 fingerprint of a DER encoded certificate as `AB:CD:...`
+pub fn fingerprint(der: &[u8]) -> String {
+    let hex = glib::compute_checksum_for_data(ChecksumType::Sha256, der)
+        .map(|s| s.to_uppercase())
+        .unwrap_or_default();
+    hex.as_bytes()
+        .chunks(2)
+        .map(|c| String::from_utf8_lossy(c).to_string())
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint.chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+impl TlsOptions {
+    pub fn load(settings: &Settings) -> Self {
+        Self {
+            ca_file: maybe_string(settings, "tls-ca-file"),
+            pinned_fingerprint: maybe_string(settings, "tls-pinned-fingerprint"),
+            client_cert: maybe_string(settings, "tls-client-cert"),
+            client_key: maybe_string(settings, "tls-client-key"),
+        }
+    }
+
+    pub fn save(&self, settings: &Settings) -> Result<(), Box<dyn Error>> {
+        let to_variant = |v: &Option<String>| match v {
+            Some(s) => glib::Variant::from_some(&glib::Variant::from(s.as_str())),
+            None => glib::Variant::from_none(glib::VariantTy::STRING),
+        };
+        settings.set_value("tls-ca-file", &to_variant(&self.ca_file))?;
+        settings.set_value("tls-pinned-fingerprint", &to_variant(&self.pinned_fingerprint))?;
+        settings.set_value("tls-client-cert", &to_variant(&self.client_cert))?;
+        settings.set_value("tls-client-key", &to_variant(&self.client_key))?;
+        Ok(())
+    }
+
+    pub fn apply(&self, mut builder: ClientBuilder) -> Result<ClientBuilder, Box<dyn Error>> {
+        builder = builder.tls_info(true);
+        if let Some(ca_file) = &self.ca_file {
+            let pem = std::fs::read(ca_file)
+                .map_err(|e| format!("Error reading CA file '{}': {}", ca_file, e))?;
+            builder = builder.tls_certs_merge(Certificate::from_pem_bundle(&pem)?);
+        }
+        if let Some(pin) = &self.pinned_fingerprint {
+            // Normal verification would reject the certificate, so it's checked against the pin
+            // during the handshake instead, before anything is sent
+            return Ok(builder.use_preconfigured_tls(self.pinned_config(pin)?));
+        }
+        if let Some(cert) = &self.client_cert {
+            let mut pem = std::fs::read(cert)
+                .map_err(|e| format!("Error reading client certificate '{}': {}", cert, e))?;
+            if let Some(key) = &self.client_key {
+                pem.push(b'\n');
+                pem.extend(std::fs::read(key).map_err(|e| format!("Error reading client key '{}': {}", key, e))?);
+            }
+            builder = builder.identity(Identity::from_pem(&pem)?);
+        }
+        Ok(builder)
+    }
+
+    /// TLS config that only trusts the certificate with the pinned fingerprint
+    fn pinned_config(&self, pin: &str) -> Result<rustls::ClientConfig, Box<dyn Error>> {
+        let provider = Arc::new(crypto::aws_lc_rs::default_provider());
+        let verifier = PinnedCertVerifier {
+            fingerprint: normalize_fingerprint(pin),
+            provider: provider.clone(),
+        };
+        let builder = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier));
+        let mut config = match &self.client_cert {
+            Some(cert) => {
+                let certs = CertificateDer::pem_file_iter(cert)
+                    .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                    .map_err(|e| format!("Error reading client certificate '{}': {}", cert, e))?;
+                let key_file = self.client_key.as_ref().unwrap_or(cert);
+                let key = PrivateKeyDer::from_pem_file(key_file)
+                    .map_err(|e| format!("Error reading client key '{}': {}", key_file, e))?;
+                builder.with_client_auth_cert(certs, key)?
+            }
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(config)
+    }
+
+    /// Applies the options to the HTTP source element GStreamer uses for streaming
+    pub fn configure_source(&self, source: &gstreamer::Element) {
+        if source.find_property("tls-interaction").is_none() {
+            return; // Not souphttpsrc (e.g. a local file)
+        }
+        if let Some(ca_file) = &self.ca_file {
+            match gio::TlsFileDatabase::new(ca_file) {
+                Ok(db) => source.set_property("tls-database", db.upcast_ref::<gio::TlsDatabase>()),
+                Err(e) => warn!("Error loading CA file '{}' for streaming: {}", ca_file, e),
+            }
+        }
+        if let Some(pin) = &self.pinned_fingerprint {
+            source.set_property("tls-database", PinnedDatabase::new(pin).upcast::<gio::TlsDatabase>());
+        }
+        if let Some(cert) = &self.client_cert {
+            let result = match &self.client_key {
+                Some(key) => gio::TlsCertificate::from_files(cert, key),
+                None => gio::TlsCertificate::from_file(cert),
+            };
+            match result {
+                Ok(certificate) => source.set_property(
+                    "tls-interaction",
+                    ClientCertInteraction::new(certificate).upcast::<gio::TlsInteraction>()
+                ),
+                Err(e) => warn!("Error loading client certificate '{}' for streaming: {}", cert, e),
+            }
+        }
+    }
+}
+
+/// Connects to the server ignoring certificate errors and returns the fingerprint of the presented
+/// certificate, but only if a connection with normal verification fails.
+pub async fn probe_untrusted_certificate(host: &str, options: &TlsOptions) -> Option<String> {
+    if !host.starts_with("https://") {
+        return None;
+    }
+    let strict = options.apply(ClientBuilder::new()).ok()?.build().ok()?;
+    match strict.get(host).send().await {
+        Ok(_) => return None,
+        Err(e) if !e.is_connect() => return None,
+        Err(_) => {}
+    }
+    let lenient = options.apply(ClientBuilder::new().tls_danger_accept_invalid_certs(true)).ok()?.build().ok()?;
+    let response = lenient.get(host).send().await.ok()?;
+    response.extensions()
+        .get::<TlsInfo>()
+        .and_then(|info| info.peer_certificate())
+        .map(fingerprint)
+}
+
+/// Trusts exactly the certificate with the pinned fingerprint. The handshake signature is still
+/// checked, so the server has to actually own that certificate.
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    /// Normalized SHA-256 fingerprint
+    fingerprint: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if normalize_fingerprint(&fingerprint(end_entity)) == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+glib::wrapper! {
+    /// Certificate database for streaming that only trusts the certificate with the pinned fingerprint
+    pub struct PinnedDatabase(ObjectSubclass<imp::PinnedDatabase>)
+        @extends gio::TlsDatabase;
+}
+
+impl PinnedDatabase {
+    pub fn new(pin: &str) -> Self {
+        let obj: Self = glib::Object::new();
+        obj.imp().fingerprint.lock().unwrap().replace(normalize_fingerprint(pin));
+        obj
+    }
+}
+
+// Like GTlsInteraction, GTlsDatabase can't be subclassed through gio-rs
+unsafe impl IsSubclassable<imp::PinnedDatabase> for gio::TlsDatabase {
+    fn class_init(class: &mut glib::Class<Self>) {
+        Self::parent_class_init::<imp::PinnedDatabase>(class);
+        let klass = class.as_mut();
+        klass.verify_chain = Some(imp::verify_chain);
+    }
+}
+
+glib::wrapper! {
+    /// Presents the configured client certificate when the server asks for one
+    pub struct ClientCertInteraction(ObjectSubclass<imp::ClientCertInteraction>)
+        @extends gio::TlsInteraction;
+}
+
+impl ClientCertInteraction {
+    pub fn new(certificate: gio::TlsCertificate) -> Self {
+        let obj: Self = glib::Object::new();
+        obj.imp().certificate.lock().unwrap().replace(certificate);
+        obj
+    }
+}
+
+// gio-rs has no subclassing support for GTlsInteraction, so the vfunc is set up manually
+unsafe impl IsSubclassable<imp::ClientCertInteraction> for gio::TlsInteraction {
+    fn class_init(class: &mut glib::Class<Self>) {
+        Self::parent_class_init::<imp::ClientCertInteraction>(class);
+        let klass = class.as_mut();
+        klass.request_certificate = Some(imp::request_certificate);
+    }
+}
+
+mod imp {
+    use std::ffi::c_char;
+    use std::sync::Mutex;
+    use relm4::gtk::gio;
+    use relm4::gtk::glib;
+    use relm4::gtk::glib::prelude::*;
+    use relm4::gtk::glib::subclass::prelude::*;
+    use relm4::gtk::glib::translate::{from_glib_none, ToGlibPtr};
+
+    #[derive(Default)]
+    pub struct PinnedDatabase {
+        pub fingerprint: Mutex<Option<String>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for PinnedDatabase {
+        const NAME: &'static str = "SanicPinnedDatabase";
+        type Type = super::PinnedDatabase;
+        type ParentType = gio::TlsDatabase;
+    }
+
+    impl ObjectImpl for PinnedDatabase {}
+
+    pub unsafe extern "C" fn verify_chain(
+        database: *mut gio::ffi::GTlsDatabase,
+        chain: *mut gio::ffi::GTlsCertificate,
+        _purpose: *const c_char,
+        _identity: *mut gio::ffi::GSocketConnectable,
+        _interaction: *mut gio::ffi::GTlsInteraction,
+        _flags: gio::ffi::GTlsDatabaseVerifyFlags,
+        _cancellable: *mut gio::ffi::GCancellable,
+        _error: *mut *mut glib::ffi::GError,
+    ) -> gio::ffi::GTlsCertificateFlags {
+        unsafe {
+            let instance = &*(database as *mut <PinnedDatabase as ObjectSubclass>::Instance);
+            let certificate: gio::TlsCertificate = from_glib_none(chain);
+            let der = certificate.property::<glib::ByteArray>("certificate");
+            let trusted = instance.imp().fingerprint.lock().unwrap()
+                .as_ref()
+                .is_some_and(|pin| super::normalize_fingerprint(&super::fingerprint(&der)) == *pin);
+            if trusted { 0 } else { gio::ffi::G_TLS_CERTIFICATE_UNKNOWN_CA }
+        }
+    }
+
+    #[derive(Default)]
+    pub struct ClientCertInteraction {
+        pub certificate: Mutex<Option<gio::TlsCertificate>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for ClientCertInteraction {
+        const NAME: &'static str = "SanicClientCertInteraction";
+        type Type = super::ClientCertInteraction;
+        type ParentType = gio::TlsInteraction;
+    }
+
+    impl ObjectImpl for ClientCertInteraction {}
+
+    pub unsafe extern "C" fn request_certificate(
+        interaction: *mut gio::ffi::GTlsInteraction,
+        connection: *mut gio::ffi::GTlsConnection,
+        _flags: gio::ffi::GTlsCertificateRequestFlags,
+        _cancellable: *mut gio::ffi::GCancellable,
+        _error: *mut *mut glib::ffi::GError,
+    ) -> gio::ffi::GTlsInteractionResult {
+        unsafe {
+            let instance = &*(interaction as *mut <ClientCertInteraction as ObjectSubclass>::Instance);
+            match instance.imp().certificate.lock().unwrap().as_ref() {
+                Some(certificate) => {
+                    gio::ffi::g_tls_connection_set_certificate(connection, certificate.to_glib_none().0);
+                    gio::ffi::G_TLS_INTERACTION_HANDLED
+                }
+                None => gio::ffi::G_TLS_INTERACTION_UNHANDLED,
+            }
+        }
+    }
+}
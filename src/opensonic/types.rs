@@ -45,7 +45,7 @@ pub struct Songs {
     pub song: Vec<Song>
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum AlbumListType {
     Random,
     Newest,
@@ -315,6 +315,23 @@ pub struct Search3Results {
     pub song: Option<Vec<Song>>,
 }
 
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Playlist {
+    pub id: String,
+    pub name: String,
+    pub comment: Option<String>,
+    pub owner: Option<String>,
+    pub public: Option<bool>,
+    pub song_count: u32,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub duration: Duration,
+    pub created: String,
+    pub changed: String,
+    pub cover_art: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SubsonicError {
     pub code: i32,
@@ -1,22 +1,30 @@
 use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use crate::crossfade;
+use crate::history::{self, HistoryEntry};
 use crate::opensonic::cache::SongCache;
 use crate::opensonic::client::{OpenSubsonicClient};
 use crate::opensonic::types::{InvalidResponseError, Song};
+use crate::queue_state::{SavedEntry, SavedQueue};
+use crate::listenbrainz::{Endpoint, Listen, ListenBrainz};
+use crate::mixer::Mixer;
+use crate::scrobbler::{PendingScrobble, Scrobbler};
+use crate::shuffle::{self, ShuffleMode};
+use crate::ui::song_object::PositionState;
 use crate::ui::track_list::MoveDirection;
-use crate::PlayerCommand;
+use crate::{logging, PlayerCommand};
 use async_channel::Sender;
 use mpris_server::{LoopStatus, TrackId};
-use rand::prelude::SliceRandom;
 use rand::Rng;
 use relm4::gtk::gio::prelude::SettingsExt;
 use relm4::gtk::gio::Settings;
 use std::error::Error;
 use std::rc::Rc;
 use std::sync::Arc;
-use std::time::Duration;
-use gstreamer::glib::clone;
-use gstreamer::prelude::{Cast, ElementExt, ElementExtManual, GstBinExt, ObjectExt, PadExt};
+use std::time::{Duration, Instant};
+use gstreamer::prelude::{Cast, ElementExt, GstBinExt, ObjectExt, PadExt};
 use gstreamer_play::PlayState;
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
 pub const MAX_PLAYBACK_RATE: f64 = 2.0;
@@ -36,16 +44,31 @@ pub struct PlayerSettings {
     pub replay_gain_mode: ReplayGainMode,
     pub volume: f64,
     pub should_scrobble: bool,
+    /// Percentage of a song that has to be played before it's scrobbled
+    pub scrobble_percent: u32,
+    /// Songs are scrobbled after playing this long, even if that's less than `scrobble_percent`
+    pub scrobble_after: Duration,
     pub continuous_play: bool,
+    pub shuffle_mode: ShuffleMode,
+    /// How long songs fade into each other, zero for no crossfade
+    pub crossfade: Duration,
 }
 
 impl Default for PlayerSettings {
     fn default() -> Self {
-        Self { replay_gain_mode: Default::default(), volume: 1.0, should_scrobble: true, continuous_play: false }
+        Self { replay_gain_mode: Default::default(), volume: 1.0, should_scrobble: true, scrobble_percent: 50, scrobble_after: Duration::from_secs(240), continuous_play: false, shuffle_mode: Default::default(), crossfade: Duration::ZERO }
     }
 }
 
 impl PlayerSettings {
+    /// How long a song has to play before it's scrobbled
+    pub fn scrobble_threshold(&self, duration: Option<Duration>) -> Duration {
+        match duration {
+            Some(duration) => (duration * self.scrobble_percent / 100).min(self.scrobble_after),
+            None => self.scrobble_after,
+        }
+    }
+
     pub fn load_settings(&mut self, settings: &Settings) -> Result<(), Box<dyn Error>>{
         let mode: u8 = settings.value("replay-gain-mode").try_get()?;
         self.replay_gain_mode = match mode {
@@ -53,27 +76,92 @@ impl PlayerSettings {
             1 => ReplayGainMode::Track,
             2 => ReplayGainMode::Album,
             v => {
-                eprintln!("Unknown replay-gain-mode setting: {}", v);
+                warn!("Unknown replay-gain-mode setting: {}", v);
                 ReplayGainMode::None
             }
         };
         self.volume = settings.value("volume").try_get()?;
         self.should_scrobble = settings.boolean("should-scrobble");
+        self.scrobble_percent = settings.uint("scrobble-percent").clamp(1, 100);
+        self.scrobble_after = Duration::from_secs(settings.uint("scrobble-after-seconds") as u64);
         self.continuous_play = settings.boolean("continuous-play");
+        self.shuffle_mode = settings.value("shuffle-mode").try_get::<u8>()?.into();
+        self.crossfade = Duration::from_secs(settings.uint("crossfade-seconds") as u64).min(crossfade::MAX_CROSSFADE);
 
         Ok(())
     }
 }
 
+/// Songs that stop this close to their end count as played through rather than skipped
+const SKIP_MARGIN: Duration = Duration::from_secs(3);
+
+/// The song that's loaded, for the listening history
+struct NowPlaying {
+    song: Rc<Song>,
+    /// Unix time it first started playing, `None` while it hasn't played yet
+    started: Option<u64>,
+    played: Duration,
+    /// When playback last resumed, while it's playing
+    resumed: Option<Instant>,
+    scrobbled: bool,
+    /// Set when it played through to the start of the next song
+    ended: bool,
+}
+
+impl NowPlaying {
+    fn played(&self) -> Duration {
+        self.played + self.resumed.map(|r| r.elapsed()).unwrap_or_default()
+    }
+}
+
+/// Applies the client's TLS and HTTP options to the sources `uridecodebin` creates for streaming
+pub fn configure_source_setup(client: &OpenSubsonicClient, decodebin: &gstreamer::Element) {
+    let tls = client.tls_options().clone();
+    let http = client.http_options().clone();
+    decodebin.connect("source-setup", false, move |args| {
+        if let Ok(source) = args[1].get::<gstreamer::Element>() {
+            tls.configure_source(&source);
+            http.configure_source(&source);
+        }
+        None
+    });
+}
+
+/// Replay gain filter bin for a song's decoding branch, with its rgvolume element
+pub fn replay_gain_bin() -> Result<(gstreamer::Element, gstreamer::Element), Box<dyn Error>> {
+    let rg_volume = gstreamer::ElementFactory::make_with_name("rgvolume", Some("rg volume"))?;
+    let rg_limiter = gstreamer::ElementFactory::make_with_name("rglimiter", Some("rg limiter"))?;
+
+    let filter_bin = gstreamer::Bin::builder().name("filter bin").build();
+    filter_bin.add(&rg_volume)?;
+    filter_bin.add(&rg_limiter)?;
+    rg_volume.link(&rg_limiter)?;
+
+    let pad_src = rg_limiter.static_pad("src").unwrap();
+    pad_src.set_active(true).unwrap();
+    let ghost_src = gstreamer::GhostPad::with_target(&pad_src)?;
+    filter_bin.add_pad(&ghost_src)?;
+
+    let pad_sink = rg_volume.static_pad("sink").unwrap();
+    pad_sink.set_active(true).unwrap();
+    let ghost_sink = gstreamer::GhostPad::with_target(&pad_sink)?;
+    filter_bin.add_pad(&ghost_sink)?;
+
+    Ok((filter_bin.upcast(), rg_volume))
+}
+
 pub struct PlayerInfo {
     client: &'static OpenSubsonicClient,
     // sink: Sink,
     track_list: RefCell<TrackList>,
 
-    gst_player: gstreamer_play::Play,
-    rg_filter_bin: gstreamer::Element,
-    rg_volume: gstreamer::Element,
+    mixer: Rc<Mixer>,
+    /// Entry a crossfade into the next song was last tried for, so it's only tried once
+    crossfaded: Cell<Option<Uuid>>,
     play_state: Cell<PlayState>,
+    now_playing: RefCell<Option<NowPlaying>>,
+    scrobbler: Scrobbler,
+    listenbrainz: Rc<ListenBrainz>,
 
     pub settings: RefCell<PlayerSettings>,
 }
@@ -84,114 +172,42 @@ impl PlayerInfo {
         track_list: TrackList,
         cmd_channel: Arc<Sender<PlayerCommand>>,
     ) -> Result<Self, Box<dyn Error>> {
-        // GStreamer initialization code adapted from Amberol (https://gitlab.gnome.org/World/amberol/-/blob/main/src/audio/gst_backend.rs)
         gstreamer::init()?;
 
-        let gst_player = gstreamer_play::Play::default();
-        gst_player.set_video_track_enabled(false);
-
-        let mut config = gst_player.config();
-        config.set_position_update_interval(250);
-        gst_player.set_config(config).unwrap();
-
-        gst_player.message_bus().set_sync_handler(clone!(
-            move |_bus, msg| {
-                let Ok(play_msg) = gstreamer_play::PlayMessage::parse(&msg) else {
-                    return gstreamer::BusSyncReply::Drop;
-                };
-
-                match play_msg {
-                    gstreamer_play::PlayMessage::Error(error) => {
-                        let err_str = format!("{:?}", error);
-                        eprintln!("GStreamer error: {}", err_str);
-                        if let Err(_) = cmd_channel.send_blocking(PlayerCommand::Error("Error from GStreamer".to_string(), err_str)) {
-                            eprintln!("Error sending error string to main");
-                        }
-                    }
-                    gstreamer_play::PlayMessage::Warning(warning) => {
-                        eprintln!("GStreamer warning: {:?}", warning);
-                    }
-                    gstreamer_play::PlayMessage::EndOfStream(_) => {
-                        if let Err(e) = cmd_channel.send_blocking(PlayerCommand::TrackOver) {
-                            eprintln!("Failed to send TrackOver: {e}");
-                        }
-                    }
-                    gstreamer_play::PlayMessage::PositionUpdated(pos) => {
-                        if let Some(position) = pos.position() {
-                            if let Err(e) = cmd_channel.send_blocking(PlayerCommand::PositionUpdate(position.seconds_f64())) {
-                                eprintln!("Failed to send PositionUpdate: {e}");
-                            }
-                        }
-                    },
-                    gstreamer_play::PlayMessage::SeekDone(sd) => {
-                        if let Some(position) = sd.position() {
-                            if let Err(e) = cmd_channel.send_blocking(PlayerCommand::PositionUpdate(position.seconds_f64())) {
-                                eprintln!("Failed to send PositionUpdate: {e}");
-                            }
-                        }
-                    }
-                    gstreamer_play::PlayMessage::StateChanged(state) => {
-                        if let Err(e) = cmd_channel.send_blocking(PlayerCommand::PlayStateUpdate(state.state())) {
-                            eprintln!("Failed to send PlayStateUpdate: {e}");
-                        }
-                    }
-                    _ => {}
-                }
-
-                gstreamer::BusSyncReply::Drop
-            }
-        ));
-
-        let rg_volume = gstreamer::ElementFactory::make_with_name("rgvolume", Some("rg volume"))?;
-        let rg_limiter = gstreamer::ElementFactory::make_with_name("rglimiter", Some("rg limiter"))?;
-
-        let filter_bin = gstreamer::Bin::builder().name("filter bin").build();
-        filter_bin.add(&rg_volume)?;
-        filter_bin.add(&rg_limiter)?;
-        rg_volume.link(&rg_limiter)?;
-
-        let pad_src = rg_limiter.static_pad("src").unwrap();
-        pad_src.set_active(true).unwrap();
-        let ghost_src = gstreamer::GhostPad::with_target(&pad_src)?;
-        filter_bin.add_pad(&ghost_src)?;
-
-        let pad_sink = rg_volume.static_pad("sink").unwrap();
-        pad_sink.set_active(true).unwrap();
-        let ghost_sink = gstreamer::GhostPad::with_target(&pad_sink)?;
-        filter_bin.add_pad(&ghost_sink)?;
-
+        let mixer = Mixer::new(client, cmd_channel)?;
 
         Ok(PlayerInfo {
             client,
             track_list: RefCell::new(track_list),
-            gst_player,
-            rg_filter_bin: filter_bin.upcast(),
-            rg_volume,
+            mixer,
+            crossfaded: Cell::new(None),
             play_state: Cell::new(PlayState::Stopped),
+            now_playing: RefCell::new(None),
+            scrobbler: Scrobbler::new(client),
+            listenbrainz: Rc::new(ListenBrainz::new()),
             settings: RefCell::default()
         })
     }
 
     pub fn set_playstate(&self, new_state: PlayState) {
         self.play_state.set(new_state);
-        if new_state == PlayState::Playing {
-            // For some reason gstreamer seems to randomly forget these settings when playing new songs
-            self.set_set_volume();
-            self.load_rg_from_settings();
+        if let Some(now_playing) = self.now_playing.borrow_mut().as_mut() {
+            if new_state == PlayState::Playing {
+                now_playing.started.get_or_insert_with(history::unix_now);
+                now_playing.resumed.get_or_insert_with(Instant::now);
+            } else if let Some(resumed) = now_playing.resumed.take() {
+                now_playing.played += resumed.elapsed();
+            }
         }
     }
 
     pub fn load_rg_from_settings(&self) {
-        let identity = gstreamer::ElementFactory::make_with_name("identity", None).unwrap();
-
-        let (filter, album_mode) = match self.settings.borrow().replay_gain_mode {
-            ReplayGainMode::Album => (self.rg_filter_bin.as_ref(), true),
-            ReplayGainMode::Track => (self.rg_filter_bin.as_ref(), false),
-            ReplayGainMode::None => (&identity, true),
+        let album_mode = match self.settings.borrow().replay_gain_mode {
+            ReplayGainMode::Album => Some(true),
+            ReplayGainMode::Track => Some(false),
+            ReplayGainMode::None => None,
         };
-
-        self.rg_volume.set_property("album-mode", album_mode);
-        self.gst_player.pipeline().set_property("audio-filter", filter);
+        self.mixer.set_replay_gain(album_mode);
     }
 
 
@@ -203,7 +219,9 @@ impl PlayerInfo {
         {
             let mut s = self.settings.borrow_mut();
             s.load_settings(settings)?;
+            self.track_list.borrow_mut().set_shuffle_mode(s.shuffle_mode);
         }
+        self.listenbrainz.set_endpoint(Endpoint::from_settings(settings));
         self.set_set_volume();
         self.load_rg_from_settings();
         Ok(())
@@ -218,7 +236,16 @@ impl PlayerInfo {
     }
 
     pub async fn goto(&self, index: usize) -> Result<Option<SongEntry>, Box<dyn Error>> {
-        self.track_list.borrow_mut().set_current(index);
+        if !self.track_list.borrow_mut().set_current(index) {
+            return Err("No song at index".into());
+        }
+        self.start_current().await
+    }
+
+    pub async fn goto_up_next(&self, index: usize) -> Result<Option<SongEntry>, Box<dyn Error>> {
+        if !self.track_list.borrow_mut().play_up_next(index) {
+            return Err("No up next song at index".into());
+        }
         self.start_current().await
     }
 
@@ -236,49 +263,188 @@ impl PlayerInfo {
     pub async fn play(&self) {
         match self.play_state.get() {
             PlayState::Stopped => {
-                self.start_current().await.expect("Error playing");
+                self.start_or_stop().await;
             }
             PlayState::Paused => {
-                self.gst_player.play();
+                self.mixer.play();
             }
             _ => {}
         }
     }
 
     pub fn pause(&self) {
-        self.gst_player.pause();
+        self.mixer.pause();
     }
 
     pub async fn playpause(&self) {
         match self.play_state.get() {
             PlayState::Stopped => {
-                self.start_current().await.expect("Error playing");
+                self.start_or_stop().await;
             }
             PlayState::Paused => {
-                self.gst_player.play();
+                self.mixer.play();
             }
             _ => {
-                self.gst_player.pause();
+                self.mixer.pause();
             }
         }
     }
 
+    fn stream_url(&self, song: &Song) -> String {
+        self.client.stream_get_url(&song.id, None, None, None, None, Some(true), None)
+    }
+
+    /// Starts keeping track of a newly loaded song for the history and scrobbling.
+    /// `playing` is for songs that are already playing, others start counting once they do.
+    fn begin_song(&self, song: &Rc<Song>, playing: bool) {
+        self.record_history();
+        self.crossfaded.set(None);
+        self.now_playing.replace(Some(NowPlaying {
+            song: song.clone(),
+            started: playing.then(history::unix_now),
+            played: Duration::ZERO,
+            resumed: playing.then(Instant::now),
+            scrobbled: false,
+            ended: false,
+        }));
+    }
+
+    fn set_stream_uri(&self, song: &Rc<Song>) -> Result<(), Box<dyn Error>> {
+        self.begin_song(song, false);
+        let uri = self.stream_url(song);
+        debug!(uri = %logging::redact(&uri), "Stream URL");
+        self.mixer.set_uri(&uri)
+    }
+
+    /// Lines up the song after the current one, for the mixer to switch to without a gap or to crossfade into.
+    /// Called regularly while playing, so it follows queue edits.
+    pub fn prepare_next(&self) {
+        let next = self.track_list.borrow().peek_next().map(|e| (e.uuid, e.song.clone()));
+        if self.mixer.lined_up() == next.as_ref().map(|(uuid, _)| *uuid) {
+            return;
+        }
+        self.mixer.line_up(next.map(|(uuid, song)| (uuid, self.stream_url(&song))));
+    }
+
+    async fn notify_playing(&self, song: &Rc<Song>) -> Result<(), Box<dyn Error>> {
+        if self.listenbrainz.is_enabled() {
+            let listenbrainz = self.listenbrainz.clone();
+            let song = song.clone();
+            relm4::spawn_local(async move { listenbrainz.playing_now(&song).await });
+        }
+        if self.settings.borrow().should_scrobble {
+            self.client.scrobble(song.id.as_str(), Some(false), None).await?;
+        }
+        Ok(())
+    }
+
     pub async fn start_current(&self) -> Result<Option<SongEntry>, Box<dyn Error>> {
-        let track_list = self.track_list.borrow();
-        let song = match track_list.current() {
+        let song = match self.track_list.borrow().current() {
             None => {return Ok(None)}
-            Some(s) => s
+            Some(s) => s.clone()
         };
 
-        println!("Playing: {}", song.song.title);
-        self.gst_player.set_uri(Some(&self.client.stream_get_url(&song.song.id, None, None, None, None, Some(true), None)));
-        self.gst_player.play();
+        info!(id = %song.song.id, title = %song.song.title, "Playing");
+        self.set_stream_uri(&song.song)?;
+        self.mixer.play();
+        self.prepare_next();
+        self.notify_playing(&song.song).await?;
 
-        if self.settings.borrow().should_scrobble {
-            self.client.scrobble(song.song.id.as_str(), Some(false)).await?;
+        Ok(Some(song))
+    }
+
+    /// `start_current` for callers with nowhere to send the error, which stop instead
+    async fn start_or_stop(&self) -> Option<SongEntry> {
+        match self.start_current().await {
+            Ok(song) => song,
+            Err(err) => {
+                error!(%err, "Couldn't start the current song");
+                self.stop_playback();
+                None
+            }
         }
+    }
 
-        Ok(Some(song.clone()))
+    /// Moves the queue along after the mixer switched to the lined up song by itself.
+    /// If the queue changed so that song isn't next anymore, the right one is started instead.
+    pub async fn advance_gapless(&self, uuid: Uuid) -> Option<SongEntry> {
+        if let Some(now_playing) = self.now_playing.borrow_mut().as_mut() {
+            now_playing.ended = true;
+        }
+        let over = self.track_list.borrow_mut().next();
+        let current = self.track_list.borrow().current().cloned();
+        match current {
+            Some(song) if !over && song.uuid == uuid => {
+                info!(id = %song.song.id, title = %song.song.title, "Playing without a gap");
+                self.begin_song(&song.song, self.play_state.get() == PlayState::Playing);
+                self.prepare_next();
+                if let Err(err) = self.notify_playing(&song.song).await {
+                    warn!(%err, "Couldn't report now playing");
+                }
+                Some(song)
+            }
+            _ if over => {
+                self.stop_playback();
+                None
+            }
+            _ => self.start_or_stop().await,
+        }
+    }
+
+    /// Whether it's time to start crossfading into the next song
+    pub fn crossfade_due(&self) -> bool {
+        let fade = self.settings.borrow().crossfade;
+        if fade.is_zero() || self.play_state.get() != PlayState::Playing {
+            return false;
+        }
+        let track_list = self.track_list.borrow();
+        let (Some(current), Some(next)) = (track_list.current(), track_list.peek_next()) else {
+            return false;
+        };
+        let Some(duration) = current.song.duration else {
+            return false;
+        };
+        let position = Duration::from_micros(self.position() as u64);
+        self.crossfaded.get() != Some(current.uuid)
+            && duration.saturating_sub(position) <= fade + crossfade::PREPARE_LEAD
+            && crossfade::should_crossfade(&current.song, &next.song)
+    }
+
+    /// Starts crossfading into the next song. It's mixed in over the end of the current one,
+    /// the queue moves on through `advance_gapless` once the fade starts.
+    pub async fn crossfade(&self) {
+        let Some(current) = self.track_list.borrow().current().cloned() else {
+            return;
+        };
+        self.crossfaded.set(Some(current.uuid));
+        let Some(duration) = current.song.duration else {
+            return;
+        };
+        let position = Duration::from_micros(self.position() as u64);
+        let fade = self.settings.borrow().crossfade.min(duration.saturating_sub(position));
+        if fade < crossfade::MIN_CROSSFADE {
+            return;
+        }
+        info!(id = %current.song.id, ?fade, "Crossfading");
+        if let Err(err) = self.mixer.crossfade(fade).await {
+            warn!(%err, "Couldn't crossfade, playing on without");
+        }
+    }
+
+    /// Loads the current song paused at `position`, without scrobbling it
+    pub fn load_current_paused(&self, position: Duration) -> Option<SongEntry> {
+        let track_list = self.track_list.borrow();
+        let song = track_list.current()?;
+        info!(id = %song.song.id, title = %song.song.title, "Loading paused");
+        if let Err(err) = self.set_stream_uri(&song.song) {
+            error!(%err, "Couldn't load the current song");
+            return None;
+        }
+        self.mixer.pause();
+        if !position.is_zero() {
+            self.mixer.seek(gstreamer::ClockTime::from_mseconds(position.as_millis() as u64));
+        }
+        Some(song.clone())
     }
 
     pub async fn next(&self) -> Option<SongEntry> {
@@ -290,9 +456,7 @@ impl PlayerInfo {
         if over {
             None
         } else {
-            self.start_current()
-                .await
-                .expect("Error starting next track")
+            self.start_or_stop().await
         }
     }
 
@@ -301,13 +465,77 @@ impl PlayerInfo {
             let mut track_list = self.track_list.borrow_mut();
             track_list.previous();
         }
-        self.start_current()
-            .await
-            .expect("Error starting next track")
+        self.start_or_stop().await
+    }
+
+    /// Finishes the history entry of the loaded song, `None` if it never played
+    pub fn take_history_entry(&self) -> Option<HistoryEntry> {
+        let now_playing = self.now_playing.take()?;
+        let started = now_playing.started?;
+        let played = now_playing.played();
+        let position = self.mixer.position()
+            .map(|p| Duration::from_nanos(p.nseconds()))
+            .unwrap_or(played);
+        let skipped = !now_playing.ended && now_playing.song.duration.is_some_and(|d| position + SKIP_MARGIN < d);
+        Some(HistoryEntry::new(&now_playing.song, started, played, skipped))
+    }
+
+    pub fn scrobbler(&self) -> &Scrobbler {
+        &self.scrobbler
+    }
+
+    pub fn listenbrainz(&self) -> &ListenBrainz {
+        &self.listenbrainz
+    }
+
+    /// The loaded song and when it started, once it has played long enough to be scrobbled.
+    /// Only given once per song.
+    pub fn due_scrobble(&self) -> Option<(Rc<Song>, u64)> {
+        let settings = self.settings.borrow();
+        if !settings.should_scrobble && !self.listenbrainz.is_enabled() {
+            return None;
+        }
+        let mut now_playing = self.now_playing.borrow_mut();
+        let now_playing = now_playing.as_mut()?;
+        let started = now_playing.started?;
+        if now_playing.scrobbled || now_playing.played() < settings.scrobble_threshold(now_playing.song.duration) {
+            return None;
+        }
+        now_playing.scrobbled = true;
+        Some((now_playing.song.clone(), started))
+    }
+
+    /// Sends a play to the server and to ListenBrainz, whichever are turned on.
+    /// Plays that can't be sent yet are kept, only a refused ListenBrainz token is an error.
+    pub async fn submit_scrobble(&self, song: &Song, started: u64) -> Result<(), Box<dyn Error>> {
+        let to_server = self.settings.borrow().should_scrobble;
+        let server = async {
+            if to_server {
+                self.scrobbler.submit(PendingScrobble { id: song.id.clone(), time: started * 1000, attempts: 0 }).await;
+            }
+        };
+        let ((), listenbrainz) = futures_util::join!(server, self.listenbrainz.submit(Listen::new(song, Some(started))));
+        listenbrainz
+    }
+
+    fn record_history(&self) {
+        if let Some(entry) = self.take_history_entry() {
+            relm4::spawn_local(async move {
+                if let Err(err) = entry.append().await {
+                    warn!(%err, "Couldn't save to the listening history");
+                }
+            });
+        }
+    }
+
+    /// Stops playback, leaving the queue as it is
+    pub fn stop_playback(&self) {
+        self.record_history();
+        self.mixer.stop();
     }
 
     pub fn stop(&self) {
-        self.gst_player.stop();
+        self.stop_playback();
         let mut track_list = self.track_list.borrow_mut();
         track_list.clear();
     }
@@ -324,13 +552,15 @@ impl PlayerInfo {
                 return Ok(());
             }
         }
-        self.gst_player.seek(gstreamer::ClockTime::from_seconds_f64(position.as_secs_f64()));
+        // The mixer drops a fade that was going on, it's tried again from the new position
+        self.crossfaded.set(None);
+        self.mixer.seek(gstreamer::ClockTime::from_seconds_f64(position.as_secs_f64()));
         Ok(())
     }
 
     fn set_set_volume(&self) {
         let v = self.settings.borrow().volume;
-        self.gst_player.set_volume(v);
+        self.mixer.set_volume(v);
     }
 
     pub fn volume(&self) -> f64 {
@@ -342,7 +572,7 @@ impl PlayerInfo {
             let mut settings = self.settings.borrow_mut();
             settings.volume = volume;
         }
-        self.gst_player.set_volume(volume);
+        self.mixer.set_volume(volume);
     }
 
     pub fn playback_status(&self) -> PlayState {
@@ -350,7 +580,7 @@ impl PlayerInfo {
     }
 
     pub fn rate(&self) -> f64 {
-        self.gst_player.rate()
+        self.mixer.rate()
     }
 
     pub fn set_rate(&self, rate: f64) {
@@ -364,12 +594,12 @@ impl PlayerInfo {
         if rate == 0.0 {
             self.pause();
         } else {
-            self.gst_player.set_rate(rate);
+            self.mixer.set_rate(rate);
         }
     }
 
     pub fn position(&self) -> i64 {
-        self.gst_player.position().and_then(|c| Some(c.useconds() as i64)).unwrap_or(0)
+        self.mixer.position().and_then(|c| Some(c.useconds() as i64)).unwrap_or(0)
     }
 
     pub fn shuffled(&self) -> bool {
@@ -406,23 +636,63 @@ impl From<(Uuid, Rc<Song>)> for SongEntry {
     }
 }
 
+/// Queue edits that can be undone
+const MAX_UNDO_STEPS: usize = 50;
+
+/// Saved state of the queue for undo and redo
+#[derive(Clone)]
+struct Snapshot {
+    songs: Vec<SongEntry>,
+    order: Vec<Uuid>,
+    current: usize,
+    up_next: VecDeque<SongEntry>,
+    playing_up_next: Option<SongEntry>,
+    main_pending: bool,
+    shuffled: bool,
+    loop_status: LoopStatus,
+}
+
+/// State of the queue before an edit, see `TrackList::begin_edit`
+pub struct Checkpoint(Snapshot);
+
+/// The play queue. Entries are tracked by their uuid, so the current song and the
+/// shuffled order stay valid while songs are inserted, moved and removed.
 pub struct TrackList {
+    /// Entries in queue order, as shown in the queue sidebar
     songs: Vec<SongEntry>,
+    /// Entry uuids in playback order, the same as `songs` unless shuffled
+    order: Vec<Uuid>,
+    /// Position in `order` of the current song, `order.len()` once the end has been reached
     current: usize,
-    shuffled_order: Vec<usize>,
+    /// Songs played before continuing with `songs`, consumed as they're played
+    up_next: VecDeque<SongEntry>,
+    /// Entry taken from `up_next` that is playing, `current` then points at the last played song of `songs`
+    playing_up_next: Option<SongEntry>,
+    /// Set when up next started with nothing playing, so the song at `current` hasn't been played yet
+    main_pending: bool,
 
     shuffled: bool,
+    shuffle_mode: ShuffleMode,
     pub loop_status: LoopStatus,
+
+    undo_stack: Vec<Snapshot>,
+    redo_stack: Vec<Snapshot>,
 }
 
 impl TrackList {
     pub fn new() -> Self {
         TrackList{
             songs: Vec::new(),
+            order: Vec::new(),
             current: 0,
             shuffled: false,
+            shuffle_mode: ShuffleMode::Random,
             loop_status: LoopStatus::None,
-            shuffled_order: Vec::new(),
+            up_next: VecDeque::new(),
+            playing_up_next: None,
+            main_pending: false,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
         }
     }
 
@@ -430,21 +700,54 @@ impl TrackList {
         self.shuffled
     }
 
+    /// Shuffling keeps the songs played so far and the current one, only the rest is reordered.
+    /// Unshuffling goes back to queue order, continuing from the current song.
     pub fn set_shuffle(&mut self, shuffle: bool) {
+        if shuffle == self.shuffled {
+            return;
+        }
+        let current = self.current_uuid();
         if shuffle {
-            let start = if self.current + 1 >= self.songs.len() {
-                0
-            } else {
-                self.current + 1
-            };
-            let mut shuffled_order: Vec<usize> = (start..self.songs.len()).collect();
-            shuffled_order.shuffle(&mut rand::rng());
-            self.shuffled_order = (0..start).collect();
-            self.shuffled_order.append(&mut shuffled_order);
+            self.shuffle_upcoming();
+        } else {
+            self.order = self.songs.iter().map(|e| e.uuid).collect();
         }
+        self.current = match current {
+            Some(uuid) => self.order_position(&uuid).unwrap_or(self.order.len()),
+            None => self.order.len(),
+        };
         self.shuffled = shuffle;
     }
 
+    pub fn shuffle_mode(&self) -> ShuffleMode {
+        self.shuffle_mode
+    }
+
+    /// Changes how shuffling is done, reshuffling the songs still to come if shuffled.
+    /// Returns whether the order changed.
+    pub fn set_shuffle_mode(&mut self, mode: ShuffleMode) -> bool {
+        if mode == self.shuffle_mode {
+            return false;
+        }
+        self.shuffle_mode = mode;
+        if self.shuffled {
+            self.shuffle_upcoming();
+        }
+        self.shuffled
+    }
+
+    /// Reorders the songs after the current one, or all of them if there's no current song
+    fn shuffle_upcoming(&mut self) {
+        let start = if self.current_uuid().is_some() { self.current + 1 } else { 0 };
+        let upcoming_uuids: HashSet<&Uuid> = self.order[start..].iter().collect();
+        let upcoming: Vec<&SongEntry> = self.songs.iter()
+            .filter(|e| upcoming_uuids.contains(&e.uuid))
+            .collect();
+        let shuffled = shuffle::shuffle(&upcoming, self.shuffle_mode, &mut rand::rng());
+        self.order.truncate(start);
+        self.order.extend(shuffled);
+    }
+
     pub async fn add_song_from_uri(&mut self, uri: &str, client: &SongCache, index: Option<usize>) -> Option<Box<dyn Error>> {
         if !uri.starts_with("sanic://song/"){
             return Some(InvalidResponseError::new_boxed("Invalid URI, should be sanic://song/<song-id>"));
@@ -463,43 +766,70 @@ impl TrackList {
         None
     }
 
-    pub fn set_current(&mut self, index: usize) {
-        if !self.shuffled {
-            self.current = index;
-        } else {
-            self.current = self.shuffled_order.iter().position(|i| *i == index).unwrap_or(index);
-        }
+    /// Makes the song at `index` in queue order the current one, returns false if there's no such song
+    pub fn set_current(&mut self, index: usize) -> bool {
+        let Some(position) = self.songs.get(index).and_then(|e| self.order_position(&e.uuid)) else {
+            return false;
+        };
+        self.playing_up_next = None;
+        self.main_pending = false;
+        self.current = position;
+        true
     }
 
+    /// Removes the song at `index` in queue order. Removing the current song makes the one after it current.
     pub fn remove_song(&mut self, index: usize) -> SongEntry {
-        if self.current > index && self.current != 0 {
-            self.current -= 1;
+        let entry = self.songs.remove(index);
+        if let Some(position) = self.order_position(&entry.uuid) {
+            self.order.remove(position);
+            if position < self.current {
+                self.current -= 1;
+            } else if position == self.current && self.playing_up_next.is_some() {
+                // The song played before up next is gone, the one now at `current` hasn't been played yet
+                self.main_pending = true;
+            }
         }
-        self.songs.remove(index)
+        entry
     }
 
     pub fn clear(&mut self) {
         self.songs.clear();
-        self.shuffled_order.clear();
+        self.order.clear();
+        self.up_next.clear();
+        self.playing_up_next = None;
+        self.main_pending = false;
         self.current = 0;
         self.shuffled = false;
         self.loop_status = LoopStatus::None;
     }
 
     pub fn empty(&self) -> bool {
-        self.songs.is_empty()
+        self.songs.is_empty() && self.up_next.is_empty() && self.playing_up_next.is_none()
     }
 
+    /// Advances to the next song, returns true when the end of the queue has been reached
     pub fn next(&mut self) -> bool {
+        if self.loop_status == LoopStatus::Track {
+            return false;
+        }
+        if let Some(entry) = self.up_next.pop_front() {
+            self.playing_up_next = Some(entry);
+            return false;
+        }
+        if self.playing_up_next.take().is_some() && std::mem::take(&mut self.main_pending) {
+            return self.current >= self.order.len();
+        }
+        if self.order.is_empty() {
+            return true;
+        }
         match self.loop_status {
             LoopStatus::None => {
-                self.current += 1;
-
-                self.current >= self.songs.len()
+                self.current = (self.current + 1).min(self.order.len());
+                self.current >= self.order.len()
             },
             LoopStatus::Track => false,
             LoopStatus::Playlist => {
-                if self.current != self.songs.len()-1 {
+                if self.current + 1 < self.order.len() {
                     self.current += 1;
                 } else {
                     self.current = 0;
@@ -509,100 +839,1038 @@ impl TrackList {
         }
     }
 
+    /// The entry `next` would move to, without moving. `None` if the queue would be over.
+    pub fn peek_next(&self) -> Option<&SongEntry> {
+        if self.loop_status == LoopStatus::Track {
+            return self.current();
+        }
+        if let Some(entry) = self.up_next.front() {
+            return Some(entry);
+        }
+        if self.playing_up_next.is_some() && self.main_pending {
+            return self.order.get(self.current).and_then(|uuid| self.entry(uuid));
+        }
+        let next = match self.loop_status {
+            LoopStatus::Playlist if self.current + 1 >= self.order.len() => 0,
+            _ => self.current + 1,
+        };
+        self.order.get(next).and_then(|uuid| self.entry(uuid))
+    }
+
     pub fn previous(&mut self) {
+        if self.playing_up_next.is_some() {
+            // Without a played song before it, the up next song just restarts
+            if !self.main_pending && let Some(entry) = self.playing_up_next.take() {
+                self.up_next.push_front(entry);
+            }
+            return;
+        }
         if self.current != 0 {
             self.current -= 1;
         }
     }
 
+    /// Song at `i` in queue order
     pub fn song_at_index(&self, i: usize) -> Option<&SongEntry> {
-        if self.shuffled {
-            self.songs.get(self.shuffled_order[i])
-        } else {
-            self.songs.get(i)
-        }
+        self.songs.get(i)
     }
 
     pub fn current(&self) -> Option<&SongEntry> {
-        self.song_at_index(self.current)
+        self.playing_up_next.as_ref().or_else(|| self.current_uuid().and_then(|uuid| self.entry(&uuid)))
     }
 
-    pub fn current_index(&self) -> Option<usize> {
-        if self.songs.len() > 0{
-            if self.shuffled {
-                Some(self.shuffled_order[self.current])
-            } else {
-                Some(self.current)
-            }
+    pub fn is_playing_up_next(&self) -> bool {
+        self.playing_up_next.is_some()
+    }
+
+    pub fn up_next(&self) -> &VecDeque<SongEntry> {
+        &self.up_next
+    }
+
+    pub fn playing_up_next(&self) -> Option<&SongEntry> {
+        self.playing_up_next.as_ref()
+    }
+
+    pub fn add_up_next(&mut self, songs: Vec<Rc<Song>>) {
+        self.up_next.extend(songs.into_iter().map(|song| SongEntry::from((Uuid::new_v4(), song))));
+    }
+
+    /// Starts playing the up next queue if nothing is playing, returns whether it did
+    pub fn start_up_next(&mut self) -> bool {
+        if self.current().is_some() {
+            return false;
+        }
+        match self.up_next.pop_front() {
+            Some(entry) => {
+                self.playing_up_next = Some(entry);
+                self.main_pending = true;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Jumps to an entry of the up next queue, the ones before it stay queued
+    pub fn play_up_next(&mut self, index: usize) -> bool {
+        let Some(entry) = self.up_next.remove(index) else {
+            return false;
+        };
+        if self.playing_up_next.is_none() && self.current().is_none() {
+            self.main_pending = true;
+        }
+        self.playing_up_next = Some(entry);
+        true
+    }
+
+    pub fn remove_up_next(&mut self, index: usize) -> Option<SongEntry> {
+        self.up_next.remove(index)
+    }
+
+    pub fn clear_up_next(&mut self) {
+        self.up_next.clear();
+    }
+
+    /// All entries in playback order: the songs played so far, the up next queue, then the rest
+    pub fn ordered_entries(&self) -> Vec<&SongEntry> {
+        let split = self.up_next_position();
+        let entries: HashMap<Uuid, &SongEntry> = self.songs.iter().map(|e| (e.uuid, e)).collect();
+        let main: Vec<&SongEntry> = self.order.iter().filter_map(|uuid| entries.get(uuid).copied()).collect();
+        main[..split].iter().copied()
+            .chain(self.playing_up_next.iter())
+            .chain(self.up_next.iter())
+            .chain(main[split..].iter().copied())
+            .collect()
+    }
+
+    /// Position in playback order the up next queue is played before
+    fn up_next_position(&self) -> usize {
+        if self.main_pending {
+            self.current.min(self.order.len())
         } else {
-            None
+            (self.current + 1).min(self.order.len())
         }
     }
 
+    /// Index in queue order of the current song
+    pub fn current_index(&self) -> Option<usize> {
+        self.current_uuid().and_then(|uuid| self.songs.iter().position(|e| e.uuid == uuid))
+    }
+
+    /// Where each song, in queue order, is relative to what's playing in playback order
+    pub fn position_states(&self) -> Vec<PositionState> {
+        let positions: HashMap<Uuid, usize> = self.order.iter().enumerate().map(|(i, uuid)| (*uuid, i)).collect();
+        let up_next_split = self.playing_up_next.is_some().then(|| self.up_next_position());
+        self.songs.iter().map(|entry| {
+            let position = positions.get(&entry.uuid).copied().unwrap_or(self.order.len());
+            match up_next_split {
+                Some(split) if position < split => PositionState::Passed,
+                Some(_) => PositionState::Upcoming,
+                None if position < self.current => PositionState::Passed,
+                None if position > self.current => PositionState::Upcoming,
+                None => PositionState::Current,
+            }
+        }).collect()
+    }
+
+    /// Inserts a song at `index` in queue order, or at the end. When shuffled it's
+    /// placed at a random point after the current song in playback order.
     pub fn insert_song(&mut self, song: Rc<Song>, index: Option<usize>) {
-        let index = if let Some(index) = index {
-            if index <= self.current {
+        let entry: SongEntry = (Uuid::new_v4(), song).into();
+        let uuid = entry.uuid;
+        let index = index.unwrap_or(self.songs.len()).min(self.songs.len());
+        let has_current = self.current < self.order.len();
+        self.songs.insert(index, entry);
+        if self.shuffled {
+            let start = if has_current { self.current + 1 } else { self.current };
+            let position = rand::rng().random_range(start..=self.order.len());
+            self.order.insert(position, uuid);
+        } else {
+            self.order.insert(index, uuid);
+            // Keep the current song, or stay at the end unless the song was appended after it
+            if index < self.current || (index == self.current && has_current) {
                 self.current += 1;
             }
-            self.songs.insert(index, (Uuid::new_v4(), song).into());
-            index
-        } else {
-            self.songs.push((Uuid::new_v4(), song).into());
-            self.songs.len()-1
-        };
-        if self.shuffled {
-            let mut rng = rand::rng();
-            self.shuffled_order.insert(rng.random_range(self.current..=self.songs.len()), index);
         }
     }
 
     pub fn add_songs(&mut self, songs: Vec<Rc<Song>>) {
-        let mut x: Vec<SongEntry> = songs.into_iter().map(|song| {
-            (Uuid::new_v4(), song).into()
-        }).collect();
-        if self.shuffled {
-            let prev_songs_n = self.songs.len();
-            let songs_n = x.len() + prev_songs_n;
-            let mut rng = rand::rng();
-            for i in prev_songs_n..songs_n {
-                self.shuffled_order.insert(rng.random_range(self.current..=self.shuffled_order.len()), i);
+        self.insert_songs(songs, None);
+    }
+
+    /// Inserts songs together at `index` in queue order, or at the end
+    pub fn insert_songs(&mut self, songs: Vec<Rc<Song>>, index: Option<usize>) {
+        for (i, song) in songs.into_iter().enumerate() {
+            self.insert_song(song, index.map(|index| index + i));
+        }
+    }
+
+    /// Moves the songs at `indexes` in queue order so they're together, in their current order,
+    /// before the song at `to`, or at the end. Returns the new index of the first moved song.
+    pub fn move_songs(&mut self, indexes: &[usize], to: usize) -> Option<usize> {
+        let mut indexes: Vec<usize> = indexes.iter().copied().filter(|i| *i < self.songs.len()).collect();
+        indexes.sort_unstable();
+        indexes.dedup();
+        if indexes.is_empty() {
+            return None;
+        }
+        let to = to.min(self.songs.len());
+        let target = to - indexes.iter().filter(|i| **i < to).count();
+        let mut moved: Vec<SongEntry> = indexes.iter().rev().map(|i| self.songs.remove(*i)).collect();
+        moved.reverse();
+        self.songs.splice(target..target, moved);
+        if !self.shuffled {
+            let current = self.current_uuid();
+            self.order = self.songs.iter().map(|e| e.uuid).collect();
+            if let Some(uuid) = current {
+                self.current = self.order_position(&uuid).unwrap_or(self.current);
             }
         }
-        self.songs.append(&mut x);
+        Some(target)
     }
 
     pub fn get_songs(&self) -> &Vec<SongEntry> {
         &self.songs
     }
 
-    // Returns the index of the current song after the move. This may be the same as before
-    // or it may be different due to the move. If None is returned, no move was performed.
+    /// Moves the song at `index` one place in queue order, returning its new index.
+    /// None is returned if no move was performed. The shuffled playback order is left as is.
     pub fn move_song(&mut self, index: usize, direction: MoveDirection) -> Option<usize> {
         let new_i = match direction {
-            MoveDirection::Up => if index == 0 {
-                return None;
-            } else {
-                index - 1
-            },
-            MoveDirection::Down => if index == self.songs.len()-1 {
-                return None;
-            } else {
-                index + 1
-            },
+            MoveDirection::Up => index.checked_sub(1)?,
+            MoveDirection::Down => index + 1,
         };
+        if index >= self.songs.len() || new_i >= self.songs.len() {
+            return None;
+        }
+        let current = self.current_uuid();
         self.songs.swap(index, new_i);
-        // When shuffled also swap the two indexes in the shuffled_order vec. This should leave the shuffled order unaffected
-        if self.shuffled {
-            let oldi = self.shuffled_order.iter().find(|i| **i==index).expect("Couldn't find old index in shuffle map").clone();
-            let newi = self.shuffled_order.iter().find(|i| **i==new_i).expect("Couldn't find new index in shuffle map").clone();
-            self.shuffled_order.swap(oldi, newi);
-        } else {
-            if index == self.current {
-                self.current = new_i;
-            } else if new_i == self.current {
-                self.current = index;
+        if !self.shuffled {
+            self.order.swap(index, new_i);
+            if let Some(uuid) = current {
+                self.current = self.order_position(&uuid).unwrap_or(self.current);
             }
         }
-        self.current_index()
+        Some(new_i)
+    }
+
+    /// Saves the queue so the edit about to be made can be undone
+    pub fn checkpoint(&mut self) {
+        let checkpoint = self.begin_edit();
+        self.commit_edit(checkpoint);
+    }
+
+    /// The queue before an edit that may fail, only pass it to `commit_edit` once the edit was made
+    pub fn begin_edit(&self) -> Checkpoint {
+        Checkpoint(self.snapshot())
+    }
+
+    /// Makes the edit started with `begin_edit` undoable
+    pub fn commit_edit(&mut self, checkpoint: Checkpoint) {
+        self.undo_stack.push(checkpoint.0);
+        if self.undo_stack.len() > MAX_UNDO_STEPS {
+            self.undo_stack.remove(0);
+        }
+        self.redo_stack.clear();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    /// Goes back to the queue before the last edit, returns false if there was nothing to undo
+    pub fn undo(&mut self) -> bool {
+        let Some(snapshot) = self.undo_stack.pop() else {
+            return false;
+        };
+        let replaced = self.restore(snapshot);
+        self.redo_stack.push(replaced);
+        true
+    }
+
+    /// Reapplies the last undone edit, returns false if there was nothing to redo
+    pub fn redo(&mut self) -> bool {
+        let Some(snapshot) = self.redo_stack.pop() else {
+            return false;
+        };
+        let replaced = self.restore(snapshot);
+        self.undo_stack.push(replaced);
+        true
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            songs: self.songs.clone(),
+            order: self.order.clone(),
+            current: self.current,
+            up_next: self.up_next.clone(),
+            playing_up_next: self.playing_up_next.clone(),
+            main_pending: self.main_pending,
+            shuffled: self.shuffled,
+            loop_status: self.loop_status,
+        }
+    }
+
+    /// Replaces the queue with a saved one, returning the replaced state. The song
+    /// that's playing stays current if it's part of the restored queue.
+    fn restore(&mut self, snapshot: Snapshot) -> Snapshot {
+        let replaced = self.snapshot();
+        let playing = self.current().map(|e| e.uuid);
+        let playing_up_next = self.playing_up_next.take();
+
+        self.songs = snapshot.songs;
+        self.order = snapshot.order;
+        self.current = snapshot.current;
+        self.up_next = snapshot.up_next;
+        self.playing_up_next = snapshot.playing_up_next;
+        self.main_pending = snapshot.main_pending;
+        self.shuffled = snapshot.shuffled;
+        self.loop_status = snapshot.loop_status;
+
+        if let Some(uuid) = playing {
+            if let Some(position) = self.order_position(&uuid) {
+                self.current = position;
+                self.playing_up_next = None;
+                self.main_pending = false;
+            } else if let Some(entry) = playing_up_next.filter(|e| e.uuid == uuid) {
+                self.up_next.retain(|e| e.uuid != uuid);
+                self.playing_up_next = Some(entry);
+            }
+        }
+        replaced
+    }
+
+    /// The queue in the form it's saved in between runs, without the playback position
+    pub fn to_saved(&self) -> SavedQueue {
+        let saved_entry = |e: &SongEntry| SavedEntry {
+            uuid: e.uuid,
+            id: e.song.id.clone(),
+            song: Some((*e.song).clone()),
+        };
+        SavedQueue {
+            songs: self.songs.iter().map(saved_entry).collect(),
+            order: self.order.clone(),
+            current: self.current,
+            up_next: self.up_next.iter().map(saved_entry).collect(),
+            playing_up_next: self.playing_up_next.as_ref().map(saved_entry),
+            main_pending: self.main_pending,
+            shuffled: self.shuffled,
+            loop_status: match self.loop_status {
+                LoopStatus::None => "none",
+                LoopStatus::Track => "track",
+                LoopStatus::Playlist => "playlist",
+            }.to_string(),
+            position: 0,
+        }
+    }
+
+    /// Replaces the queue with a saved one. Entries whose song isn't in `songs` are left out,
+    /// if that's the current one the song after it becomes current.
+    pub fn restore_saved(&mut self, saved: SavedQueue, songs: &HashMap<String, Rc<Song>>) {
+        let entry = |e: SavedEntry| songs.get(&e.id).map(|song| SongEntry { uuid: e.uuid, song: song.clone() });
+        self.clear();
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+
+        self.songs = saved.songs.into_iter().filter_map(entry).collect();
+        self.shuffled = saved.shuffled;
+        let kept: HashSet<Uuid> = self.songs.iter().map(|e| e.uuid).collect();
+        self.order = if saved.shuffled {
+            let mut order: Vec<Uuid> = saved.order.iter()
+                .copied()
+                .filter(|uuid| kept.contains(uuid))
+                .collect();
+            // Keep songs missing from the order playable
+            let ordered: HashSet<Uuid> = order.iter().copied().collect();
+            order.extend(self.songs.iter().map(|e| e.uuid).filter(|uuid| !ordered.contains(uuid)));
+            order
+        } else {
+            self.songs.iter().map(|e| e.uuid).collect()
+        };
+        // Kept entries before the saved current one, which is its position if it was kept too
+        let current = saved.order.get(saved.current).copied();
+        self.current = match current.and_then(|uuid| self.order_position(&uuid)) {
+            Some(position) => position,
+            None => saved.order.iter()
+                .take(saved.current)
+                .filter(|uuid| kept.contains(uuid))
+                .count(),
+        };
+        self.up_next = saved.up_next.into_iter().filter_map(entry).collect();
+        self.playing_up_next = saved.playing_up_next.and_then(entry);
+        self.main_pending = saved.main_pending && self.playing_up_next.is_some();
+        self.loop_status = match saved.loop_status.as_str() {
+            "track" => LoopStatus::Track,
+            "playlist" => LoopStatus::Playlist,
+            _ => LoopStatus::None,
+        };
+    }
+
+    fn current_uuid(&self) -> Option<Uuid> {
+        self.order.get(self.current).copied()
+    }
+
+    fn order_position(&self, uuid: &Uuid) -> Option<usize> {
+        self.order.iter().position(|u| u == uuid)
+    }
+
+    fn entry(&self, uuid: &Uuid) -> Option<&SongEntry> {
+        self.songs.iter().find(|e| e.uuid == *uuid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn songs(ids: &[&str]) -> Vec<Rc<Song>> {
+        ids.iter().map(|id| Rc::new(Song { id: id.to_string(), title: id.to_string(), ..Default::default() })).collect()
+    }
+
+    fn track_list(ids: &[&str]) -> TrackList {
+        let mut track_list = TrackList::new();
+        track_list.add_songs(songs(ids));
+        track_list
+    }
+
+    fn queue_ids(track_list: &TrackList) -> Vec<String> {
+        track_list.get_songs().iter().map(|e| e.song.id.clone()).collect()
+    }
+
+    fn playback_ids(track_list: &TrackList) -> Vec<String> {
+        track_list.ordered_entries().iter().map(|e| e.song.id.clone()).collect()
+    }
+
+    fn current_id(track_list: &TrackList) -> Option<String> {
+        track_list.current().map(|e| e.song.id.clone())
+    }
+
+    fn sorted(mut ids: Vec<String>) -> Vec<String> {
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn plays_in_queue_order_until_the_end() {
+        let mut tl = track_list(&["a", "b", "c"]);
+        assert_eq!(current_id(&tl).as_deref(), Some("a"));
+        assert!(!tl.next());
+        assert_eq!(current_id(&tl).as_deref(), Some("b"));
+        assert!(!tl.next());
+        assert_eq!(current_id(&tl).as_deref(), Some("c"));
+        assert!(tl.next());
+        assert_eq!(current_id(&tl), None);
+        assert_eq!(tl.current_index(), None);
+        // Staying at the end
+        assert!(tl.next());
+        assert_eq!(current_id(&tl), None);
+    }
+
+    #[test]
+    fn appending_after_the_end_continues_with_new_songs() {
+        let mut tl = track_list(&["a"]);
+        assert!(tl.next());
+        tl.add_songs(songs(&["b", "c"]));
+        assert_eq!(current_id(&tl).as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn inserting_into_the_middle_after_the_end_stays_at_the_end() {
+        let mut tl = track_list(&["a", "b"]);
+        tl.next();
+        tl.next();
+        tl.insert_song(songs(&["x"]).remove(0), Some(1));
+        assert_eq!(queue_ids(&tl), ["a", "x", "b"]);
+        assert_eq!(current_id(&tl), None);
+    }
+
+    #[test]
+    fn inserting_before_current_keeps_current() {
+        let mut tl = track_list(&["a", "b", "c"]);
+        tl.set_current(1);
+        tl.insert_song(songs(&["x"]).remove(0), Some(0));
+        tl.insert_song(songs(&["y"]).remove(0), Some(2));
+        assert_eq!(queue_ids(&tl), ["x", "a", "y", "b", "c"]);
+        assert_eq!(current_id(&tl).as_deref(), Some("b"));
+        assert_eq!(tl.current_index(), Some(3));
+        tl.next();
+        assert_eq!(current_id(&tl).as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn inserting_after_current_plays_it_next() {
+        let mut tl = track_list(&["a", "b"]);
+        tl.insert_song(songs(&["x"]).remove(0), Some(1));
+        assert_eq!(current_id(&tl).as_deref(), Some("a"));
+        tl.next();
+        assert_eq!(current_id(&tl).as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn inserting_into_an_empty_list_makes_it_current() {
+        let mut tl = TrackList::new();
+        tl.insert_song(songs(&["a"]).remove(0), Some(0));
+        assert_eq!(current_id(&tl).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn removing_before_current_keeps_current() {
+        let mut tl = track_list(&["a", "b", "c"]);
+        tl.set_current(2);
+        let removed = tl.remove_song(0);
+        assert_eq!(removed.song.id, "a");
+        assert_eq!(current_id(&tl).as_deref(), Some("c"));
+        assert_eq!(tl.current_index(), Some(1));
+    }
+
+    #[test]
+    fn removing_after_current_keeps_current() {
+        let mut tl = track_list(&["a", "b", "c"]);
+        tl.remove_song(1);
+        assert_eq!(current_id(&tl).as_deref(), Some("a"));
+        tl.next();
+        assert_eq!(current_id(&tl).as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn removing_current_moves_to_the_next_song() {
+        let mut tl = track_list(&["a", "b", "c"]);
+        tl.set_current(1);
+        tl.remove_song(1);
+        assert_eq!(current_id(&tl).as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn removing_the_last_current_song_ends_the_list() {
+        let mut tl = track_list(&["a", "b"]);
+        tl.set_current(1);
+        tl.remove_song(1);
+        assert_eq!(current_id(&tl), None);
+        tl.remove_song(0);
+        assert!(tl.empty());
+    }
+
+    #[test]
+    fn removing_while_shuffled_updates_the_playback_order() {
+        let mut tl = track_list(&["a", "b", "c", "d", "e"]);
+        tl.set_current(2);
+        tl.set_shuffle(true);
+        tl.remove_song(3);
+        tl.remove_song(0);
+        assert_eq!(queue_ids(&tl), ["b", "c", "e"]);
+        assert_eq!(sorted(playback_ids(&tl)), ["b", "c", "e"]);
+        assert_eq!(current_id(&tl).as_deref(), Some("c"));
+        assert_eq!(tl.current_index(), Some(1));
+    }
+
+    #[test]
+    fn moving_keeps_current() {
+        let mut tl = track_list(&["a", "b", "c"]);
+        tl.set_current(1);
+        assert_eq!(tl.move_song(1, MoveDirection::Up), Some(0));
+        assert_eq!(queue_ids(&tl), ["b", "a", "c"]);
+        assert_eq!(current_id(&tl).as_deref(), Some("b"));
+        assert_eq!(tl.current_index(), Some(0));
+        // Moving another song across the current one
+        assert_eq!(tl.move_song(1, MoveDirection::Up), Some(0));
+        assert_eq!(queue_ids(&tl), ["a", "b", "c"]);
+        assert_eq!(current_id(&tl).as_deref(), Some("b"));
+        tl.next();
+        assert_eq!(current_id(&tl).as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn moving_past_the_edges_does_nothing() {
+        let mut tl = track_list(&["a", "b"]);
+        assert_eq!(tl.move_song(0, MoveDirection::Up), None);
+        assert_eq!(tl.move_song(1, MoveDirection::Down), None);
+        assert_eq!(tl.move_song(5, MoveDirection::Down), None);
+        assert_eq!(queue_ids(&tl), ["a", "b"]);
+    }
+
+    #[test]
+    fn moving_several_songs_keeps_their_order() {
+        let mut tl = track_list(&["a", "b", "c", "d", "e"]);
+        tl.set_current(3);
+        assert_eq!(tl.move_songs(&[4, 1, 3], 0), Some(0));
+        assert_eq!(queue_ids(&tl), ["b", "d", "e", "a", "c"]);
+        assert_eq!(playback_ids(&tl), queue_ids(&tl));
+        assert_eq!(current_id(&tl).as_deref(), Some("d"));
+        assert_eq!(tl.current_index(), Some(1));
+        tl.next();
+        assert_eq!(current_id(&tl).as_deref(), Some("e"));
+    }
+
+    #[test]
+    fn moving_songs_down_and_to_the_end() {
+        let mut tl = track_list(&["a", "b", "c", "d", "e"]);
+        assert_eq!(tl.move_songs(&[0, 1], 4), Some(2));
+        assert_eq!(queue_ids(&tl), ["c", "d", "a", "b", "e"]);
+        assert_eq!(tl.move_songs(&[0], 99), Some(4));
+        assert_eq!(queue_ids(&tl), ["d", "a", "b", "e", "c"]);
+        assert_eq!(current_id(&tl).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn moving_nothing_or_invalid_indexes_does_nothing() {
+        let mut tl = track_list(&["a", "b"]);
+        assert_eq!(tl.move_songs(&[], 0), None);
+        assert_eq!(tl.move_songs(&[2, 7], 0), None);
+        assert_eq!(queue_ids(&tl), ["a", "b"]);
+    }
+
+    #[test]
+    fn moving_several_while_shuffled_keeps_the_playback_order() {
+        let mut tl = track_list(&["a", "b", "c", "d", "e"]);
+        tl.set_shuffle(true);
+        let before = playback_ids(&tl);
+        tl.move_songs(&[3, 4], 1);
+        assert_eq!(queue_ids(&tl), ["a", "d", "e", "b", "c"]);
+        assert_eq!(playback_ids(&tl), before);
+    }
+
+    #[test]
+    fn inserting_several_songs_keeps_them_together() {
+        let mut tl = track_list(&["a", "b"]);
+        tl.insert_songs(songs(&["x", "y"]), Some(1));
+        assert_eq!(queue_ids(&tl), ["a", "x", "y", "b"]);
+        assert_eq!(current_id(&tl).as_deref(), Some("a"));
+        tl.next();
+        assert_eq!(current_id(&tl).as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn moving_while_shuffled_keeps_the_playback_order() {
+        let mut tl = track_list(&["a", "b", "c", "d", "e"]);
+        tl.set_shuffle(true);
+        let before = playback_ids(&tl);
+        let current = current_id(&tl);
+        assert_eq!(tl.move_song(3, MoveDirection::Up), Some(2));
+        assert_eq!(tl.move_song(0, MoveDirection::Down), Some(1));
+        assert_eq!(playback_ids(&tl), before);
+        assert_eq!(current_id(&tl), current);
+        assert_eq!(tl.song_at_index(tl.current_index().unwrap()).map(|e| e.song.id.clone()), current);
+    }
+
+    #[test]
+    fn shuffling_keeps_played_songs_and_current() {
+        let ids = ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"];
+        let mut tl = track_list(&ids);
+        tl.set_current(3);
+        tl.set_shuffle(true);
+        assert!(tl.is_suffled());
+        assert_eq!(queue_ids(&tl), ids);
+        let order = playback_ids(&tl);
+        assert_eq!(order[..4], ["0", "1", "2", "3"]);
+        assert_eq!(sorted(order), ids);
+        assert_eq!(current_id(&tl).as_deref(), Some("3"));
+        assert_eq!(tl.current_index(), Some(3));
+    }
+
+    #[test]
+    fn shuffled_playback_plays_every_song_once() {
+        let ids = ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"];
+        let mut tl = track_list(&ids);
+        tl.set_shuffle(true);
+        let mut played = vec![current_id(&tl).unwrap()];
+        while !tl.next() {
+            played.push(current_id(&tl).unwrap());
+        }
+        assert_eq!(played.len(), ids.len());
+        assert_eq!(sorted(played), ids);
+    }
+
+    #[test]
+    fn unshuffling_continues_in_queue_order_from_current() {
+        let mut tl = track_list(&["a", "b", "c", "d", "e"]);
+        tl.set_shuffle(true);
+        tl.next();
+        tl.next();
+        let current = current_id(&tl).unwrap();
+        tl.set_shuffle(false);
+        assert!(!tl.is_suffled());
+        assert_eq!(playback_ids(&tl), queue_ids(&tl));
+        assert_eq!(current_id(&tl), Some(current.clone()));
+        let index = queue_ids(&tl).iter().position(|id| *id == current).unwrap();
+        assert_eq!(tl.current_index(), Some(index));
+    }
+
+    #[test]
+    fn toggling_shuffle_twice_is_stable() {
+        let mut tl = track_list(&["a", "b", "c"]);
+        tl.set_shuffle(true);
+        let order = playback_ids(&tl);
+        tl.set_shuffle(true);
+        assert_eq!(playback_ids(&tl), order);
+        tl.set_shuffle(false);
+        tl.set_shuffle(false);
+        assert_eq!(playback_ids(&tl), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn songs_added_while_shuffled_play_after_current() {
+        let mut tl = track_list(&["a", "b", "c"]);
+        tl.set_current(1);
+        tl.set_shuffle(true);
+        tl.add_songs(songs(&["x", "y"]));
+        tl.insert_song(songs(&["z"]).remove(0), Some(0));
+        assert_eq!(queue_ids(&tl), ["z", "a", "b", "c", "x", "y"]);
+        let order = playback_ids(&tl);
+        assert_eq!(order[..2], ["a", "b"]);
+        assert_eq!(sorted(order), ["a", "b", "c", "x", "y", "z"]);
+        assert_eq!(current_id(&tl).as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn set_current_out_of_range_is_rejected() {
+        let mut tl = track_list(&["a", "b"]);
+        tl.set_current(1);
+        assert!(!tl.set_current(2));
+        assert_eq!(current_id(&tl).as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn set_current_while_shuffled_uses_queue_index() {
+        let mut tl = track_list(&["a", "b", "c", "d"]);
+        tl.set_shuffle(true);
+        assert!(tl.set_current(2));
+        assert_eq!(current_id(&tl).as_deref(), Some("c"));
+        assert_eq!(tl.current_index(), Some(2));
+    }
+
+    #[test]
+    fn loop_track_repeats_the_current_song() {
+        let mut tl = track_list(&["a", "b"]);
+        tl.loop_status = LoopStatus::Track;
+        assert!(!tl.next());
+        assert_eq!(current_id(&tl).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn loop_playlist_wraps_around() {
+        let mut tl = track_list(&["a", "b"]);
+        tl.loop_status = LoopStatus::Playlist;
+        assert!(!tl.next());
+        assert!(!tl.next());
+        assert_eq!(current_id(&tl).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn loop_playlist_wraps_around_while_shuffled() {
+        let mut tl = track_list(&["a", "b", "c"]);
+        tl.set_shuffle(true);
+        tl.loop_status = LoopStatus::Playlist;
+        let first = current_id(&tl);
+        for _ in 0..3 {
+            assert!(!tl.next());
+        }
+        assert_eq!(current_id(&tl), first);
+    }
+
+    #[test]
+    fn loop_playlist_restarts_after_the_end() {
+        let mut tl = track_list(&["a", "b"]);
+        tl.next();
+        tl.next();
+        tl.loop_status = LoopStatus::Playlist;
+        assert!(!tl.next());
+        assert_eq!(current_id(&tl).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn previous_stops_at_the_start_and_returns_from_the_end() {
+        let mut tl = track_list(&["a", "b"]);
+        tl.previous();
+        assert_eq!(current_id(&tl).as_deref(), Some("a"));
+        tl.next();
+        tl.next();
+        tl.previous();
+        assert_eq!(current_id(&tl).as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn up_next_plays_before_the_rest() {
+        let mut tl = track_list(&["a", "b"]);
+        tl.add_up_next(songs(&["x", "y"]));
+        assert!(!tl.start_up_next());
+        assert_eq!(playback_ids(&tl), ["a", "x", "y", "b"]);
+        assert!(!tl.next());
+        assert_eq!(current_id(&tl).as_deref(), Some("x"));
+        assert!(tl.is_playing_up_next());
+        assert_eq!(playback_ids(&tl), ["a", "x", "y", "b"]);
+        tl.next();
+        tl.next();
+        assert_eq!(current_id(&tl).as_deref(), Some("b"));
+        assert!(!tl.is_playing_up_next());
+    }
+
+    #[test]
+    fn removing_the_song_before_up_next_keeps_the_one_after_it() {
+        let mut tl = track_list(&["a", "b", "c"]);
+        tl.add_up_next(songs(&["x"]));
+        tl.next();
+        assert_eq!(current_id(&tl).as_deref(), Some("x"));
+        tl.remove_song(0);
+        assert_eq!(current_id(&tl).as_deref(), Some("x"));
+        assert_eq!(playback_ids(&tl), ["x", "b", "c"]);
+        assert_eq!(tl.position_states(), [PositionState::Upcoming; 2]);
+        assert!(!tl.next());
+        assert_eq!(current_id(&tl).as_deref(), Some("b"));
+        assert!(!tl.next());
+        assert_eq!(current_id(&tl).as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn up_next_started_while_idle_plays_the_pending_song_after() {
+        let mut tl = TrackList::new();
+        tl.add_up_next(songs(&["x"]));
+        assert!(tl.start_up_next());
+        tl.add_songs(songs(&["a"]));
+        assert_eq!(current_id(&tl).as_deref(), Some("x"));
+        assert_eq!(playback_ids(&tl), ["x", "a"]);
+        assert!(!tl.next());
+        assert_eq!(current_id(&tl).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn position_states_follow_playback_order() {
+        let mut tl = track_list(&["a", "b", "c"]);
+        tl.set_current(1);
+        assert_eq!(tl.position_states(), [PositionState::Passed, PositionState::Current, PositionState::Upcoming]);
+        tl.add_up_next(songs(&["x"]));
+        tl.next();
+        assert_eq!(tl.position_states(), [PositionState::Passed, PositionState::Passed, PositionState::Upcoming]);
+        tl.next();
+        tl.next();
+        assert_eq!(tl.position_states(), [PositionState::Passed; 3]);
+    }
+
+    fn uuids(track_list: &TrackList) -> Vec<Uuid> {
+        track_list.get_songs().iter().map(|e| e.uuid).collect()
+    }
+
+    #[test]
+    fn undoing_clear_restores_the_same_entries() {
+        let mut tl = track_list(&["a", "b", "c"]);
+        tl.set_current(1);
+        tl.add_up_next(songs(&["x"]));
+        let before = uuids(&tl);
+        tl.checkpoint();
+        tl.clear();
+        assert!(tl.can_undo());
+        assert!(tl.undo());
+        assert_eq!(uuids(&tl), before);
+        assert_eq!(current_id(&tl).as_deref(), Some("b"));
+        assert_eq!(tl.up_next().len(), 1);
+        assert!(!tl.can_undo());
+        assert!(!tl.undo());
+    }
+
+    #[test]
+    fn redo_reapplies_the_undone_edit() {
+        let mut tl = track_list(&["a", "b", "c"]);
+        tl.checkpoint();
+        tl.remove_song(1);
+        tl.undo();
+        assert_eq!(queue_ids(&tl), ["a", "b", "c"]);
+        assert!(tl.can_redo());
+        assert!(tl.redo());
+        assert_eq!(queue_ids(&tl), ["a", "c"]);
+        assert!(!tl.redo());
+        tl.undo();
+        assert_eq!(queue_ids(&tl), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn uncommitted_edits_leave_the_history_alone() {
+        let mut tl = track_list(&["a", "b"]);
+        tl.checkpoint();
+        tl.remove_song(0);
+        tl.undo();
+        let checkpoint = tl.begin_edit();
+        assert_eq!(tl.move_song(0, MoveDirection::Up), None);
+        drop(checkpoint);
+        assert!(!tl.can_undo());
+        assert!(tl.can_redo());
+    }
+
+    #[test]
+    fn a_new_edit_drops_the_redo_history() {
+        let mut tl = track_list(&["a", "b"]);
+        tl.checkpoint();
+        tl.remove_song(0);
+        tl.undo();
+        tl.checkpoint();
+        tl.add_songs(songs(&["c"]));
+        assert!(!tl.can_redo());
+    }
+
+    #[test]
+    fn undo_keeps_the_playing_song_current() {
+        let mut tl = track_list(&["a", "b", "c", "d"]);
+        tl.checkpoint();
+        tl.remove_song(3);
+        tl.next();
+        tl.next();
+        tl.undo();
+        assert_eq!(queue_ids(&tl), ["a", "b", "c", "d"]);
+        assert_eq!(current_id(&tl).as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn undo_keeps_a_playing_up_next_song() {
+        let mut tl = track_list(&["a", "b"]);
+        tl.add_up_next(songs(&["x", "y"]));
+        tl.checkpoint();
+        tl.remove_song(1);
+        tl.next();
+        tl.undo();
+        assert_eq!(current_id(&tl).as_deref(), Some("x"));
+        assert_eq!(tl.up_next().iter().map(|e| e.song.id.as_str()).collect::<Vec<_>>(), ["y"]);
+        assert_eq!(queue_ids(&tl), ["a", "b"]);
+    }
+
+    #[test]
+    fn undo_history_is_limited() {
+        let mut tl = track_list(&["a"]);
+        for _ in 0..MAX_UNDO_STEPS + 10 {
+            tl.checkpoint();
+            tl.add_songs(songs(&["b"]));
+        }
+        let mut steps = 0;
+        while tl.undo() {
+            steps += 1;
+        }
+        assert_eq!(steps, MAX_UNDO_STEPS);
+        assert_eq!(tl.get_songs().len(), 11);
+    }
+
+    #[test]
+    fn saving_and_restoring_keeps_entries_and_position() {
+        let mut tl = track_list(&["a", "b", "c", "d"]);
+        tl.set_current(2);
+        tl.set_shuffle(true);
+        tl.add_up_next(songs(&["x"]));
+        tl.loop_status = LoopStatus::Playlist;
+        let saved = tl.to_saved();
+        let library: HashMap<String, Rc<Song>> = songs(&["a", "b", "c", "d", "x"]).into_iter().map(|s| (s.id.clone(), s)).collect();
+
+        let mut restored = TrackList::new();
+        restored.restore_saved(saved, &library);
+        let uuids = |tl: &TrackList| tl.get_songs().iter().map(|e| e.uuid).collect::<Vec<_>>();
+        assert_eq!(uuids(&restored), uuids(&tl));
+        assert_eq!(playback_ids(&restored), playback_ids(&tl));
+        assert_eq!(current_id(&restored).as_deref(), Some("c"));
+        assert!(restored.is_suffled());
+        assert_eq!(restored.loop_status, LoopStatus::Playlist);
+        assert_eq!(restored.up_next()[0].uuid, tl.up_next()[0].uuid);
+    }
+
+    #[test]
+    fn saved_queue_restores_without_the_server() {
+        let mut tl = track_list(&["a", "b", "a"]);
+        tl.add_up_next(songs(&["x"]));
+        let saved: SavedQueue = serde_json::from_str(&serde_json::to_string(&tl.to_saved()).unwrap()).unwrap();
+        assert!(saved.missing_song_ids().is_empty());
+
+        let mut restored = TrackList::new();
+        restored.restore_saved(saved, &SavedQueue::default().saved_songs());
+        assert!(restored.empty());
+
+        let saved: SavedQueue = serde_json::from_str(&serde_json::to_string(&tl.to_saved()).unwrap()).unwrap();
+        let songs = saved.saved_songs();
+        restored.restore_saved(saved, &songs);
+        assert_eq!(queue_ids(&restored), ["a", "b", "a"]);
+        assert_eq!(restored.up_next()[0].song.id, "x");
+
+        // Saved by an older version, only the ids are there
+        let mut old = tl.to_saved();
+        old.songs.iter_mut().chain(old.up_next.iter_mut()).for_each(|e| e.song = None);
+        assert_eq!(old.missing_song_ids(), ["a", "b", "x"]);
+    }
+
+    #[test]
+    fn restoring_without_the_current_song_continues_after_it() {
+        let mut tl = track_list(&["a", "b", "c"]);
+        tl.set_current(1);
+        let saved = tl.to_saved();
+        let library: HashMap<String, Rc<Song>> = songs(&["a", "c"]).into_iter().map(|s| (s.id.clone(), s)).collect();
+
+        let mut restored = TrackList::new();
+        restored.restore_saved(saved, &library);
+        assert_eq!(queue_ids(&restored), ["a", "c"]);
+        assert_eq!(current_id(&restored).as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn changing_shuffle_mode_keeps_played_songs_and_current() {
+        let mut tl = track_list(&["a", "b", "c", "d", "e"]);
+        tl.set_current(1);
+        tl.set_shuffle(true);
+        let played: Vec<String> = playback_ids(&tl)[..2].to_vec();
+        assert!(tl.set_shuffle_mode(ShuffleMode::Album));
+        assert_eq!(playback_ids(&tl)[..2], played);
+        assert_eq!(current_id(&tl).as_deref(), Some("b"));
+        assert_eq!(sorted(playback_ids(&tl)), ["a", "b", "c", "d", "e"]);
+        // Not shuffled, so nothing to reorder
+        tl.set_shuffle(false);
+        assert!(!tl.set_shuffle_mode(ShuffleMode::Weighted));
+        assert_eq!(playback_ids(&tl), ["a", "b", "c", "d", "e"]);
+    }
+
+    fn assert_peek_matches_next(tl: &mut TrackList) {
+        let peeked = tl.peek_next().map(|e| e.uuid);
+        let over = tl.next();
+        assert_eq!(peeked, if over { None } else { tl.current().map(|e| e.uuid) });
+    }
+
+    #[test]
+    fn peeking_gives_what_next_moves_to() {
+        let mut tl = track_list(&["a", "b", "c"]);
+        for _ in 0..4 {
+            assert_peek_matches_next(&mut tl);
+        }
+
+        let mut tl = track_list(&["a", "b"]);
+        tl.loop_status = LoopStatus::Playlist;
+        for _ in 0..3 {
+            assert_peek_matches_next(&mut tl);
+        }
+        tl.loop_status = LoopStatus::Track;
+        assert_peek_matches_next(&mut tl);
+
+        let mut tl = track_list(&["a", "b", "c", "d"]);
+        tl.set_shuffle(true);
+        tl.add_up_next(songs(&["x", "y"]));
+        for _ in 0..6 {
+            assert_peek_matches_next(&mut tl);
+        }
+
+        // Up next started with nothing playing, the main queue's current song is still to come
+        let mut tl = TrackList::new();
+        tl.add_up_next(songs(&["x"]));
+        tl.play_up_next(0);
+        tl.add_songs(songs(&["a", "b"]));
+        for _ in 0..3 {
+            assert_peek_matches_next(&mut tl);
+        }
+    }
+
+    #[test]
+    fn clear_resets_everything() {
+        let mut tl = track_list(&["a", "b"]);
+        tl.set_shuffle(true);
+        tl.add_up_next(songs(&["x"]));
+        tl.loop_status = LoopStatus::Playlist;
+        tl.clear();
+        assert!(tl.empty());
+        assert!(!tl.is_suffled());
+        assert_eq!(tl.loop_status, LoopStatus::None);
+        assert_eq!(current_id(&tl), None);
+        tl.add_songs(songs(&["c"]));
+        assert_eq!(current_id(&tl).as_deref(), Some("c"));
     }
 }
@@ -0,0 +1,93 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::rc::Rc;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::opensonic::client;
+use crate::opensonic::types::Song;
+
+const STATE_FILE: &str = "queue.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SavedEntry {
+    pub uuid: Uuid,
+    pub id: String,
+    /// The song as it was when saved, so the queue comes back without asking the server.
+    /// Not in queues saved by older versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub song: Option<Song>,
+}
+
+/// The play queue as saved between runs, in the data dir
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SavedQueue {
+    pub songs: Vec<SavedEntry>,
+    /// Playback order, only differs from the order of `songs` when shuffled
+    pub order: Vec<Uuid>,
+    pub current: usize,
+    pub up_next: Vec<SavedEntry>,
+    pub playing_up_next: Option<SavedEntry>,
+    pub main_pending: bool,
+    pub shuffled: bool,
+    pub loop_status: String,
+    /// Position in the current song in milliseconds
+    pub position: u64,
+}
+
+fn state_path() -> Option<PathBuf> {
+    client::get_default_data_dir().map(|d| PathBuf::from(d).join(STATE_FILE))
+}
+
+impl SavedQueue {
+    pub fn is_empty(&self) -> bool {
+        self.songs.is_empty() && self.up_next.is_empty() && self.playing_up_next.is_none()
+    }
+
+    fn entries(&self) -> impl Iterator<Item = &SavedEntry> {
+        self.songs.iter().chain(self.playing_up_next.iter()).chain(self.up_next.iter())
+    }
+
+    /// The songs saved along with the queue, by id
+    pub fn saved_songs(&self) -> HashMap<String, Rc<Song>> {
+        self.entries()
+            .filter_map(|e| e.song.as_ref())
+            .map(|song| (song.id.clone(), Rc::new(song.clone())))
+            .collect()
+    }
+
+    /// Ids of the songs that weren't saved along with the queue, without duplicates
+    pub fn missing_song_ids(&self) -> Vec<String> {
+        let mut seen = HashSet::new();
+        self.entries()
+            .filter(|e| e.song.is_none() && seen.insert(&e.id))
+            .map(|e| e.id.clone())
+            .collect()
+    }
+
+    /// Reads the saved queue, `None` if nothing has been saved yet
+    pub async fn load() -> Result<Option<SavedQueue>, Box<dyn Error>> {
+        let Some(path) = state_path() else {
+            return Ok(None);
+        };
+        match tokio::fs::read_to_string(&path).await {
+            Ok(text) => Ok(Some(serde_json::from_str(&text)?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub async fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = state_path().ok_or("No data directory to save the queue in")?;
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        // Written next to it first so a crash while writing doesn't lose the old state
+        let tmp_path = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp_path, serde_json::to_string(self)?).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+        Ok(())
+    }
+}
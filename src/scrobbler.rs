@@ -0,0 +1,142 @@
+use std::cell::{Cell, RefCell};
+use std::error::Error;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use crate::opensonic::client::{self, OpenSubsonicClient};
+use crate::opensonic::types::SubsonicError;
+
+const PENDING_FILE: &str = "scrobbles.json";
+/// Tries for a scrobble the server keeps rejecting before it's dropped.
+/// Only errors the server gives about the scrobble count as tries, see `is_rejection`.
+const MAX_ATTEMPTS: u32 = 3;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingScrobble {
+    pub id: String,
+    /// When the song started playing, in milliseconds since the Unix epoch
+    pub time: u64,
+    #[serde(default)]
+    pub attempts: u32,
+}
+
+/// Submits scrobbles to the server, keeping the ones that couldn't be sent in the data dir
+/// so they can be sent once the server is reachable again
+pub struct Scrobbler {
+    client: &'static OpenSubsonicClient,
+    pending: RefCell<Vec<PendingScrobble>>,
+    flushing: Cell<bool>,
+}
+
+/// Path of a file in the data dir for submissions that haven't been sent yet
+pub fn pending_path(file_name: &str) -> Option<PathBuf> {
+    client::get_default_data_dir().map(|d| PathBuf::from(d).join(file_name))
+}
+
+pub async fn load_pending<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>, Box<dyn Error>> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(text) => Ok(serde_json::from_str(&text)?),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub async fn save_pending<T: Serialize>(path: &Path, pending: &[T]) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let json = serde_json::to_string(pending)?;
+    let tmp_path = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp_path, json).await?;
+    tokio::fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+impl Scrobbler {
+    pub fn new(client: &'static OpenSubsonicClient) -> Self {
+        Self {
+            client,
+            pending: RefCell::new(Vec::new()),
+            flushing: Cell::new(false),
+        }
+    }
+
+    /// Reads scrobbles left over from previous runs
+    pub async fn load(&self) -> Result<(), Box<dyn Error>> {
+        let Some(path) = pending_path(PENDING_FILE) else {
+            return Ok(());
+        };
+        let saved: Vec<PendingScrobble> = load_pending(&path).await?;
+        if !saved.is_empty() {
+            info!(count = saved.len(), "Loaded pending scrobbles");
+        }
+        // Anything submitted before loading finished goes after the older ones
+        let mut pending = self.pending.borrow_mut();
+        let newer = std::mem::replace(&mut *pending, saved);
+        pending.extend(newer);
+        Ok(())
+    }
+
+    async fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = pending_path(PENDING_FILE).ok_or("No data directory to save scrobbles in")?;
+        let pending = self.pending.borrow().clone();
+        save_pending(&path, &pending).await
+    }
+
+    pub async fn submit(&self, scrobble: PendingScrobble) {
+        debug!(id = %scrobble.id, "Scrobbling");
+        self.pending.borrow_mut().push(scrobble);
+        if let Err(err) = self.save().await {
+            warn!(%err, "Couldn't save pending scrobbles");
+        }
+        self.flush().await;
+    }
+
+    /// Sends pending scrobbles oldest first, stopping when the server can't be reached
+    pub async fn flush(&self) {
+        if self.flushing.replace(true) {
+            return;
+        }
+        let mut changed = false;
+        loop {
+            let Some(next) = self.pending.borrow().first().cloned() else {
+                break;
+            };
+            match self.client.scrobble(&next.id, Some(true), Some(next.time)).await {
+                Ok(()) => {
+                    self.pending.borrow_mut().remove(0);
+                    changed = true;
+                }
+                Err(err) if !is_rejection(err.as_ref()) => {
+                    debug!(%err, "Couldn't submit scrobbles, keeping them for later");
+                    break;
+                }
+                Err(err) => {
+                    let mut pending = self.pending.borrow_mut();
+                    pending[0].attempts += 1;
+                    changed = true;
+                    if pending[0].attempts >= MAX_ATTEMPTS {
+                        warn!(id = %next.id, %err, "Dropping scrobble the server keeps rejecting");
+                        pending.remove(0);
+                    } else {
+                        warn!(id = %next.id, %err, "Scrobble rejected, trying again later");
+                        break;
+                    }
+                }
+            }
+        }
+        if changed && let Err(err) = self.save().await {
+            warn!(%err, "Couldn't save pending scrobbles");
+        }
+        self.flushing.set(false);
+    }
+}
+
+/// Whether the server answered with an error about the scrobble itself, such as not knowing the song.
+/// Anything else, like an unreadable response from a captive portal or failed authentication,
+/// could go away by itself, so the scrobble is kept.
+fn is_rejection(err: &(dyn Error + 'static)) -> bool {
+    err.downcast_ref::<SubsonicError>().is_some_and(|err| !(40..=44).contains(&err.code))
+}
@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use rand::prelude::SliceRandom;
+use rand::Rng;
+use tracing::warn;
+use uuid::Uuid;
+use crate::opensonic::types::Song;
+use crate::player::SongEntry;
+
+/// How the queue gets shuffled
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ShuffleMode {
+    #[default]
+    Random = 0,
+    /// Songs by the same artist are kept as far apart as possible
+    ArtistSpread = 1,
+    /// Albums are shuffled, songs within an album stay in track order
+    Album = 2,
+    /// Starred, highly rated and not recently played songs tend to come first
+    Weighted = 3,
+}
+
+impl From<u8> for ShuffleMode {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => ShuffleMode::Random,
+            1 => ShuffleMode::ArtistSpread,
+            2 => ShuffleMode::Album,
+            3 => ShuffleMode::Weighted,
+            v => {
+                warn!("Unknown shuffle-mode setting: {}", v);
+                ShuffleMode::Random
+            }
+        }
+    }
+}
+
+/// Shuffled order of `entries`, which are given in queue order
+pub fn shuffle<R: Rng + ?Sized>(entries: &[&SongEntry], mode: ShuffleMode, rng: &mut R) -> Vec<Uuid> {
+    match mode {
+        ShuffleMode::Random => {
+            let mut order: Vec<Uuid> = entries.iter().map(|e| e.uuid).collect();
+            order.shuffle(rng);
+            order
+        }
+        ShuffleMode::ArtistSpread => artist_spread(entries, rng),
+        ShuffleMode::Album => album_shuffle(entries, rng),
+        ShuffleMode::Weighted => weighted(entries, rng),
+    }
+}
+
+fn group_by<'a, K: Eq + std::hash::Hash>(entries: &[&'a SongEntry], key: impl Fn(&SongEntry) -> K) -> Vec<Vec<&'a SongEntry>> {
+    let mut groups: Vec<Vec<&SongEntry>> = Vec::new();
+    let mut indexes: HashMap<K, usize> = HashMap::new();
+    for entry in entries {
+        let i = *indexes.entry(key(entry)).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[i].push(entry);
+    }
+    groups
+}
+
+/// Artist of a song, songs without one are each treated as their own artist
+fn artist_key(entry: &SongEntry) -> String {
+    entry.song.artist_id.clone()
+        .or_else(|| entry.song.artist.clone())
+        .unwrap_or_else(|| entry.uuid.to_string())
+}
+
+/// Spreads each artist's songs evenly over the queue with a random offset, so songs by an artist
+/// with n songs are 1/n of the queue apart and others fill the gaps in between
+fn artist_spread<R: Rng + ?Sized>(entries: &[&SongEntry], rng: &mut R) -> Vec<Uuid> {
+    let mut placed: Vec<(f64, Uuid)> = Vec::with_capacity(entries.len());
+    for mut group in group_by(entries, artist_key) {
+        group.shuffle(rng);
+        let n = group.len() as f64;
+        let offset: f64 = rng.random();
+        placed.extend(group.iter().enumerate().map(|(i, e)| ((i as f64 + offset) / n, e.uuid)));
+    }
+    placed.sort_by(|a, b| a.0.total_cmp(&b.0));
+    placed.into_iter().map(|(_, uuid)| uuid).collect()
+}
+
+fn album_shuffle<R: Rng + ?Sized>(entries: &[&SongEntry], rng: &mut R) -> Vec<Uuid> {
+    let mut albums = group_by(entries, |e| {
+        e.song.album_id.clone()
+            .or_else(|| e.song.album.clone())
+            .unwrap_or_else(|| e.uuid.to_string())
+    });
+    albums.shuffle(rng);
+    albums
+        .into_iter()
+        .flat_map(|mut album| {
+            // Stable, so songs without track numbers keep their queue order
+            album.sort_by_key(|e| (e.song.disc_number.unwrap_or(0), e.song.track.unwrap_or(0)));
+            album.into_iter().map(|e| e.uuid)
+        })
+        .collect()
+}
+
+fn song_weight(song: &Song, recency: f64) -> f64 {
+    let mut weight = 1.0;
+    if song.is_starred() {
+        weight += 1.0;
+    }
+    if let Some(rating) = song.user_rating {
+        weight += (rating as f64 - 3.0).max(0.0) * 0.5;
+    }
+    weight * recency
+}
+
+/// Weighted random order (Efraimidis-Spirakis), songs with a bigger weight are more likely to come early
+fn weighted<R: Rng + ?Sized>(entries: &[&SongEntry], rng: &mut R) -> Vec<Uuid> {
+    // Dates are ISO 8601, so they sort as text. Never played songs count as the least recent.
+    let mut by_played: Vec<&SongEntry> = entries.to_vec();
+    by_played.sort_by(|a, b| a.song.played.cmp(&b.song.played));
+    let count = by_played.len().max(1) as f64;
+    let recency: HashMap<Uuid, f64> = by_played
+        .iter()
+        .enumerate()
+        .map(|(i, e)| (e.uuid, 1.5 - i as f64 / count))
+        .collect();
+
+    let mut keyed: Vec<(f64, Uuid)> = entries
+        .iter()
+        .map(|e| {
+            let weight = song_weight(&e.song, recency[&e.uuid]);
+            let u: f64 = rng.random();
+            (u.powf(1.0 / weight), e.uuid)
+        })
+        .collect();
+    keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+    keyed.into_iter().map(|(_, uuid)| uuid).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    fn entry(id: &str, artist: &str, album: &str, track: i32) -> SongEntry {
+        SongEntry {
+            uuid: Uuid::new_v4(),
+            song: Rc::new(Song {
+                id: id.to_string(),
+                title: id.to_string(),
+                artist_id: Some(artist.to_string()),
+                album_id: Some(album.to_string()),
+                track: Some(track),
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn library() -> Vec<SongEntry> {
+        vec![
+            entry("a1", "a", "a-album", 1),
+            entry("a2", "a", "a-album", 2),
+            entry("a3", "a", "a-album", 3),
+            entry("b1", "b", "b-album", 1),
+            entry("b2", "b", "b-album", 2),
+            entry("b3", "b", "b-album", 3),
+            entry("c1", "c", "c-album", 1),
+            entry("c2", "c", "c-album", 2),
+        ]
+    }
+
+    fn ids(entries: &[SongEntry], order: &[Uuid]) -> Vec<String> {
+        order.iter()
+            .map(|uuid| entries.iter().find(|e| e.uuid == *uuid).unwrap().song.id.clone())
+            .collect()
+    }
+
+    #[test]
+    fn every_mode_keeps_all_songs() {
+        let entries = library();
+        let refs: Vec<&SongEntry> = entries.iter().collect();
+        for mode in [ShuffleMode::Random, ShuffleMode::ArtistSpread, ShuffleMode::Album, ShuffleMode::Weighted] {
+            let mut order = shuffle(&refs, mode, &mut rand::rng());
+            order.sort();
+            let mut expected: Vec<Uuid> = entries.iter().map(|e| e.uuid).collect();
+            expected.sort();
+            assert_eq!(order, expected, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn artist_spread_never_repeats_an_artist_when_it_can_avoid_it() {
+        let entries = library();
+        let refs: Vec<&SongEntry> = entries.iter().collect();
+        for _ in 0..50 {
+            let order = ids(&entries, &shuffle(&refs, ShuffleMode::ArtistSpread, &mut rand::rng()));
+            for pair in order.windows(2) {
+                assert_ne!(pair[0][..1], pair[1][..1], "{:?}", order);
+            }
+        }
+    }
+
+    #[test]
+    fn album_shuffle_keeps_track_order() {
+        let mut entries = library();
+        entries.reverse();
+        let refs: Vec<&SongEntry> = entries.iter().collect();
+        let order = ids(&entries, &shuffle(&refs, ShuffleMode::Album, &mut rand::rng()));
+        for album in ["a", "b", "c"] {
+            let tracks: Vec<&String> = order.iter().filter(|id| id.starts_with(album)).collect();
+            let mut sorted = tracks.clone();
+            sorted.sort();
+            assert_eq!(tracks, sorted);
+            // Albums aren't split up
+            let first = order.iter().position(|id| id.starts_with(album)).unwrap();
+            assert!(order[first..first + tracks.len()].iter().all(|id| id.starts_with(album)));
+        }
+    }
+}
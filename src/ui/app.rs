@@ -2,10 +2,10 @@ use crate::dbus::player::MprisPlayer;
 use crate::opensonic::cache::{AlbumCache, ArtistCache, CoverCache, LyricsCache, SongCache, SuperCache};
 use crate::ui::browse::search::SearchType;
 use crate::ui::browse::{BrowseMsg, BrowseMsgOut, BrowseWidget};
-use crate::ui::current_song::{CurrentSong, CurrentSongOut};
+use crate::ui::current_song::{CurrentSong, CurrentSongMsg, CurrentSongOut};
 use crate::ui::preferences_view::{PreferencesOut, PreferencesWidget};
 use crate::ui::random_songs_dialog::RandomSongsDialog;
-use crate::ui::track_list::TrackListWidget;
+use crate::ui::track_list::{TrackListMsg, TrackListWidget};
 use async_channel::Receiver;
 use color_thief::Color;
 use gtk::prelude::GtkWindowExt;
@@ -25,7 +25,8 @@ use relm4::prelude::*;
 use relm4::{adw, component::{AsyncComponent, AsyncComponentParts, AsyncComponentSender}};
 use std::cell::LazyCell;
 use std::rc::Rc;
-use crate::{APP_ID, VERSION_STR};
+use crate::{icon_names, logging, APP_ID, VERSION_STR};
+use tracing::error;
 use crate::ui::bottom_bar::{BottomBar, BottomBarOut};
 use crate::ui::header_bar::HeaderBar;
 use crate::ui::info_dialog::{InfoDialogUpdate, InfoDialogWidget};
@@ -67,12 +68,24 @@ pub enum AppMsg {
     Restart,
     ReloadPlayer,
     ShowError(String, String),
+    /// Toast offering to undo a queue edit
+    ShowUndoToast(String),
+    Undo,
+    Redo,
     PlayPause,
     Next,
     Previous,
     CloseRequest,
     ShowSong,
     Search,
+    SearchActivated,
+    SearchFor(String),
+    ClearSearchHistory,
+    ViewAllAlbums,
+    ViewYears,
+    ViewAllSongs,
+    ViewHistory,
+    ViewYear(String),
     ViewAlbum(String, Option<u32>),
     ViewSong(String),
     ShowRandomSongsDialog,
@@ -84,7 +97,19 @@ pub enum AppMsg {
     PlayAlbum(String),
     QueueSong(String),
     QueueAlbum(String),
-    PlayArtistRadio(String)
+    PlayArtistRadio(String),
+    PlayNextSong(String),
+    PlayNextAlbum(String),
+    PlayArtist(String, bool),
+    ViewSongArtist(String),
+    ViewAlbumArtist(String),
+    StartRadio(String),
+    ToggleStarSong(String),
+    ToggleStarAlbum(String),
+    ToggleStarArtist(String),
+    CopyLink(String),
+    ShowInQueue(String),
+    ImportLyrics,
 }
 
 pub type Init = (
@@ -137,6 +162,30 @@ relm4::new_stateful_action!(pub PlayAlbum, WindowActionGroup, "play.album", Stri
 relm4::new_stateful_action!(pub QueueSong, WindowActionGroup, "queue.song", String, u8);
 relm4::new_stateful_action!(pub QueueAlbum, WindowActionGroup, "queue.album", String, u8);
 relm4::new_stateful_action!(pub PlayArtistRadio, WindowActionGroup, "artist.radio", String, u8);
+relm4::new_stateless_action!(pub ImportLyricsAction, WindowActionGroup, "lyrics.import");
+relm4::new_stateful_action!(pub SearchHistoryAction, WindowActionGroup, "search.history", String, u8);
+relm4::new_stateless_action!(pub ClearSearchHistoryAction, WindowActionGroup, "search.history.clear");
+relm4::new_stateless_action!(pub ViewAllAlbumsAction, WindowActionGroup, "albums");
+relm4::new_stateless_action!(pub ViewYearsAction, WindowActionGroup, "years");
+relm4::new_stateless_action!(pub ViewAllSongsAction, WindowActionGroup, "songs");
+relm4::new_stateless_action!(pub ViewHistoryAction, WindowActionGroup, "history");
+relm4::new_stateful_action!(pub ViewYearAction, WindowActionGroup, "year", String, u8);
+relm4::new_stateful_action!(pub PlayNextSong, WindowActionGroup, "play-next.song", String, u8);
+relm4::new_stateful_action!(pub PlayNextAlbum, WindowActionGroup, "play-next.album", String, u8);
+relm4::new_stateful_action!(pub PlayArtist, WindowActionGroup, "play.artist", String, u8);
+relm4::new_stateful_action!(pub ShuffleArtist, WindowActionGroup, "shuffle.artist", String, u8);
+relm4::new_stateful_action!(pub ViewSongArtist, WindowActionGroup, "song.artist", String, u8);
+relm4::new_stateful_action!(pub ViewAlbumArtist, WindowActionGroup, "album.artist", String, u8);
+relm4::new_stateful_action!(pub StartRadio, WindowActionGroup, "radio", String, u8);
+relm4::new_stateful_action!(pub StarSong, WindowActionGroup, "star.song", String, u8);
+relm4::new_stateful_action!(pub StarAlbum, WindowActionGroup, "star.album", String, u8);
+relm4::new_stateful_action!(pub StarArtist, WindowActionGroup, "star.artist", String, u8);
+relm4::new_stateful_action!(pub CopyLink, WindowActionGroup, "copy.link", String, u8);
+relm4::new_stateful_action!(pub ShowInQueue, WindowActionGroup, "queue.show", String, u8);
+relm4::new_stateless_action!(pub UndoAction, WindowActionGroup, "undo");
+relm4::new_stateless_action!(pub RedoAction, WindowActionGroup, "redo");
+
+const SEARCH_HISTORY_SIZE: usize = 10;
 
 #[relm4::component(pub async)]
 impl AsyncComponent for Model {
@@ -202,12 +251,22 @@ impl AsyncComponent for Model {
                                                 gtk::DropDown {
                                                     set_enable_search: false,
                                                     set_selected: 0,
-                                                    set_model: Some(&gtk::StringList::new(&["Song", "Album", "Artist"])),
+                                                    set_model: Some(&gtk::StringList::new(&["All", "Song", "Album", "Artist"])),
                                                     connect_selected_notify => AppMsg::Search,
                                                 },
                                                 #[name = "search_entry"]
                                                 gtk::SearchEntry {
-                                                    connect_activate => AppMsg::Search,
+                                                    set_search_delay: 300,
+                                                    connect_search_changed => AppMsg::Search,
+                                                    connect_activate => AppMsg::SearchActivated,
+                                                },
+                                                gtk::MenuButton {
+                                                    set_icon_name: "document-open-recent-symbolic",
+                                                    set_tooltip: "Recent searches",
+
+                                                    #[name = "search_history_menu"]
+                                                    #[wrap(Some)]
+                                                    set_menu_model = &gio::Menu {}
                                                 }
                                             }
                                         }
@@ -224,7 +283,20 @@ impl AsyncComponent for Model {
                                 set_content = model.current_song.widget(),
 
                                 #[template]
-                                add_top_bar = &HeaderBar{}
+                                add_top_bar = &HeaderBar{
+                                    #[template_child]
+                                    header_bar {
+                                        pack_start = &gtk::MenuButton {
+                                            set_icon_name: icon_names::shipped::SUBTITLES2,
+                                            set_tooltip: "Lyrics",
+
+                                            #[wrap(Some)]
+                                            set_menu_model = &gio::Menu {
+                                                append_item = &gio::MenuItem::new(Some("Import lyrics file…"), Some("win.lyrics.import")),
+                                            }
+                                        }
+                                    }
+                                }
                             },
                         }
                     },
@@ -360,6 +432,28 @@ impl AsyncComponent for Model {
         let queue_song_action: RelmAction<QueueSong> = Self::message_action_state_with_value(&sender, |value| AppMsg::QueueSong(value));
         let queue_album_action: RelmAction<QueueAlbum> = Self::message_action_state_with_value(&sender, |value| AppMsg::QueueAlbum(value));
         let play_artist_radio: RelmAction<PlayArtistRadio> = Self::message_action_state_with_value(&sender, |value| AppMsg::PlayArtistRadio(value));
+        let import_lyrics_action: RelmAction<ImportLyricsAction> = Self::message_action_stateless(&sender, AppMsg::ImportLyrics);
+        let search_history_action: RelmAction<SearchHistoryAction> = Self::message_action_state_with_value(&sender, |value| AppMsg::SearchFor(value));
+        let clear_search_history_action: RelmAction<ClearSearchHistoryAction> = Self::message_action_stateless(&sender, AppMsg::ClearSearchHistory);
+        let view_all_albums_action: RelmAction<ViewAllAlbumsAction> = Self::message_action_stateless(&sender, AppMsg::ViewAllAlbums);
+        let view_years_action: RelmAction<ViewYearsAction> = Self::message_action_stateless(&sender, AppMsg::ViewYears);
+        let view_all_songs_action: RelmAction<ViewAllSongsAction> = Self::message_action_stateless(&sender, AppMsg::ViewAllSongs);
+        let view_history_action: RelmAction<ViewHistoryAction> = Self::message_action_stateless(&sender, AppMsg::ViewHistory);
+        let view_year_action: RelmAction<ViewYearAction> = Self::message_action_state_with_value(&sender, |value| AppMsg::ViewYear(value));
+        let play_next_song_action: RelmAction<PlayNextSong> = Self::message_action_state_with_value(&sender, |value| AppMsg::PlayNextSong(value));
+        let play_next_album_action: RelmAction<PlayNextAlbum> = Self::message_action_state_with_value(&sender, |value| AppMsg::PlayNextAlbum(value));
+        let play_artist_action: RelmAction<PlayArtist> = Self::message_action_state_with_value(&sender, |value| AppMsg::PlayArtist(value, false));
+        let shuffle_artist_action: RelmAction<ShuffleArtist> = Self::message_action_state_with_value(&sender, |value| AppMsg::PlayArtist(value, true));
+        let view_song_artist_action: RelmAction<ViewSongArtist> = Self::message_action_state_with_value(&sender, |value| AppMsg::ViewSongArtist(value));
+        let view_album_artist_action: RelmAction<ViewAlbumArtist> = Self::message_action_state_with_value(&sender, |value| AppMsg::ViewAlbumArtist(value));
+        let start_radio_action: RelmAction<StartRadio> = Self::message_action_state_with_value(&sender, |value| AppMsg::StartRadio(value));
+        let star_song_action: RelmAction<StarSong> = Self::message_action_state_with_value(&sender, |value| AppMsg::ToggleStarSong(value));
+        let star_album_action: RelmAction<StarAlbum> = Self::message_action_state_with_value(&sender, |value| AppMsg::ToggleStarAlbum(value));
+        let star_artist_action: RelmAction<StarArtist> = Self::message_action_state_with_value(&sender, |value| AppMsg::ToggleStarArtist(value));
+        let copy_link_action: RelmAction<CopyLink> = Self::message_action_state_with_value(&sender, |value| AppMsg::CopyLink(value));
+        let show_in_queue_action: RelmAction<ShowInQueue> = Self::message_action_state_with_value(&sender, |value| AppMsg::ShowInQueue(value));
+        let undo_action: RelmAction<UndoAction> = Self::message_action_stateless(&sender, AppMsg::Undo);
+        let redo_action: RelmAction<RedoAction> = Self::message_action_stateless(&sender, AppMsg::Redo);
 
         let mut group = RelmActionGroup::<WindowActionGroup>::new();
         group.add_action(about_action);
@@ -381,9 +475,32 @@ impl AsyncComponent for Model {
         group.add_action(queue_song_action);
         group.add_action(queue_album_action);
         group.add_action(play_artist_radio);
+        group.add_action(import_lyrics_action);
+        group.add_action(search_history_action);
+        group.add_action(clear_search_history_action);
+        group.add_action(view_all_albums_action);
+        group.add_action(view_years_action);
+        group.add_action(view_all_songs_action);
+        group.add_action(view_history_action);
+        group.add_action(view_year_action);
+        group.add_action(play_next_song_action);
+        group.add_action(play_next_album_action);
+        group.add_action(play_artist_action);
+        group.add_action(shuffle_artist_action);
+        group.add_action(view_song_artist_action);
+        group.add_action(view_album_artist_action);
+        group.add_action(start_radio_action);
+        group.add_action(star_song_action);
+        group.add_action(star_album_action);
+        group.add_action(star_artist_action);
+        group.add_action(copy_link_action);
+        group.add_action(show_in_queue_action);
+        group.add_action(undo_action);
+        group.add_action(redo_action);
         group.register_for_widget(&root);
 
         widgets.search_bar.connect_entry(&widgets.search_entry);
+        Self::fill_search_history_menu(&widgets.search_history_menu, &model.settings);
         widgets.search_bar.set_key_capture_widget(Some(&root));
         root.add_breakpoint(breakpoint);
 
@@ -396,6 +513,12 @@ impl AsyncComponent for Model {
         });
         widgets.search_entry.add_controller(focus_controller);
 
+        // Not accelerators, those would take Ctrl+Z away from text fields
+        let history_shortcuts = gtk::ShortcutController::new();
+        history_shortcuts.add_shortcut(queue_history_shortcut(gdk::ModifierType::CONTROL_MASK, "win.undo"));
+        history_shortcuts.add_shortcut(queue_history_shortcut(gdk::ModifierType::CONTROL_MASK | gdk::ModifierType::SHIFT_MASK, "win.redo"));
+        root.add_controller(history_shortcuts);
+
         AsyncComponentParts { model, widgets }
     }
 
@@ -494,7 +617,7 @@ impl AsyncComponent for Model {
                     .button_label("Details")
                     .timeout(8)
                     .build();
-                eprintln!("Error occurred: {}", description);
+                error!(summary = %summary, description = %logging::redact(&description), "Error occurred");
                 toast.connect_button_clicked(clone!(
                     #[strong]
                     root,
@@ -508,6 +631,29 @@ impl AsyncComponent for Model {
                 ));
                 self.toaster.add_toast(toast);
             },
+            AppMsg::ShowUndoToast(title) => {
+                let toast = adw::Toast::builder()
+                    .title(glib::markup_escape_text(&title))
+                    .button_label("Undo")
+                    .action_name(UndoAction::action_name())
+                    .timeout(5)
+                    .build();
+                self.toaster.add_toast(toast);
+            },
+            AppMsg::Undo => {
+                match player.undo().await {
+                    Ok(true) => {},
+                    Ok(false) => self.toaster.add_toast(adw::Toast::new("Nothing to undo")),
+                    Err(err) => player.send_error(err),
+                }
+            },
+            AppMsg::Redo => {
+                match player.redo().await {
+                    Ok(true) => {},
+                    Ok(false) => self.toaster.add_toast(adw::Toast::new("Nothing to redo")),
+                    Err(err) => player.send_error(err),
+                }
+            },
             AppMsg::CloseRequest => {
                 if !self.settings.boolean("stay-in-background") {
                     sender.input(AppMsg::Quit);
@@ -521,16 +667,70 @@ impl AsyncComponent for Model {
             },
             AppMsg::Search => {
                 let search_type = match widgets.search_type.selected() {
-                    0 => SearchType::Song,
-                    1 => SearchType::Album,
-                    2 => SearchType::Artist,
+                    0 => SearchType::All,
+                    1 => SearchType::Song,
+                    2 => SearchType::Album,
+                    3 => SearchType::Artist,
                     _ => {
                         player.send_error("Invalid search type".into());
-                        SearchType::Song
+                        SearchType::All
                     },
                 };
                 self.browse_connector.emit(BrowseMsg::Search(widgets.search_entry.text().into(), search_type));
             },
+            AppMsg::SearchActivated => {
+                let query = widgets.search_entry.text().trim().to_string();
+                if !query.is_empty() {
+                    let mut history: Vec<String> = self.settings.strv("search-history")
+                        .iter()
+                        .map(|q| q.to_string())
+                        .filter(|q| *q != query)
+                        .collect();
+                    history.insert(0, query);
+                    history.truncate(SEARCH_HISTORY_SIZE);
+                    self.settings.set_strv("search-history", history).expect("Error saving search history");
+                    Self::fill_search_history_menu(&widgets.search_history_menu, &self.settings);
+                }
+                sender.input(AppMsg::Search);
+            },
+            AppMsg::SearchFor(query) => {
+                widgets.search_bar.set_search_mode(true);
+                // Searching is triggered by the entry's search-changed signal
+                widgets.search_entry.set_text(&query);
+            },
+            AppMsg::ClearSearchHistory => {
+                self.settings.set_strv("search-history", Vec::<String>::new()).expect("Error saving search history");
+                Self::fill_search_history_menu(&widgets.search_history_menu, &self.settings);
+            },
+            AppMsg::ViewAllAlbums => {
+                widgets.nav_view.pop_to_tag("base");
+                // Popping the page pops a color set, so push the default one
+                sender.input(AppMsg::PushViewColors(None));
+                self.browse_connector.emit(BrowseMsg::ViewAllAlbums(None));
+            },
+            AppMsg::ViewYears => {
+                widgets.nav_view.pop_to_tag("base");
+                sender.input(AppMsg::PushViewColors(None));
+                self.browse_connector.emit(BrowseMsg::ViewYears);
+            },
+            AppMsg::ViewAllSongs => {
+                widgets.nav_view.pop_to_tag("base");
+                sender.input(AppMsg::PushViewColors(None));
+                self.browse_connector.emit(BrowseMsg::ViewAllSongs);
+            },
+            AppMsg::ViewHistory => {
+                widgets.nav_view.pop_to_tag("base");
+                sender.input(AppMsg::PushViewColors(None));
+                self.browse_connector.emit(BrowseMsg::ViewHistory);
+            },
+            AppMsg::ViewYear(year) => match year.parse::<u32>() {
+                Ok(year) => {
+                    widgets.nav_view.pop_to_tag("base");
+                    sender.input(AppMsg::PushViewColors(None));
+                    self.browse_connector.emit(BrowseMsg::ViewAllAlbums(Some((year, year))));
+                },
+                Err(err) => self.mpris_player.imp().send_error(err.into()),
+            },
             AppMsg::ViewAlbum(album, highlight) => {
                 match self.album_cache.get_album(&album).await {
                     Ok(album) => {
@@ -653,6 +853,73 @@ impl AsyncComponent for Model {
             AppMsg::PlayArtistRadio(id) => {
                 player.send_res(player.queue_similar_songs_for_id(id.as_str(), true, None).await);
             },
+            AppMsg::PlayNextSong(id) => {
+                match self.song_cache.get_song(&id).await {
+                    Ok(song) => player.send_res(player.play_next(vec![song]).await),
+                    Err(err) => player.send_error(err),
+                }
+            },
+            AppMsg::PlayNextAlbum(id) => {
+                match player.album_songs(&id).await {
+                    Ok(songs) => player.send_res(player.play_next(songs).await),
+                    Err(err) => player.send_error(err),
+                }
+            },
+            AppMsg::PlayArtist(id, shuffle) => {
+                player.send_res(player.queue_artist(&id, shuffle, true).await);
+            },
+            AppMsg::ViewSongArtist(id) => {
+                match self.song_cache.get_song(&id).await {
+                    Ok(song) => {
+                        let artist_id = song.artist_id.clone()
+                            .or_else(|| song.artists.as_ref().and_then(|a| a.first()).map(|a| a.id.clone()));
+                        match artist_id {
+                            Some(artist_id) => sender.input(AppMsg::ViewArtist(artist_id)),
+                            None => player.send_error("Song has no artist".into()),
+                        }
+                    },
+                    Err(err) => player.send_error(err),
+                }
+            },
+            AppMsg::ViewAlbumArtist(id) => {
+                match self.album_cache.get_album(&id).await {
+                    Ok(album) => match album.get_inner().and_then(|a| a.artist_id) {
+                        Some(artist_id) => sender.input(AppMsg::ViewArtist(artist_id)),
+                        None => player.send_error("Album has no artist".into()),
+                    },
+                    Err(err) => player.send_error(err),
+                }
+            },
+            AppMsg::StartRadio(id) => {
+                player.send_res(player.queue_similar_songs_for_id(id.as_str(), true, None).await);
+            },
+            AppMsg::ToggleStarSong(id) => {
+                match self.song_cache.get_song(&id).await {
+                    Ok(song) => player.send_res(self.song_cache.toggle_starred(&song).await),
+                    Err(err) => player.send_error(err),
+                }
+            },
+            AppMsg::ToggleStarAlbum(id) => {
+                match self.album_cache.get_album(&id).await {
+                    Ok(album) => player.send_res(self.album_cache.toggle_starred(&album).await),
+                    Err(err) => player.send_error(err),
+                }
+            },
+            AppMsg::ToggleStarArtist(id) => {
+                match self.artist_cache.get_artist(&id).await {
+                    Ok(artist) => player.send_res(self.artist_cache.toggle_starred(&artist).await),
+                    Err(err) => player.send_error(err),
+                }
+            },
+            AppMsg::CopyLink(uri) => {
+                root.clipboard().set_text(&uri);
+                self.toaster.add_toast(adw::Toast::new("Link copied"));
+            },
+            AppMsg::ShowInQueue(id) => {
+                widgets.split_view.set_show_sidebar(true);
+                self.track_list_connector.emit(TrackListMsg::ShowSong(id));
+            },
+            AppMsg::ImportLyrics => self.current_song.emit(CurrentSongMsg::ImportLyrics),
         };
         self.update_view(widgets, sender);
     }
@@ -687,6 +954,21 @@ impl Model {
         ))
     }
 
+    fn fill_search_history_menu(menu: &gio::Menu, settings: &Settings) {
+        menu.remove_all();
+        let history = settings.strv("search-history");
+        let recent = gio::Menu::new();
+        for query in history.iter() {
+            let item = gio::MenuItem::new(Some(query.as_str()), None);
+            item.set_action_and_target_value(Some("win.search.history"), Some(&query.as_str().to_variant()));
+            recent.append_item(&item);
+        }
+        menu.append_section(None, &recent);
+        if !history.is_empty() {
+            menu.append(Some("Clear history"), Some("win.search.history.clear"));
+        }
+    }
+
     fn vec_to_arr(colors: Option<Vec<Color>>) -> Option<[Color; BG_COLORS]> {
         if let Some(color) = colors {
             let mut it = color.into_iter().cycle();
@@ -732,3 +1014,19 @@ impl Model {
         root.action_set_enabled("win.enable-recoloring", true);
     }
 }
+
+/// Ctrl+Z with `modifiers` running `action`, unless a text field has focus, which keeps its own undo
+fn queue_history_shortcut(modifiers: gdk::ModifierType, action: &'static str) -> gtk::Shortcut {
+    gtk::Shortcut::new(
+        Some(gtk::KeyvalTrigger::new(gdk::Key::z, modifiers)),
+        Some(gtk::CallbackAction::new(move |widget, _| {
+            let editing = widget.root()
+                .and_then(|root| root.focus())
+                .is_some_and(|focus| focus.is::<gtk::Editable>() || focus.is::<gtk::TextView>());
+            if editing || widget.activate_action(action, None).is_err() {
+                return glib::Propagation::Proceed;
+            }
+            glib::Propagation::Stop
+        })),
+    )
+}
@@ -0,0 +1,271 @@
+use std::error::Error;
+use std::rc::Rc;
+
+use mpris_server::LocalServer;
+use relm4::adw::gio;
+use relm4::adw::glib::{clone, Object};
+use relm4::adw::prelude::*;
+use relm4::{adw, gtk};
+
+use crate::dbus::player::MprisPlayer;
+use crate::opensonic::types::Song;
+use crate::ui::album_object::AlbumObject;
+use crate::ui::artist_object::ArtistObject;
+use crate::ui::song_object::SongObject;
+
+const GROUP: &str = "batch";
+
+#[derive(Clone, Copy)]
+enum AddMode {
+    Play,
+    Queue,
+    PlayNext,
+}
+
+/// Installs the `batch.*` actions on a list view with a `gtk::MultiSelection` model.
+/// The actions act on whatever is selected when they're activated.
+/// `in_queue` adds removing, with the selected positions being indexes into the queue.
+pub fn install(list: &gtk::ListView, mpris_player: &Rc<LocalServer<MprisPlayer>>, in_queue: bool) {
+    let group = gio::SimpleActionGroup::new();
+
+    for (name, mode) in [("play", AddMode::Play), ("queue", AddMode::Queue), ("play-next", AddMode::PlayNext)] {
+        let action = gio::SimpleAction::new(name, None);
+        action.connect_activate(clone!(
+            #[weak]
+            list,
+            #[strong]
+            mpris_player,
+            move |_, _| {
+                let items = selected_items(&list);
+                let mpris_player = mpris_player.clone();
+                relm4::spawn_local(async move {
+                    let player = mpris_player.imp();
+                    player.send_res(add_songs(&items, mode, player).await);
+                });
+            }
+        ));
+        group.add_action(&action);
+    }
+
+    for (name, starred) in [("star", true), ("unstar", false)] {
+        let action = gio::SimpleAction::new(name, None);
+        action.connect_activate(clone!(
+            #[weak]
+            list,
+            #[strong]
+            mpris_player,
+            move |_, _| {
+                let items = selected_items(&list);
+                let mpris_player = mpris_player.clone();
+                relm4::spawn_local(async move {
+                    let player = mpris_player.imp();
+                    player.send_res(set_starred(&items, starred, player).await);
+                });
+            }
+        ));
+        group.add_action(&action);
+    }
+
+    let playlist_action = gio::SimpleAction::new("playlist", Some(&String::static_variant_type()));
+    playlist_action.connect_activate(clone!(
+        #[weak]
+        list,
+        #[strong]
+        mpris_player,
+        move |_, param| {
+            let Some(playlist_id) = param.and_then(|p| p.get::<String>()) else {
+                return;
+            };
+            let items = selected_items(&list);
+            let mpris_player = mpris_player.clone();
+            relm4::spawn_local(async move {
+                let player = mpris_player.imp();
+                player.send_res(add_to_playlist(&items, Some(&playlist_id), None, player).await);
+            });
+        }
+    ));
+    group.add_action(&playlist_action);
+
+    let new_playlist_action = gio::SimpleAction::new("new-playlist", None);
+    new_playlist_action.connect_activate(clone!(
+        #[weak]
+        list,
+        #[strong]
+        mpris_player,
+        move |_, _| {
+            let items = selected_items(&list);
+            let entry = gtk::Entry::builder()
+                .placeholder_text("Playlist name")
+                .activates_default(true)
+                .build();
+            let dialog = adw::AlertDialog::new(Some("New playlist"), None);
+            dialog.set_extra_child(Some(&entry));
+            dialog.add_responses(&[("cancel", "Cancel"), ("create", "Create")]);
+            dialog.set_default_response(Some("create"));
+            dialog.set_close_response("cancel");
+            dialog.set_response_appearance("create", adw::ResponseAppearance::Suggested);
+            dialog.choose(Some(&list), None::<&gio::Cancellable>, clone!(
+                #[strong]
+                mpris_player,
+                move |response| {
+                    let name = entry.text().trim().to_string();
+                    if response != "create" || name.is_empty() {
+                        return;
+                    }
+                    relm4::spawn_local(async move {
+                        let player = mpris_player.imp();
+                        player.send_res(add_to_playlist(&items, None, Some(&name), player).await);
+                    });
+                }
+            ));
+        }
+    ));
+    group.add_action(&new_playlist_action);
+
+    if in_queue {
+        let remove_action = gio::SimpleAction::new("remove", None);
+        remove_action.connect_activate(clone!(
+            #[weak]
+            list,
+            #[strong]
+            mpris_player,
+            move |_, _| {
+                let indexes = selected_positions(&list).into_iter().map(|i| i as usize).collect();
+                let mpris_player = mpris_player.clone();
+                relm4::spawn_local(async move {
+                    let player = mpris_player.imp();
+                    player.send_res(player.remove_many(indexes).await);
+                });
+            }
+        ));
+        group.add_action(&remove_action);
+    }
+
+    list.insert_action_group(GROUP, Some(&group));
+}
+
+/// Menu for the `batch.*` actions, playlists are filled in once they're loaded
+pub fn make_popup_menu(count: u32, mpris_player: &Rc<LocalServer<MprisPlayer>>, in_queue: bool) -> gtk::PopoverMenu {
+    let menu = gio::Menu::new();
+
+    let play_section = gio::Menu::new();
+    play_section.append(Some(&format!("Play {} items", count)), Some("batch.play"));
+    play_section.append(Some("Play next"), Some("batch.play-next"));
+    play_section.append(Some("Add to queue"), Some("batch.queue"));
+    menu.append_section(None, &play_section);
+
+    let edit_section = gio::Menu::new();
+    edit_section.append(Some("Star"), Some("batch.star"));
+    edit_section.append(Some("Unstar"), Some("batch.unstar"));
+    let playlist_menu = gio::Menu::new();
+    playlist_menu.append(Some("New playlist…"), Some("batch.new-playlist"));
+    let playlists_section = gio::Menu::new();
+    playlist_menu.append_section(None, &playlists_section);
+    edit_section.append_submenu(Some("Add to playlist"), &playlist_menu);
+    if in_queue {
+        edit_section.append(Some("Remove from queue"), Some("batch.remove"));
+    }
+    menu.append_section(None, &edit_section);
+
+    let mpris_player = mpris_player.clone();
+    relm4::spawn_local(async move {
+        let player = mpris_player.imp();
+        match player.client.get_playlists().await {
+            Ok(playlists) => for playlist in playlists {
+                let item = gio::MenuItem::new(Some(&playlist.name), None);
+                item.set_action_and_target_value(Some("batch.playlist"), Some(&playlist.id.to_variant()));
+                playlists_section.append_item(&item);
+            },
+            Err(err) => player.send_error(err),
+        }
+    });
+
+    gtk::PopoverMenu::from_model_full(&menu, gtk::PopoverMenuFlags::NESTED)
+}
+
+fn selected_positions(list: &gtk::ListView) -> Vec<u32> {
+    let Some(selection) = list.model().and_downcast::<gtk::MultiSelection>() else {
+        return Vec::new();
+    };
+    let bitset = selection.selection();
+    (0..bitset.size() as u32).map(|i| bitset.nth(i)).collect()
+}
+
+fn selected_items(list: &gtk::ListView) -> Vec<Object> {
+    let Some(model) = list.model() else {
+        return Vec::new();
+    };
+    selected_positions(list)
+        .into_iter()
+        .filter_map(|i| model.item(i))
+        .collect()
+}
+
+/// Songs of the selected items, albums and artists are expanded to all their songs
+async fn resolve_songs(items: &[Object], player: &MprisPlayer) -> Result<Vec<Rc<Song>>, Box<dyn Error>> {
+    let mut songs = Vec::new();
+    for item in items {
+        if let Some(song) = item.downcast_ref::<SongObject>() {
+            songs.extend(song.get_entry());
+        } else if let Some(album) = item.downcast_ref::<AlbumObject>() {
+            songs.extend(player.album_songs(&album.id()).await?);
+        } else if let Some(artist) = item.downcast_ref::<ArtistObject>() {
+            songs.extend(player.artist_songs(&artist.id()).await?);
+        }
+    }
+    Ok(songs)
+}
+
+async fn add_songs(items: &[Object], mode: AddMode, player: &MprisPlayer) -> Result<(), Box<dyn Error>> {
+    let songs = resolve_songs(items, player).await?;
+    match mode {
+        AddMode::Play => player.queue_songs(songs, Some(0), true).await,
+        AddMode::Queue => player.queue_songs(songs, None, false).await,
+        AddMode::PlayNext => player.play_next(songs).await,
+    }
+}
+
+async fn set_starred(items: &[Object], starred: bool, player: &MprisPlayer) -> Result<(), Box<dyn Error>> {
+    let mut song_ids = Vec::new();
+    let mut album_ids = Vec::new();
+    let mut artist_ids = Vec::new();
+    for item in items {
+        if let Some(song) = item.downcast_ref::<SongObject>() {
+            song_ids.push(song.id());
+        } else if let Some(album) = item.downcast_ref::<AlbumObject>() {
+            album_ids.push(album.id());
+        } else if let Some(artist) = item.downcast_ref::<ArtistObject>() {
+            artist_ids.push(artist.id());
+        }
+    }
+    let as_strs = |ids: &Vec<String>| ids.iter().map(String::as_str).collect::<Vec<&str>>();
+    if starred {
+        player.client.star(as_strs(&song_ids), as_strs(&album_ids), as_strs(&artist_ids)).await?;
+    } else {
+        player.client.unstar(as_strs(&song_ids), as_strs(&album_ids), as_strs(&artist_ids)).await?;
+    }
+
+    for item in items {
+        if let Some(song) = item.downcast_ref::<SongObject>() {
+            if let Some(song) = song.get_entry() {
+                song.starred.replace(if starred { Some("yes".into()) } else { None });
+            }
+        } else if let Some(album) = item.downcast_ref::<AlbumObject>() {
+            album.set_starred(starred);
+        } else if let Some(artist) = item.downcast_ref::<ArtistObject>() {
+            artist.set_starred(starred);
+        }
+    }
+    Ok(())
+}
+
+/// Adds the songs of the selected items to an existing playlist, or to a new one with the given name
+async fn add_to_playlist(items: &[Object], playlist_id: Option<&str>, new_name: Option<&str>, player: &MprisPlayer) -> Result<(), Box<dyn Error>> {
+    let songs = resolve_songs(items, player).await?;
+    let song_ids: Vec<&str> = songs.iter().map(|s| s.id.as_str()).collect();
+    match (playlist_id, new_name) {
+        (Some(id), _) => player.client.update_playlist(id, song_ids).await,
+        (None, Some(name)) => player.client.create_playlist(name, song_ids).await,
+        (None, None) => Err("No playlist specified".into()),
+    }
+}
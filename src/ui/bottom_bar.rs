@@ -19,6 +19,8 @@ use crate::player::SongEntry;
 use crate::ui::app::{Init, NextAction, PlayPauseAction, PreviousAction, ShowRandomSongsAction, ShowTracklistAction};
 use crate::ui::cover_picture::{CoverPicture, CoverSize};
 use crate::ui::current_song::CurrentSongMsg;
+use crate::ui::info_dialog;
+use crate::ui::song_object::{PositionState, SongObject};
 
 pub struct BottomBar {
     mpris_player: Rc<LocalServer<MprisPlayer>>,
@@ -364,6 +366,27 @@ impl AsyncComponent for BottomBar {
         ));
         widgets.start_box.add_controller(gesture);
 
+        let menu_gesture = gtk::GestureClick::builder()
+            .button(3)
+            .build();
+        menu_gesture.connect_pressed(clone!(
+            #[strong(rename_to = mpris_player)]
+            model.mpris_player,
+            #[weak(rename_to = start_box)]
+            widgets.start_box,
+            move |this, _n, x, y| {
+                this.set_state(gtk::EventSequenceState::Claimed);
+                let Some(entry) = mpris_player.imp().track_list().borrow().current().cloned() else {
+                    return;
+                };
+                let menu = info_dialog::make_popup_menu(&SongObject::new(entry, PositionState::Current), false);
+                menu.set_parent(&start_box);
+                menu.set_pointing_to(Some(&gdk::Rectangle::new(x as i32, y as i32, 1, 1)));
+                menu.popup();
+            }
+        ));
+        widgets.start_box.add_controller(menu_gesture);
+
         widgets.like_btn
             .property_expression("active")
             .chain_closure::<String>(closure!(
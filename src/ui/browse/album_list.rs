@@ -1,8 +1,20 @@
+use std::rc::Rc;
+use mpris_server::LocalServer;
 use relm4::WidgetTemplate;
+use relm4::adw::gdk;
+use relm4::adw::glib::{clone, closure, Object};
 use relm4::adw::gtk;
 use relm4::adw::prelude::*;
 use relm4::adw::gtk::{Orientation, Align};
+use relm4::gtk::pango::EllipsizeMode;
+use relm4::gtk::{glib, ListItem, SignalListItemFactory, Widget};
+use crate::dbus::player::MprisPlayer;
 use crate::icon_names;
+use crate::opensonic::cache::{AlbumCache, CoverCache};
+use crate::ui::album_object::AlbumObject;
+use crate::ui::cover_picture::{CoverPicture, CoverSize};
+use crate::ui::drag_and_drop;
+use crate::ui::info_dialog;
 
 #[relm4::widget_template(pub)]
 impl WidgetTemplate for AlbumList {
@@ -60,3 +72,154 @@ impl WidgetTemplate for AlbumList {
         }
     }
 }
+
+/// Sets up a factory for album entries with cover, star and play buttons, as used in album grids and shelves
+pub fn setup_album_factory(
+    factory: &SignalListItemFactory,
+    cover_cache: &CoverCache,
+    album_cache: &AlbumCache,
+    mpris_player: &Rc<LocalServer<MprisPlayer>>,
+) {
+    factory.connect_setup(clone!(
+        #[strong]
+        cover_cache,
+        #[strong]
+        album_cache,
+        #[strong]
+        mpris_player,
+        move |_, list_item| {
+            let vbox = gtk::Box::builder()
+                .orientation(Orientation::Vertical)
+                .spacing(3)
+                .build();
+            vbox.add_css_class("album-entry");
+
+            let overlay = gtk::Overlay::new();
+            let cover_picture = CoverPicture::new(cover_cache.clone(), CoverSize::Large);
+            overlay.set_child(Some(&cover_picture));
+            overlay.set_halign(Align::Center);
+            overlay.set_valign(Align::Center);
+            let overlay_box = gtk::Box::new(Orientation::Horizontal, 5);
+            overlay_box.set_halign(Align::End);
+            overlay_box.set_valign(Align::End);
+            let play_btn = gtk::Button::new();
+            play_btn.set_icon_name(icon_names::shipped::PLAY);
+            play_btn.add_css_class("flat");
+            play_btn.set_tooltip("Play");
+            let like_btn = gtk::ToggleButton::new();
+            like_btn.add_css_class("flat");
+            like_btn.set_tooltip("Star");
+            like_btn
+                .property_expression("active")
+                .chain_closure::<String>(closure!(
+                    move |_: Option<Object>, active: bool| {
+                        if active {
+                            icon_names::shipped::HEART_FILLED
+                        } else {
+                            icon_names::shipped::HEART_OUTLINE_THIN
+                        }
+                    }
+                ))
+                .bind(&like_btn, "icon-name", Widget::NONE);
+            overlay_box.append(&like_btn);
+            overlay_box.append(&play_btn);
+            overlay.add_overlay(&overlay_box);
+            vbox.append(&overlay);
+
+            let name = gtk::Label::builder().css_classes(["bold"]).build();
+            let artist = gtk::Label::new(None);
+            name.set_width_chars(25);
+            name.set_ellipsize(EllipsizeMode::End);
+            artist.set_width_chars(25);
+            artist.set_use_markup(true);
+            artist.set_ellipsize(EllipsizeMode::End);
+            vbox.append(&name);
+            vbox.append(&artist);
+
+            artist.connect_activate_link(move |this, url| {
+                this.activate_action("win.artist", Some(&url.to_variant())).expect("Error executing action");
+                glib::Propagation::Stop
+            });
+
+            let list_item = list_item
+                .downcast_ref::<ListItem>()
+                .expect("Needs to be ListItem");
+            list_item.set_child(Some(&vbox));
+            
+            let ctrl = gtk::GestureClick::builder()
+                .button(3)
+                .build();
+            ctrl.connect_pressed(clone!(
+                #[weak]
+                list_item,
+                #[weak]
+                vbox,
+                move |_controller, _btn, x, y| {
+                    let item = list_item.item().expect("Expected ListItem to have item");
+                    let menu = info_dialog::make_popup_menu(&item, false);
+                    menu.set_parent(&vbox);
+                    menu.set_pointing_to(Some(&gdk::Rectangle::new(x as i32, y as i32, 1, 1)));
+                    menu.popup();
+                }
+            ));
+            vbox.add_controller(ctrl);
+            drag_and_drop::add_drag_source(&vbox, clone!(
+                #[weak]
+                list_item,
+                #[upgrade_or_default]
+                move || drag_and_drop::row_items(&list_item, None)
+            ));
+
+            play_btn.connect_clicked(clone!(
+                #[weak]
+                list_item,
+                #[strong]
+                mpris_player,
+                move |_this| {
+                    if let Some(item) = list_item.item() {
+                        let album = item.downcast::<AlbumObject>().expect("Item should be AlbumObject");
+                        let mpris_player = mpris_player.clone();
+                        relm4::spawn_local(async move {
+                            mpris_player.imp().send_res(mpris_player.imp().queue_album(album.id(), None, true).await);
+                        });
+                    }
+                }
+            ));
+            like_btn.connect_clicked(clone!(
+                #[weak]
+                list_item,
+                #[strong]
+                album_cache,
+                #[strong]
+                mpris_player,
+                move |_this| {
+                    if let Some(item) = list_item.item() {
+                        let album = item.downcast::<AlbumObject>().expect("Item should be AlbumObject");
+                        let album_cache = album_cache.clone();
+                        let mpris_player = mpris_player.clone();
+                        relm4::spawn_local(async move {
+                            mpris_player.imp().send_res(album_cache.toggle_starred(&album).await);
+                        });
+                    }
+                }
+            ));
+
+            list_item
+                .property_expression("item")
+                .chain_property::<AlbumObject>("name")
+                .bind(&name, "label", Widget::NONE);
+            list_item
+                .property_expression("item")
+                .chain_property::<AlbumObject>("artist")
+                .bind(&artist, "label", Widget::NONE);
+            list_item
+                .property_expression("item")
+                .chain_property::<AlbumObject>("cover-art-id")
+                .bind(&cover_picture, "cover-id", Widget::NONE);
+            list_item
+                .property_expression("item")
+                .chain_property::<AlbumObject>("starred")
+                .bind(&like_btn, "active", Widget::NONE);
+        }
+    ));
+}
@@ -0,0 +1,265 @@
+use std::rc::Rc;
+
+use mpris_server::LocalServer;
+use relm4::adw::gio::ListStore;
+use relm4::adw::prelude::*;
+use relm4::gtk::{Align, Orientation, SignalListItemFactory};
+use relm4::prelude::*;
+use relm4::{adw, gtk, AsyncComponentSender, RelmWidgetExt};
+
+use crate::dbus::player::MprisPlayer;
+use crate::opensonic::cache::{AlbumCache, CoverCache};
+use crate::opensonic::types::AlbumListType;
+use crate::ui::album_object::AlbumObject;
+use crate::ui::browse::album_list;
+use crate::ui::item_list::PageState;
+
+const PAGE_SIZE: u32 = 40;
+const SORT_MODES: [(&str, AlbumListType); 8] = [
+    ("Name", AlbumListType::AlphabeticalByName),
+    ("Artist", AlbumListType::AlphabeticalByArtist),
+    ("Newest", AlbumListType::Newest),
+    ("Recently played", AlbumListType::Recent),
+    ("Most played", AlbumListType::Frequent),
+    ("Highest rated", AlbumListType::Highest),
+    ("Starred", AlbumListType::Starred),
+    ("Random", AlbumListType::Random),
+];
+const MAX_YEAR: u32 = 9999;
+
+pub struct AllAlbumsWidget {
+    album_cache: AlbumCache,
+    mpris_player: Rc<LocalServer<MprisPlayer>>,
+    store: ListStore,
+    factory: SignalListItemFactory,
+    page_state: PageState,
+    /// Incremented whenever the list is reloaded, so pages requested for an older sort are dropped
+    generation: u64,
+}
+
+#[derive(Debug)]
+pub enum AllAlbumsMsg {
+    Reload,
+    LoadMore,
+    PageLoaded(u64, Vec<AlbumObject>),
+    PageFailed(u64),
+}
+
+/// The last element optionally preselects a year range
+pub type AllAlbumsInit = (Rc<LocalServer<MprisPlayer>>, CoverCache, AlbumCache, Option<(u32, u32)>);
+
+#[relm4::component(pub async)]
+impl AsyncComponent for AllAlbumsWidget {
+    type CommandOutput = ();
+    type Input = AllAlbumsMsg;
+    type Output = ();
+    type Init = AllAlbumsInit;
+
+    view! {
+        adw::NavigationPage {
+            set_title: "All albums",
+
+            gtk::Box {
+                set_orientation: Orientation::Vertical,
+
+                gtk::Box {
+                    set_orientation: Orientation::Horizontal,
+                    set_spacing: 5,
+                    add_css_class: "padded",
+
+                    gtk::Label {
+                        set_label: "Sort by",
+                    },
+                    #[name = "sort"]
+                    gtk::DropDown {
+                        set_enable_search: false,
+                        set_model: Some(&gtk::StringList::new(&SORT_MODES.map(|(name, _)| name))),
+                        set_tooltip: "Albums are sorted by year while filtering by year",
+                        connect_selected_notify => AllAlbumsMsg::Reload,
+                    },
+                    gtk::Label {
+                        set_label: "Years",
+                        set_margin_start: 10,
+                    },
+                    #[name = "year_from"]
+                    gtk::SpinButton::with_range(0.0, MAX_YEAR as f64, 1.0) {
+                        set_tooltip: "0 for any",
+                        connect_value_changed => AllAlbumsMsg::Reload,
+                    },
+                    gtk::Label {
+                        set_label: "to",
+                    },
+                    #[name = "year_to"]
+                    gtk::SpinButton::with_range(0.0, MAX_YEAR as f64, 1.0) {
+                        set_tooltip: "0 for any",
+                        connect_value_changed => AllAlbumsMsg::Reload,
+                    },
+                },
+                #[name = "scrolled"]
+                gtk::ScrolledWindow {
+                    set_hscrollbar_policy: gtk::PolicyType::Never,
+                    set_vexpand: true,
+                    set_valign: Align::Fill,
+                    connect_edge_reached[sender] => move |_, pos| {
+                        if pos == gtk::PositionType::Bottom {
+                            sender.input(AllAlbumsMsg::LoadMore);
+                        }
+                    },
+
+                    gtk::GridView {
+                        set_factory: Some(&model.factory),
+                        set_model: Some(&gtk::NoSelection::new(Some(model.store.clone()))),
+                        set_single_click_activate: true,
+                        set_max_columns: 12,
+                        add_css_class: "no-bg",
+                        connect_activate => move |view, index| {
+                            if let Some(model) = view.model() {
+                                let album: AlbumObject = model.item(index)
+                                    .expect("Item at index clicked expected to exist")
+                                    .downcast::<AlbumObject>()
+                                    .expect("Item expected to be AlbumObject");
+                                view.activate_action("win.album", Some(&album.id().to_variant())).expect("Error executing action");
+                            }
+                        }
+                    }
+                },
+                gtk::Box {
+                    set_halign: Align::Center,
+                    set_margin_all: 6,
+                    #[watch]
+                    set_visible: model.page_state != PageState::Idle,
+
+                    adw::Spinner {
+                        #[watch]
+                        set_visible: model.page_state == PageState::Loading,
+                    },
+                    gtk::Label {
+                        #[watch]
+                        set_label: if model.store.n_items() == 0 { "No albums" } else { "End of albums" },
+                        add_css_class: "dim-label",
+                        #[watch]
+                        set_visible: model.page_state == PageState::End,
+                    },
+                    gtk::Box {
+                        set_spacing: 5,
+                        #[watch]
+                        set_visible: model.page_state == PageState::Failed,
+
+                        gtk::Label {
+                            set_label: "Couldn't load more albums",
+                            add_css_class: "dim-label",
+                        },
+                        gtk::Button {
+                            set_label: "Retry",
+                            add_css_class: "flat",
+                            connect_clicked => AllAlbumsMsg::LoadMore,
+                        },
+                    }
+                }
+            }
+        }
+    }
+
+    async fn init(
+        init: Self::Init,
+        root: Self::Root,
+        sender: AsyncComponentSender<Self>,
+    ) -> AsyncComponentParts<Self> {
+        let (mpris_player, cover_cache, album_cache, year_range) = init;
+        let factory = SignalListItemFactory::new();
+        album_list::setup_album_factory(&factory, &cover_cache, &album_cache, &mpris_player);
+        let model = Self {
+            album_cache,
+            mpris_player,
+            store: ListStore::new::<AlbumObject>(),
+            factory,
+            page_state: PageState::Idle,
+            generation: 0,
+        };
+
+        let widgets: Self::Widgets = view_output!();
+        if let Some((from, to)) = year_range {
+            widgets.year_from.set_value(from as f64);
+            widgets.year_to.set_value(to as f64);
+        }
+        widgets.sort.set_sensitive(list_type(&widgets).1.is_none());
+        sender.input(AllAlbumsMsg::LoadMore);
+
+        AsyncComponentParts { model, widgets }
+    }
+
+    async fn update_with_view(
+        &mut self,
+        widgets: &mut Self::Widgets,
+        message: Self::Input,
+        sender: AsyncComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match message {
+            AllAlbumsMsg::Reload => {
+                self.generation += 1;
+                self.store.remove_all();
+                self.page_state = PageState::Idle;
+                // A year range decides the order, so the sort selection would have no effect
+                widgets.sort.set_sensitive(list_type(widgets).1.is_none());
+                sender.input(AllAlbumsMsg::LoadMore);
+            }
+            AllAlbumsMsg::LoadMore => {
+                if self.page_state.can_load() {
+                    self.page_state = PageState::Loading;
+                    let (list_type, year_range) = list_type(widgets);
+                    let generation = self.generation;
+                    let offset = self.store.n_items();
+                    let album_cache = self.album_cache.clone();
+                    let mpris_player = self.mpris_player.clone();
+                    let sender = sender.clone();
+                    relm4::spawn_local(async move {
+                        let albums = album_cache.get_album_list(
+                            list_type,
+                            Some(PAGE_SIZE),
+                            Some(offset),
+                            year_range.map(|(from, _)| from),
+                            year_range.map(|(_, to)| to),
+                            None,
+                            None
+                        ).await;
+                        match albums {
+                            Ok(albums) => sender.input(AllAlbumsMsg::PageLoaded(generation, albums)),
+                            Err(err) => {
+                                mpris_player.imp().send_error(err);
+                                sender.input(AllAlbumsMsg::PageFailed(generation));
+                            }
+                        }
+                    });
+                }
+            }
+            AllAlbumsMsg::PageLoaded(generation, albums) => {
+                if generation == self.generation {
+                    // Random ignores the offset, further pages would only repeat albums
+                    let last = (albums.len() as u32) < PAGE_SIZE || list_type(widgets).0 == AlbumListType::Random;
+                    self.page_state = if last { PageState::End } else { PageState::Idle };
+                    self.store.extend_from_slice(&albums);
+                }
+            }
+            AllAlbumsMsg::PageFailed(generation) => {
+                if generation == self.generation {
+                    self.page_state = PageState::Failed;
+                }
+            }
+        }
+        self.update_view(widgets, sender);
+    }
+}
+
+/// The list type and year range for the current selection.
+/// Subsonic only takes a year range with the byYear type, which needs both ends.
+fn list_type(widgets: &AllAlbumsWidgetWidgets) -> (AlbumListType, Option<(u32, u32)>) {
+    let non_zero = |v: f64| if v >= 1.0 { Some(v as u32) } else { None };
+    let year_from = non_zero(widgets.year_from.value());
+    let year_to = non_zero(widgets.year_to.value());
+    if year_from.is_some() || year_to.is_some() {
+        (AlbumListType::ByYear, Some((year_from.unwrap_or(0), year_to.unwrap_or(MAX_YEAR))))
+    } else {
+        (SORT_MODES[widgets.sort.selected() as usize % SORT_MODES.len()].1, None)
+    }
+}
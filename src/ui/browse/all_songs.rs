@@ -0,0 +1,493 @@
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::rc::Rc;
+use std::time::Duration;
+
+use mpris_server::LocalServer;
+use relm4::adw::gdk;
+use relm4::adw::gio::{ListStore, Settings};
+use relm4::adw::glib::{self, clone};
+use relm4::adw::prelude::*;
+use relm4::gtk::pango::EllipsizeMode;
+use relm4::gtk::{Align, Orientation, SignalListItemFactory};
+use relm4::prelude::*;
+use relm4::{adw, gtk, AsyncComponentSender, RelmWidgetExt};
+use uuid::Uuid;
+
+use crate::dbus::player::MprisPlayer;
+use crate::opensonic::cache::SongCache;
+use crate::opensonic::types::{duration_display_str, Song};
+use crate::ui::drag_and_drop;
+use crate::ui::info_dialog;
+use crate::ui::item_list::PageState;
+use crate::ui::song_object::{PositionState, SongObject};
+
+const PAGE_SIZE: u32 = 100;
+/// Resizing a column notifies for every pixel, the columns are saved once it settles
+const SAVE_DELAY: Duration = Duration::from_millis(500);
+
+struct SongColumn {
+    id: &'static str,
+    title: &'static str,
+    visible: bool,
+    text: fn(&Song) -> String,
+    compare: fn(&Song, &Song) -> Ordering,
+}
+
+fn columns() -> [SongColumn; 11] {
+    [
+        SongColumn {
+            id: "title",
+            title: "Title",
+            visible: true,
+            text: |s| s.title.clone(),
+            compare: |a, b| a.title.to_lowercase().cmp(&b.title.to_lowercase()),
+        },
+        SongColumn {
+            id: "artist",
+            title: "Artist",
+            visible: true,
+            text: |s| s.artists_no_markup(),
+            compare: |a, b| a.artists_no_markup().to_lowercase().cmp(&b.artists_no_markup().to_lowercase()),
+        },
+        SongColumn {
+            id: "album",
+            title: "Album",
+            visible: true,
+            text: |s| or_empty(s.album.as_ref()),
+            compare: |a, b| lowercase(&a.album).cmp(&lowercase(&b.album)),
+        },
+        SongColumn {
+            id: "year",
+            title: "Year",
+            visible: true,
+            text: |s| or_empty(s.year),
+            compare: |a, b| a.year.cmp(&b.year),
+        },
+        SongColumn {
+            id: "genre",
+            title: "Genre",
+            visible: false,
+            text: |s| or_empty(s.genre.as_ref()),
+            compare: |a, b| lowercase(&a.genre).cmp(&lowercase(&b.genre)),
+        },
+        SongColumn {
+            id: "duration",
+            title: "Duration",
+            visible: true,
+            text: |s| s.duration.as_ref().map(duration_display_str).unwrap_or_default(),
+            compare: |a, b| a.duration.cmp(&b.duration),
+        },
+        SongColumn {
+            id: "bitrate",
+            title: "Bitrate",
+            visible: false,
+            text: |s| s.bit_rate.map(|b| format!("{} kbps", b)).unwrap_or_default(),
+            compare: |a, b| a.bit_rate.cmp(&b.bit_rate),
+        },
+        SongColumn {
+            id: "format",
+            title: "Format",
+            visible: false,
+            text: |s| s.suffix.as_ref().map(|f| f.to_uppercase()).unwrap_or_default(),
+            compare: |a, b| lowercase(&a.suffix).cmp(&lowercase(&b.suffix)),
+        },
+        SongColumn {
+            id: "play-count",
+            title: "Plays",
+            visible: false,
+            text: |s| or_empty(s.play_count),
+            compare: |a, b| a.play_count.cmp(&b.play_count),
+        },
+        SongColumn {
+            id: "rating",
+            title: "Rating",
+            visible: false,
+            text: |s| s.user_rating.map(|r| "★".repeat(r as usize)).unwrap_or_default(),
+            compare: |a, b| a.user_rating.cmp(&b.user_rating),
+        },
+        SongColumn {
+            id: "added",
+            title: "Added",
+            visible: false,
+            // Only the date part of the ISO 8601 timestamp
+            text: |s| s.created.as_ref().map(|c| c.chars().take(10).collect()).unwrap_or_default(),
+            compare: |a, b| a.created.cmp(&b.created),
+        },
+    ]
+}
+
+fn or_empty<T: Display>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn lowercase(value: &Option<String>) -> Option<String> {
+    value.as_ref().map(|v| v.to_lowercase())
+}
+
+pub struct AllSongsWidget {
+    song_cache: SongCache,
+    mpris_player: Rc<LocalServer<MprisPlayer>>,
+    settings: Settings,
+    store: ListStore,
+    page_state: PageState,
+    /// Sorting happens on the client, so no further pages are loaded while a column is sorted
+    sorted: bool,
+    /// Incremented when sorting starts, so a page that was still loading isn't mixed into the sorted songs
+    generation: u64,
+    save_pending: bool,
+}
+
+#[derive(Debug)]
+pub enum AllSongsMsg {
+    LoadMore,
+    PageLoaded(u64, Vec<SongObject>),
+    PageFailed(u64),
+    SortChanged,
+    ClearSort,
+    SaveColumns,
+    SaveColumnsNow,
+}
+
+pub type AllSongsInit = (Rc<LocalServer<MprisPlayer>>, SongCache, Settings);
+
+#[relm4::component(pub async)]
+impl AsyncComponent for AllSongsWidget {
+    type CommandOutput = ();
+    type Input = AllSongsMsg;
+    type Output = ();
+    type Init = AllSongsInit;
+
+    view! {
+        adw::NavigationPage {
+            set_title: "All songs",
+
+            gtk::Box {
+                set_orientation: Orientation::Vertical,
+
+                gtk::Box {
+                    set_orientation: Orientation::Horizontal,
+                    set_spacing: 5,
+                    add_css_class: "padded",
+
+                    gtk::Label {
+                        set_label: "Sorting only applies to the songs loaded so far, more are loaded once the sort is cleared",
+                        add_css_class: "dim-label",
+                        set_hexpand: true,
+                        set_halign: Align::Start,
+                        set_wrap: true,
+                        #[watch]
+                        set_visible: model.sorted,
+                    },
+                    gtk::Button {
+                        set_label: "Clear sort",
+                        add_css_class: "flat",
+                        #[watch]
+                        set_visible: model.sorted,
+                        connect_clicked => AllSongsMsg::ClearSort,
+                    },
+                    gtk::Box {
+                        set_hexpand: true,
+                        #[watch]
+                        set_visible: !model.sorted,
+                    },
+                    gtk::MenuButton {
+                        set_label: "Columns",
+
+                        #[wrap(Some)]
+                        set_popover = &gtk::Popover {
+                            #[name = "columns_box"]
+                            gtk::Box {
+                                set_orientation: Orientation::Vertical,
+                                set_spacing: 5,
+                            }
+                        }
+                    }
+                },
+                gtk::ScrolledWindow {
+                    set_vexpand: true,
+                    connect_edge_reached[sender] => move |_, pos| {
+                        if pos == gtk::PositionType::Bottom {
+                            sender.input(AllSongsMsg::LoadMore);
+                        }
+                    },
+
+                    #[name = "column_view"]
+                    gtk::ColumnView {
+                        set_reorderable: true,
+                        set_show_row_separators: true,
+                        add_css_class: "no-bg",
+                        connect_activate => move |view, index| {
+                            let song = view.model()
+                                .and_then(|m| m.item(index))
+                                .and_downcast::<SongObject>();
+                            if let Some(song) = song {
+                                view.activate_action("win.play.song", Some(&song.id().to_variant())).expect("Error executing action");
+                            }
+                        }
+                    }
+                },
+                gtk::Box {
+                    set_halign: Align::Center,
+                    set_margin_all: 6,
+                    #[watch]
+                    set_visible: model.page_state != PageState::Idle,
+
+                    adw::Spinner {
+                        #[watch]
+                        set_visible: model.page_state == PageState::Loading,
+                    },
+                    gtk::Label {
+                        #[watch]
+                        set_label: if model.store.n_items() == 0 { "No songs" } else { "End of songs" },
+                        add_css_class: "dim-label",
+                        #[watch]
+                        set_visible: model.page_state == PageState::End,
+                    },
+                    gtk::Box {
+                        set_spacing: 5,
+                        #[watch]
+                        set_visible: model.page_state == PageState::Failed,
+
+                        gtk::Label {
+                            set_label: "Couldn't load more songs",
+                            add_css_class: "dim-label",
+                        },
+                        gtk::Button {
+                            set_label: "Retry",
+                            add_css_class: "flat",
+                            connect_clicked => AllSongsMsg::LoadMore,
+                        },
+                    }
+                }
+            }
+        }
+    }
+
+    async fn init(
+        init: Self::Init,
+        root: Self::Root,
+        sender: AsyncComponentSender<Self>,
+    ) -> AsyncComponentParts<Self> {
+        let model = Self {
+            mpris_player: init.0,
+            song_cache: init.1,
+            settings: init.2,
+            store: ListStore::new::<SongObject>(),
+            page_state: PageState::Idle,
+            sorted: false,
+            generation: 0,
+            save_pending: false,
+        };
+
+        let widgets: Self::Widgets = view_output!();
+
+        let saved = model.settings.value("song-table-columns")
+            .get::<Vec<(String, bool, i32)>>()
+            .unwrap_or_default();
+        let mut columns = columns();
+        // Columns that aren't saved yet go to the end
+        columns.sort_by_key(|c| saved.iter().position(|(id, _, _)| id == c.id).unwrap_or(usize::MAX));
+        for song_column in columns {
+            let (visible, width) = saved.iter()
+                .find(|(id, _, _)| id == song_column.id)
+                .map(|(_, visible, width)| (*visible, *width))
+                .unwrap_or((song_column.visible, -1));
+            let column = gtk::ColumnViewColumn::new(Some(song_column.title), Some(cell_factory(song_column.text)));
+            column.set_id(Some(song_column.id));
+            column.set_resizable(true);
+            column.set_expand(song_column.id == "title");
+            column.set_visible(visible);
+            column.set_fixed_width(width);
+            let compare = song_column.compare;
+            column.set_sorter(Some(&gtk::CustomSorter::new(move |a, b| {
+                let a = a.downcast_ref::<SongObject>().and_then(|s| s.get_entry());
+                let b = b.downcast_ref::<SongObject>().and_then(|s| s.get_entry());
+                match (a, b) {
+                    (Some(a), Some(b)) => compare(&a, &b),
+                    _ => Ordering::Equal,
+                }.into()
+            })));
+            column.connect_visible_notify(clone!(
+                #[strong]
+                sender,
+                move |_| sender.input(AllSongsMsg::SaveColumns)
+            ));
+            column.connect_fixed_width_notify(clone!(
+                #[strong]
+                sender,
+                move |_| sender.input(AllSongsMsg::SaveColumns)
+            ));
+
+            let toggle = gtk::CheckButton::with_label(song_column.title);
+            column.bind_property("visible", &toggle, "active")
+                .bidirectional()
+                .sync_create()
+                .build();
+            widgets.columns_box.append(&toggle);
+            widgets.column_view.append_column(&column);
+        }
+        // Fired when the user drags a column to a new place
+        widgets.column_view.columns().connect_items_changed(clone!(
+            #[strong]
+            sender,
+            move |_, _, _, _| sender.input(AllSongsMsg::SaveColumns)
+        ));
+
+        let sorter = widgets.column_view.sorter();
+        if let Some(sorter) = &sorter {
+            sorter.connect_changed(clone!(
+                #[strong]
+                sender,
+                move |_, _| sender.input(AllSongsMsg::SortChanged)
+            ));
+        }
+        let sorted = gtk::SortListModel::new(Some(model.store.clone()), sorter);
+        widgets.column_view.set_model(Some(&gtk::NoSelection::new(Some(sorted))));
+        sender.input(AllSongsMsg::LoadMore);
+
+        AsyncComponentParts { model, widgets }
+    }
+
+    async fn update_with_view(
+        &mut self,
+        widgets: &mut Self::Widgets,
+        message: Self::Input,
+        sender: AsyncComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match message {
+            AllSongsMsg::LoadMore => {
+                if self.page_state.can_load() && !self.sorted {
+                    self.page_state = PageState::Loading;
+                    let generation = self.generation;
+                    let offset = self.store.n_items();
+                    let song_cache = self.song_cache.clone();
+                    let mpris_player = self.mpris_player.clone();
+                    let sender = sender.clone();
+                    relm4::spawn_local(async move {
+                        // OpenSubsonic allows an empty query, which matches everything
+                        match song_cache.search("", PAGE_SIZE, Some(offset)).await {
+                            Ok(songs) => {
+                                let songs = songs.into_iter()
+                                    .map(|s| SongObject::new((Uuid::from_u128(0), s).into(), PositionState::Passed))
+                                    .collect();
+                                sender.input(AllSongsMsg::PageLoaded(generation, songs));
+                            }
+                            Err(err) => {
+                                mpris_player.imp().send_error(err);
+                                sender.input(AllSongsMsg::PageFailed(generation));
+                            }
+                        }
+                    });
+                }
+            },
+            AllSongsMsg::PageLoaded(generation, songs) => {
+                if generation == self.generation {
+                    self.page_state = if (songs.len() as u32) < PAGE_SIZE { PageState::End } else { PageState::Idle };
+                    self.store.extend_from_slice(&songs);
+                }
+            },
+            AllSongsMsg::PageFailed(generation) => {
+                if generation == self.generation {
+                    self.page_state = PageState::Failed;
+                }
+            },
+            AllSongsMsg::SortChanged => {
+                let sorted = widgets.column_view.sorter()
+                    .and_downcast::<gtk::ColumnViewSorter>()
+                    .is_some_and(|s| s.primary_sort_column().is_some());
+                if sorted && !self.sorted {
+                    self.generation += 1;
+                    if self.page_state == PageState::Loading {
+                        self.page_state = PageState::Idle;
+                    }
+                } else if !sorted && self.sorted {
+                    // Carry on where paging stopped, the list may already be scrolled to the bottom
+                    sender.input(AllSongsMsg::LoadMore);
+                }
+                self.sorted = sorted;
+            },
+            AllSongsMsg::ClearSort => {
+                widgets.column_view.sort_by_column(None, gtk::SortType::Ascending);
+            },
+            AllSongsMsg::SaveColumns => {
+                if !self.save_pending {
+                    self.save_pending = true;
+                    let sender = sender.clone();
+                    relm4::spawn_local(async move {
+                        glib::timeout_future(SAVE_DELAY).await;
+                        sender.input(AllSongsMsg::SaveColumnsNow);
+                    });
+                }
+            },
+            AllSongsMsg::SaveColumnsNow => {
+                self.save_pending = false;
+                let columns: Vec<(String, bool, i32)> = widgets.column_view.columns()
+                    .iter::<gtk::ColumnViewColumn>()
+                    .filter_map(Result::ok)
+                    .filter_map(|c| c.id().map(|id| (id.to_string(), c.is_visible(), c.fixed_width())))
+                    .collect();
+                if let Err(err) = self.settings.set_value("song-table-columns", &columns.to_variant()) {
+                    self.mpris_player.imp().send_error(err.into());
+                }
+            },
+        }
+        self.update_view(widgets, sender);
+    }
+}
+
+fn cell_factory(text: fn(&Song) -> String) -> SignalListItemFactory {
+    let factory = SignalListItemFactory::new();
+    factory.connect_setup(|_, cell| {
+        let cell = cell.downcast_ref::<gtk::ColumnViewCell>().expect("Needs to be ColumnViewCell");
+        let label = gtk::Label::builder()
+            .xalign(0.0)
+            .hexpand(true)
+            .ellipsize(EllipsizeMode::End)
+            .build();
+
+        let ctrl = gtk::GestureClick::builder()
+            .button(3)
+            .build();
+        ctrl.connect_pressed(clone!(
+            #[weak]
+            cell,
+            #[weak]
+            label,
+            move |_controller, _btn, x, y| {
+                if let Some(song) = cell.item().and_downcast::<SongObject>() {
+                    let menu = info_dialog::make_popup_menu(&song, false);
+                    menu.set_parent(&label);
+                    menu.set_pointing_to(Some(&gdk::Rectangle::new(x as i32, y as i32, 1, 1)));
+                    menu.popup();
+                }
+            }
+        ));
+        label.add_controller(ctrl);
+        drag_and_drop::add_drag_source(&label, clone!(
+            #[weak]
+            cell,
+            #[weak]
+            label,
+            #[upgrade_or_default]
+            move || {
+                let selection = label.ancestor(gtk::ColumnView::static_type())
+                    .and_downcast::<gtk::ColumnView>()
+                    .and_then(|column_view| column_view.model());
+                drag_and_drop::row_items(cell.upcast_ref(), selection)
+            }
+        ));
+        cell.set_child(Some(&label));
+    });
+    factory.connect_bind(move |_, cell| {
+        let cell = cell.downcast_ref::<gtk::ColumnViewCell>().expect("Needs to be ColumnViewCell");
+        let song = cell.item()
+            .and_downcast::<SongObject>()
+            .and_then(|s| s.get_entry());
+        if let Some(label) = cell.child().and_downcast::<gtk::Label>() {
+            label.set_label(song.map(|s| text(&s)).unwrap_or_default().as_str());
+        }
+    });
+    factory
+}
@@ -0,0 +1,308 @@
+use std::rc::Rc;
+
+use mpris_server::LocalServer;
+use relm4::actions::ActionName;
+use relm4::adw::gio::ListStore;
+use relm4::adw::prelude::*;
+use relm4::gtk::glib::{self, clone};
+use relm4::gtk::{Align, Orientation};
+use relm4::prelude::*;
+use relm4::{adw, gtk, AsyncComponentSender, RelmWidgetExt};
+
+use crate::dbus::player::MprisPlayer;
+use crate::history::{self, HistoryEntry};
+use crate::icon_names;
+use crate::opensonic::cache::SongCache;
+use crate::opensonic::types::Song;
+use crate::ui::app::{PlaySong, QueueSong};
+
+/// Only the most recent plays are shown, the export has everything
+const MAX_SHOWN: usize = 1000;
+
+pub struct HistoryWidget {
+    mpris_player: Rc<LocalServer<MprisPlayer>>,
+    song_cache: SongCache,
+    loading: bool,
+    entries: Vec<HistoryEntry>,
+}
+
+#[derive(Debug)]
+pub enum HistoryMsg {
+    Reload,
+    PlaySongs(Vec<String>),
+    QueueSongs(Vec<String>),
+    Export(ExportFormat),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ExportFormat {
+    ListenBrainz,
+    Csv,
+}
+
+pub type HistoryInit = (Rc<LocalServer<MprisPlayer>>, SongCache);
+
+#[relm4::component(pub async)]
+impl AsyncComponent for HistoryWidget {
+    type CommandOutput = ();
+    type Input = HistoryMsg;
+    type Output = ();
+    type Init = HistoryInit;
+
+    view! {
+        adw::NavigationPage {
+            set_title: "History",
+
+            gtk::ScrolledWindow {
+                set_hscrollbar_policy: gtk::PolicyType::Never,
+                set_vexpand: true,
+
+                adw::Clamp {
+                    gtk::Box {
+                        set_orientation: Orientation::Vertical,
+                        set_spacing: 10,
+                        add_css_class: "padded",
+
+                        gtk::Box {
+                            set_orientation: Orientation::Horizontal,
+                            set_halign: Align::End,
+                            set_spacing: 5,
+
+                            gtk::Button {
+                                set_label: "Refresh",
+                                add_css_class: "flat",
+                                connect_clicked => HistoryMsg::Reload,
+                            },
+                            gtk::Button {
+                                set_label: "Export for ListenBrainz",
+                                add_css_class: "flat",
+                                set_tooltip: "Save listens as JSON that ListenBrainz can import",
+                                #[watch]
+                                set_sensitive: !model.entries.is_empty(),
+                                connect_clicked => HistoryMsg::Export(ExportFormat::ListenBrainz),
+                            },
+                            gtk::Button {
+                                set_label: "Export CSV",
+                                add_css_class: "flat",
+                                set_tooltip: "Save the whole history, including skipped songs, as CSV",
+                                #[watch]
+                                set_sensitive: !model.entries.is_empty(),
+                                connect_clicked => HistoryMsg::Export(ExportFormat::Csv),
+                            },
+                        },
+                        adw::Spinner {
+                            set_halign: Align::Center,
+                            #[watch]
+                            set_visible: model.loading,
+                        },
+                        gtk::Label {
+                            set_label: "Nothing has been played yet",
+                            add_css_class: "dim-label",
+                            #[watch]
+                            set_visible: !model.loading && model.entries.is_empty(),
+                        },
+                        #[name = "days_box"]
+                        gtk::Box {
+                            set_orientation: Orientation::Vertical,
+                            set_spacing: 10,
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    async fn init(
+        init: Self::Init,
+        root: Self::Root,
+        sender: AsyncComponentSender<Self>,
+    ) -> AsyncComponentParts<Self> {
+        let model = Self {
+            mpris_player: init.0,
+            song_cache: init.1,
+            loading: true,
+            entries: Vec::new(),
+        };
+
+        let widgets: Self::Widgets = view_output!();
+        sender.input(HistoryMsg::Reload);
+
+        AsyncComponentParts { model, widgets }
+    }
+
+    async fn update_with_view(
+        &mut self,
+        widgets: &mut Self::Widgets,
+        message: Self::Input,
+        sender: AsyncComponentSender<Self>,
+        root: &Self::Root,
+    ) {
+        let mpris_player = self.mpris_player.clone();
+        let player = mpris_player.imp();
+        match message {
+            HistoryMsg::Reload => {
+                self.loading = true;
+                self.update_view(widgets, sender.clone());
+                match HistoryEntry::load_all().await {
+                    Ok(entries) => self.entries = entries,
+                    Err(err) => player.send_error(err),
+                }
+                self.loading = false;
+                self.fill_days(&widgets.days_box, &sender);
+            },
+            HistoryMsg::PlaySongs(ids) => {
+                let songs = self.history_songs(&ids).await;
+                player.send_res(player.queue_songs(songs, Some(0), true).await);
+            },
+            HistoryMsg::QueueSongs(ids) => {
+                let songs = self.history_songs(&ids).await;
+                player.send_res(player.queue_songs(songs, None, false).await);
+            },
+            HistoryMsg::Export(format) => {
+                let (name, suffix, title) = match format {
+                    ExportFormat::ListenBrainz => ("listens.json", "json", "Export listens"),
+                    ExportFormat::Csv => ("history.csv", "csv", "Export history"),
+                };
+                let filter = gtk::FileFilter::new();
+                filter.add_suffix(suffix);
+                let filters = ListStore::new::<gtk::FileFilter>();
+                filters.append(&filter);
+                let dialog = gtk::FileDialog::builder()
+                    .title(title)
+                    .initial_name(name)
+                    .filters(&filters)
+                    .default_filter(&filter)
+                    .build();
+                let window = root.root().and_downcast::<gtk::Window>();
+                // Dismissing the dialog also returns an error, so it's ignored
+                if let Ok(file) = dialog.save_future(window.as_ref()).await && let Some(path) = file.path() {
+                    let contents = match format {
+                        ExportFormat::ListenBrainz => history::to_listenbrainz_json(&self.entries),
+                        ExportFormat::Csv => Ok(history::to_csv(&self.entries)),
+                    };
+                    let res = match contents {
+                        Ok(contents) => tokio::fs::write(&path, contents).await.map_err(|e| e.into()),
+                        Err(err) => Err(err),
+                    };
+                    player.send_res(res);
+                }
+            },
+        }
+        self.update_view(widgets, sender);
+    }
+}
+
+impl HistoryWidget {
+    /// Songs for history entries, in the same order, leaving out ones that couldn't be loaded
+    async fn history_songs(&self, ids: &[String]) -> Vec<Rc<Song>> {
+        let songs = self.song_cache.get_songs(ids).await;
+        ids.iter().filter_map(|id| songs.get(id).cloned()).collect()
+    }
+
+    fn fill_days(&self, days_box: &gtk::Box, sender: &AsyncComponentSender<Self>) {
+        while let Some(child) = days_box.first_child() {
+            days_box.remove(&child);
+        }
+
+        // Newest first, grouped by local day
+        let mut days: Vec<Vec<(&HistoryEntry, glib::DateTime)>> = Vec::new();
+        for entry in self.entries.iter().rev().take(MAX_SHOWN) {
+            let Ok(time) = glib::DateTime::from_unix_local(entry.timestamp as i64) else {
+                continue;
+            };
+            match days.last_mut() {
+                Some(day) if day[0].1.ymd() == time.ymd() => day.push((entry, time)),
+                _ => days.push(vec![(entry, time)]),
+            }
+        }
+
+        let today = glib::DateTime::now_local().ok();
+        let yesterday = today.as_ref().and_then(|t| t.add_days(-1).ok());
+        for day in days {
+            let date = &day[0].1;
+            let title = if today.as_ref().is_some_and(|t| t.ymd() == date.ymd()) {
+                "Today".to_string()
+            } else if yesterday.as_ref().is_some_and(|t| t.ymd() == date.ymd()) {
+                "Yesterday".to_string()
+            } else {
+                date.format("%A, %e %B %Y").map(|s| s.to_string()).unwrap_or_default()
+            };
+            // Replayed in the order they were played
+            let ids: Vec<String> = day.iter().rev().map(|(e, _)| e.song_id.clone()).collect();
+            days_box.append(&day_header(&title, ids, sender));
+
+            let list = gtk::ListBox::new();
+            list.add_css_class("boxed-list");
+            list.set_selection_mode(gtk::SelectionMode::None);
+            for (entry, time) in &day {
+                list.append(&entry_row(entry, time));
+            }
+            days_box.append(&list);
+        }
+    }
+}
+
+fn day_header(title: &str, ids: Vec<String>, sender: &AsyncComponentSender<HistoryWidget>) -> gtk::Box {
+    let header = gtk::Box::new(Orientation::Horizontal, 5);
+    let label = gtk::Label::new(Some(title));
+    label.add_css_class("heading");
+    label.set_xalign(0.0);
+    label.set_hexpand(true);
+    header.append(&label);
+
+    let play_btn = gtk::Button::from_icon_name(icon_names::shipped::PLAY);
+    play_btn.add_css_class("flat");
+    play_btn.set_tooltip("Play this day's songs");
+    play_btn.connect_clicked(clone!(
+        #[strong]
+        sender,
+        #[strong]
+        ids,
+        move |_| sender.input(HistoryMsg::PlaySongs(ids.clone()))
+    ));
+    let queue_btn = gtk::Button::from_icon_name(icon_names::shipped::ADD_REGULAR);
+    queue_btn.add_css_class("flat");
+    queue_btn.set_tooltip("Queue this day's songs");
+    queue_btn.connect_clicked(clone!(
+        #[strong]
+        sender,
+        move |_| sender.input(HistoryMsg::QueueSongs(ids.clone()))
+    ));
+    header.append(&play_btn);
+    header.append(&queue_btn);
+    header
+}
+
+fn entry_row(entry: &HistoryEntry, time: &glib::DateTime) -> adw::ActionRow {
+    let mut subtitle = vec![time.format("%H:%M").map(|s| s.to_string()).unwrap_or_default()];
+    if let Some(artist) = &entry.artist {
+        subtitle.push(artist.clone());
+    }
+    if entry.skipped {
+        subtitle.push("Skipped".to_string());
+    }
+    let row = adw::ActionRow::builder()
+        .title(glib::markup_escape_text(&entry.title))
+        .subtitle(glib::markup_escape_text(&subtitle.join(" · ")))
+        .build();
+    if entry.skipped {
+        row.add_css_class("dim-label");
+    }
+
+    let target = entry.song_id.to_variant();
+    let play_btn = gtk::Button::from_icon_name(icon_names::shipped::PLAY);
+    play_btn.add_css_class("flat");
+    play_btn.set_valign(Align::Center);
+    play_btn.set_tooltip("Play again");
+    play_btn.set_action_name(Some(PlaySong::action_name()));
+    play_btn.set_action_target_value(Some(&target));
+    let queue_btn = gtk::Button::from_icon_name(icon_names::shipped::ADD_REGULAR);
+    queue_btn.add_css_class("flat");
+    queue_btn.set_valign(Align::Center);
+    queue_btn.set_tooltip("Add to queue");
+    queue_btn.set_action_name(Some(QueueSong::action_name()));
+    queue_btn.set_action_target_value(Some(&target));
+    row.add_suffix(&play_btn);
+    row.add_suffix(&queue_btn);
+    row
+}
@@ -4,7 +4,11 @@ use std::rc::Rc;
 use color_thief::Color;
 
 mod album_list;
+mod all_albums;
+mod all_songs;
+mod years_page;
 mod browse_page;
+mod history_page;
 mod view_album_page;
 pub(super) mod search;
 mod view_artist_page;
@@ -14,12 +18,17 @@ use crate::opensonic::cache::{AlbumCache, ArtistCache, CoverCache, SongCache};
 use crate::ui::album_object::AlbumObject;
 use crate::ui::app::Init;
 use crate::ui::artist_object::ArtistObject;
+use crate::ui::browse::all_albums::AllAlbumsWidget;
+use crate::ui::browse::all_songs::AllSongsWidget;
 use crate::ui::browse::browse_page::{BrowsePageOut, BrowsePageWidget};
+use crate::ui::browse::history_page::HistoryWidget;
 use crate::ui::browse::search::{SearchMsg, SearchType, SearchWidget};
 use crate::ui::browse::view_album_page::ViewAlbumWidget;
 use crate::ui::browse::view_artist_page::ViewArtistWidget;
+use crate::ui::browse::years_page::YearsWidget;
 use mpris_server::LocalServer;
 use relm4::component::AsyncComponentParts;
+use relm4::gtk::gio::Settings;
 use relm4::prelude::{AsyncComponent, AsyncController};
 use relm4::{adw, AsyncComponentSender};
 
@@ -29,6 +38,7 @@ pub struct BrowseWidget {
     album_cache: AlbumCache,
     artist_cache: ArtistCache,
     song_cache: SongCache,
+    settings: Settings,
 
     browse_page: AsyncController<BrowsePageWidget>,
     search_controller: AsyncConnector<SearchWidget>,
@@ -38,7 +48,11 @@ pub struct BrowseWidget {
 pub enum BrowseMsg {
     ViewAlbum(AlbumObject, Option<u32>),
     ViewArtist(ArtistObject),
-    Search(String, SearchType)
+    Search(String, SearchType),
+    ViewAllAlbums(Option<(u32, u32)>),
+    ViewYears,
+    ViewAllSongs,
+    ViewHistory,
 }
 
 #[derive(Debug)]
@@ -87,6 +101,7 @@ impl AsyncComponent for BrowseWidget {
             search_controller,
             artist_cache: init.7,
             song_cache: init.1,
+            settings: init.3,
         };
 
         let widgets: Self::Widgets = view_output!();
@@ -118,7 +133,8 @@ impl AsyncComponent for BrowseWidget {
                 }
             }
             BrowseMsg::Search(query, search_type) => {
-                if widgets.navigation_view.visible_page()
+                // Clearing the search entry shouldn't navigate away from what's being viewed
+                if !query.trim().is_empty() && widgets.navigation_view.visible_page()
                     .and_then(|t| t.tag())
                     .and_then(|t| Some(t!="search"))
                     .unwrap_or(true) {
@@ -127,6 +143,26 @@ impl AsyncComponent for BrowseWidget {
                 }
                 self.search_controller.emit(SearchMsg::Search(query, search_type));
             },
+            BrowseMsg::ViewAllAlbums(year_range) => {
+                let all_albums_page = AllAlbumsWidget::builder()
+                    .launch((self.mpris_player.clone(), self.cover_cache.clone(), self.album_cache.clone(), year_range));
+                widgets.navigation_view.push(all_albums_page.widget());
+            },
+            BrowseMsg::ViewYears => {
+                let years_page = YearsWidget::builder()
+                    .launch((self.mpris_player.clone(), self.album_cache.clone()));
+                widgets.navigation_view.push(years_page.widget());
+            },
+            BrowseMsg::ViewAllSongs => {
+                let all_songs_page = AllSongsWidget::builder()
+                    .launch((self.mpris_player.clone(), self.song_cache.clone(), self.settings.clone()));
+                widgets.navigation_view.push(all_songs_page.widget());
+            },
+            BrowseMsg::ViewHistory => {
+                let history_page = HistoryWidget::builder()
+                    .launch((self.mpris_player.clone(), self.song_cache.clone()));
+                widgets.navigation_view.push(history_page.widget());
+            },
         }
         self.update_view(widgets, sender);
     }
@@ -1,25 +1,31 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::error::Error;
+use std::future::{ready, Ready};
 use std::rc::Rc;
 
 use mpris_server::LocalServer;
 use relm4::adw::glib;
 use relm4::adw::prelude::ToVariant;
-use relm4::component::AsyncComponentController;
+use relm4::component::{AsyncComponentController, AsyncController};
 use relm4::{
     AsyncComponentSender,
     adw::{self, prelude::NavigationPageExt},
     gtk::{
-        self, Align,
+        self, Align, Justification,
         glib::{clone, object::Cast},
-        prelude::WidgetExt,
+        prelude::{BoxExt, WidgetExt},
     },
     prelude::{AsyncComponent, AsyncComponentParts},
+    RelmWidgetExt,
 };
 use uuid::Uuid;
 
-use crate::ui::item_list::{ItemListInit, ItemListWidget};
+use crate::ui::filter_bar::{FilterBar, FilterBarOut, ResultFilter};
+use crate::ui::item_list::{ItemListInit, ItemListMsg, ItemListOut, ItemListWidget, PageLoader, PageState};
 use crate::{
     dbus::player::MprisPlayer,
-    opensonic::cache::{AlbumCache, ArtistCache, CoverCache, SongCache},
+    opensonic::cache::{AlbumCache, ArtistCache, CoverCache, SongCache, SuperCache},
     ui::{
         album_object::AlbumObject,
         app::Init,
@@ -34,22 +40,58 @@ pub struct SearchWidget {
     cover_cache: CoverCache,
     album_cache: AlbumCache,
     artist_cache: ArtistCache,
+    super_cache: SuperCache,
     mpris_player: Rc<LocalServer<MprisPlayer>>,
     current_type: SearchType,
+    page_state: PageState,
+    /// Incremented for every search, so results of older searches can be told apart and dropped
+    generation: u64,
+    search_task: Option<glib::JoinHandle<()>>,
+    filter_bar: AsyncController<FilterBar>,
+    filter: Rc<RefCell<ResultFilter>>,
+    /// Shared by all result lists, so the filter applies to every section and page
+    custom_filter: gtk::CustomFilter,
+    /// The list that loads further pages of the current results, if any
+    paged_list: RefCell<Option<relm4::Sender<ItemListMsg>>>,
+    /// Controllers of the result lists being shown, which hold different item types.
+    /// Dropped when the lists are replaced, which shuts their runtimes down.
+    lists: RefCell<Vec<Box<dyn Any>>>,
 }
 
+const PAGE_SIZE: u32 = 20;
+const ALL_ARTIST_COUNT: u32 = 5;
+const ALL_ALBUM_COUNT: u32 = 5;
+const ALL_SONG_COUNT: u32 = 10;
+
 #[derive(Debug)]
 pub enum SearchMsg {
     Search(String, SearchType),
+    Results(u64, String, SearchResults),
+    PageState(u64, PageState),
+    RetryPage,
+    FilterChanged(ResultFilter),
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum SearchType {
+    All,
     Song,
     Album,
     Artist,
 }
 
+#[derive(Debug)]
+pub enum SearchResults {
+    Songs(Vec<SongObject>),
+    Albums(Vec<AlbumObject>),
+    Artists(Vec<ArtistObject>),
+    All {
+        artists: Vec<ArtistObject>,
+        albums: Vec<AlbumObject>,
+        songs: Vec<SongObject>,
+    },
+}
+
 #[relm4::component(pub async)]
 impl AsyncComponent for SearchWidget {
     type CommandOutput = ();
@@ -62,12 +104,49 @@ impl AsyncComponent for SearchWidget {
             set_tag: Some("search"),
             set_title: "Search",
 
-            #[name = "scrolled"]
-            gtk::ScrolledWindow {
-                set_hscrollbar_policy: gtk::PolicyType::Never,
-                set_vexpand: true,
-                set_vexpand_set: true,
-                set_valign: Align::Fill,
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+
+                append: model.filter_bar.widget(),
+                #[name = "scrolled"]
+                gtk::ScrolledWindow {
+                    set_hscrollbar_policy: gtk::PolicyType::Never,
+                    set_vexpand: true,
+                    set_vexpand_set: true,
+                    set_valign: Align::Fill,
+                },
+                gtk::Box {
+                    set_halign: Align::Center,
+                    set_margin_all: 6,
+                    #[watch]
+                    set_visible: model.page_state != PageState::Idle,
+
+                    adw::Spinner {
+                        #[watch]
+                        set_visible: model.page_state == PageState::Loading,
+                    },
+                    gtk::Label {
+                        set_label: "End of results",
+                        add_css_class: "dim-label",
+                        #[watch]
+                        set_visible: model.page_state == PageState::End,
+                    },
+                    gtk::Box {
+                        set_spacing: 5,
+                        #[watch]
+                        set_visible: model.page_state == PageState::Failed,
+
+                        gtk::Label {
+                            set_label: "Couldn't load more results",
+                            add_css_class: "dim-label",
+                        },
+                        gtk::Button {
+                            set_label: "Retry",
+                            add_css_class: "flat",
+                            connect_clicked => SearchMsg::RetryPage,
+                        },
+                    }
+                }
             }
         }
     }
@@ -75,15 +154,35 @@ impl AsyncComponent for SearchWidget {
     async fn init(
         init: Self::Init,
         root: Self::Root,
-        _sender: AsyncComponentSender<Self>,
+        sender: AsyncComponentSender<Self>,
     ) -> AsyncComponentParts<Self> {
+        let filter_bar = FilterBar::builder()
+            .launch(())
+            .forward(sender.input_sender(), |msg| match msg {
+                FilterBarOut::Changed(filter) => SearchMsg::FilterChanged(filter),
+            });
+        let filter = Rc::new(RefCell::new(ResultFilter::default()));
+        let custom_filter = gtk::CustomFilter::new(clone!(
+            #[strong]
+            filter,
+            move |item| filter.borrow().matches(item)
+        ));
         let model = Self {
             mpris_player: init.6,
             song_cache: init.1,
             cover_cache: init.0,
             album_cache: init.2,
             artist_cache: init.7,
-            current_type: SearchType::Album,
+            super_cache: init.8,
+            current_type: SearchType::All,
+            page_state: PageState::Idle,
+            generation: 0,
+            search_task: None,
+            filter_bar,
+            filter,
+            custom_filter,
+            paged_list: RefCell::new(None),
+            lists: RefCell::new(Vec::new()),
         };
 
         let widgets: Self::Widgets = view_output!();
@@ -100,125 +199,277 @@ impl AsyncComponent for SearchWidget {
     ) {
         match message {
             SearchMsg::Search(query, search_type) => {
+                // A newer query makes the pending request useless
+                if let Some(task) = self.search_task.take() {
+                    task.abort();
+                }
+                self.generation += 1;
                 self.current_type = search_type;
-                let mpris_player = self.mpris_player.clone();
-                match search_type {
-                    SearchType::Song => {
-                        let song_cache = self.song_cache.clone();
-                        let item_list_widget = ItemListWidget::builder().launch(ItemListInit {
-                            mpris_player: self.mpris_player.clone(),
-                            cover_type: CoverType::Square,
-                            cover_cache: self.cover_cache.clone(),
-                            play_fn: Some(Box::new(|item: SongObject, _i, mpris_player| {
-                                if let Some(item) = item.get_entry() {
-                                    let mpris_player = mpris_player.clone();
-                                    relm4::spawn_local(async move {
-                                        let mpris_player = mpris_player.clone();
-                                        mpris_player
-                                            .imp()
-                                            .send_res(mpris_player.imp().set_song(item).await);
-                                    });
-                                }
-                            })),
-                            click_fn: None,
-                            load_items: async move {
-                                let results = song_cache.search(&query, 20, None).await;
-                                if let Err(err) = results {
-                                    mpris_player.imp().send_error(err);
-                                    Vec::new()
-                                } else {
-                                    results
-                                        .unwrap()
-                                        .iter()
-                                        .map(|x| {
-                                            SongObject::new(
-                                                (Uuid::from_u128(0), x.clone()).into(),
-                                                PositionState::Passed,
-                                            )
-                                        })
-                                        .collect()
-                                }
-                            },
-                            highlight: None,
-                        });
-                        widgets.scrolled.set_child(Some(item_list_widget.widget()));
-                    }
-                    SearchType::Album => {
-                        let album_cache = self.album_cache.clone();
-                        let item_list_widget = ItemListWidget::builder().launch(ItemListInit {
-                            mpris_player: self.mpris_player.clone(),
-                            cover_type: CoverType::Square,
-                            cover_cache: self.cover_cache.clone(),
-                            play_fn: Some(Box::new(|item: AlbumObject, _i, mpris_player| {
-                                let mpris_player = mpris_player.clone();
-                                relm4::spawn_local(async move {
-                                    let mpris_player = mpris_player.clone();
-                                    mpris_player.imp().send_res(
-                                        mpris_player.imp().queue_album(item.id(), None, true).await,
-                                    );
-                                });
-                            })),
-                            click_fn: Some(Box::new(clone!(
-                                #[weak]
-                                root,
-                                move |item, _i, _mpris_player| {
-                                    let album = item
-                                        .downcast::<AlbumObject>()
-                                        .expect("Item should be AlbumObject");
-                                    root.activate_action(
-                                        "win.album",
-                                        Some(&album.id().to_variant()),
-                                    )
-                                    .expect("Error executing action");
-                                }
-                            ))),
-                            load_items: async move {
-                                let results = album_cache.search(&query, 20, None).await;
-                                if let Err(err) = results {
-                                    mpris_player.imp().send_error(err);
-                                    Vec::new()
-                                } else {
-                                    results.unwrap().into_iter().collect()
+                self.paged_list.take();
+                if query.trim().is_empty() {
+                    widgets.scrolled.set_child(None::<&gtk::Widget>);
+                    self.lists.borrow_mut().clear();
+                    self.page_state = PageState::Idle;
+                } else {
+                    self.page_state = PageState::Loading;
+                    let generation = self.generation;
+                    let song_cache = self.song_cache.clone();
+                    let album_cache = self.album_cache.clone();
+                    let artist_cache = self.artist_cache.clone();
+                    let super_cache = self.super_cache.clone();
+                    let mpris_player = self.mpris_player.clone();
+                    let results_sender = sender.clone();
+                    self.search_task = Some(relm4::spawn_local(async move {
+                        let results = match search_type {
+                            SearchType::Song => SearchResults::Songs(or_report(search_songs(song_cache, query.clone(), 0).await, &mpris_player)),
+                            SearchType::Album => SearchResults::Albums(or_report(search_albums(album_cache, query.clone(), 0).await, &mpris_player)),
+                            SearchType::Artist => SearchResults::Artists(or_report(search_artists(artist_cache, query.clone(), 0).await, &mpris_player)),
+                            SearchType::All => {
+                                match super_cache.search(&query, ALL_ARTIST_COUNT, ALL_ALBUM_COUNT, ALL_SONG_COUNT).await {
+                                    Ok((songs, albums, artists)) => SearchResults::All {
+                                        artists,
+                                        albums,
+                                        songs: songs.iter().map(song_object).collect(),
+                                    },
+                                    Err(err) => {
+                                        mpris_player.imp().send_error(err);
+                                        SearchResults::All { artists: Vec::new(), albums: Vec::new(), songs: Vec::new() }
+                                    }
                                 }
-                            },
-                            highlight: None,
-                        });
-                        widgets.scrolled.set_child(Some(item_list_widget.widget()));
-                    }
-                    SearchType::Artist => {
-                        let artist_cache = self.artist_cache.clone();
-                        let item_list_widget = ItemListWidget::builder().launch(ItemListInit {
-                            mpris_player: self.mpris_player.clone(),
-                            cover_type: CoverType::Round,
-                            cover_cache: self.cover_cache.clone(),
-                            play_fn: None,
-                            click_fn: Some(Box::new(clone!(
-                                #[weak]
-                                root,
-                                move |artist: ArtistObject, _i, _mpris_player| {
-                                    root.activate_action(
-                                        "win.artist",
-                                        Some(&artist.id().to_variant()),
-                                    )
-                                    .expect("Error executing action");
-                                }
-                            ))),
-                            load_items: async move {
-                                let results = artist_cache.search(&query, 20, None).await;
-                                if let Err(err) = results {
-                                    mpris_player.imp().send_error(err);
-                                    Vec::new()
-                                } else {
-                                    results.unwrap().into_iter().collect()
-                                }
-                            },
-                            highlight: None,
-                        });
-                        widgets.scrolled.set_child(Some(item_list_widget.widget()));
-                    }
-                };
+                            }
+                        };
+                        results_sender.input(SearchMsg::Results(generation, query, results));
+                    }));
+                }
+            }
+            SearchMsg::Results(generation, query, results) => {
+                if generation == self.generation {
+                    self.search_task = None;
+                    self.show_results(query, results, widgets, &sender, root);
+                }
+            }
+            SearchMsg::PageState(generation, state) => {
+                if generation == self.generation {
+                    self.page_state = state;
+                }
+            }
+            SearchMsg::RetryPage => {
+                if let Some(list) = self.paged_list.borrow().as_ref() {
+                    list.emit(ItemListMsg::LoadNextPage);
+                }
+            }
+            SearchMsg::FilterChanged(filter) => {
+                self.filter.replace(filter);
+                self.custom_filter.changed(gtk::FilterChange::Different);
             }
         }
         self.update_view(widgets, sender);
     }
 }
+
+impl SearchWidget {
+    fn show_results(
+        &mut self,
+        query: String,
+        results: SearchResults,
+        widgets: &SearchWidgetWidgets,
+        sender: &AsyncComponentSender<Self>,
+        root: &adw::NavigationPage,
+    ) {
+        self.lists.borrow_mut().clear();
+        match results {
+            SearchResults::Songs(songs) => {
+                let list = self.song_list(songs, Some(query), sender);
+                widgets.scrolled.set_child(Some(&list));
+            }
+            SearchResults::Albums(albums) => {
+                let list = self.album_list(albums, Some(query), sender, root);
+                widgets.scrolled.set_child(Some(&list));
+            }
+            SearchResults::Artists(artists) => {
+                let list = self.artist_list(artists, Some(query), sender, root);
+                widgets.scrolled.set_child(Some(&list));
+            }
+            SearchResults::All { artists, albums, songs } => {
+                self.page_state = if artists.is_empty() && albums.is_empty() && songs.is_empty() {
+                    PageState::End
+                } else {
+                    PageState::Idle
+                };
+                let sections = gtk::Box::new(gtk::Orientation::Vertical, 5);
+                sections.add_css_class("padded");
+                if !artists.is_empty() {
+                    sections.append(&section_label("Artists"));
+                    sections.append(&self.artist_list(artists, None, sender, root));
+                }
+                if !albums.is_empty() {
+                    sections.append(&section_label("Albums"));
+                    sections.append(&self.album_list(albums, None, sender, root));
+                }
+                if !songs.is_empty() {
+                    sections.append(&section_label("Songs"));
+                    sections.append(&self.song_list(songs, None, sender));
+                }
+                widgets.scrolled.set_child(Some(&sections));
+            }
+        }
+    }
+
+    /// Launches a list for the given items. If `load_page` is set, further results are loaded when scrolling down.
+    fn launch_list<T: glib::object::IsA<glib::Object> + glib::object::ObjectType>(
+        &self,
+        init: ItemListInit<Vec<T>, Ready<Vec<T>>, T>,
+        sender: &AsyncComponentSender<Self>,
+    ) -> gtk::ListView {
+        let paged = init.load_page.is_some();
+        let generation = self.generation;
+        let controller = ItemListWidget::builder()
+            .launch(init)
+            .forward(sender.input_sender(), |msg| match msg {
+                ItemListOut::PageState(state) => SearchMsg::PageState(generation, state),
+            });
+        if paged {
+            self.paged_list.replace(Some(controller.sender().clone()));
+        }
+        let list = controller.widget().clone();
+        self.lists.borrow_mut().push(Box::new(controller));
+        list
+    }
+
+    fn song_list(&self, songs: Vec<SongObject>, query: Option<String>, sender: &AsyncComponentSender<Self>) -> gtk::ListView {
+        let song_cache = self.song_cache.clone();
+        self.launch_list(ItemListInit {
+            mpris_player: self.mpris_player.clone(),
+            cover_type: CoverType::Square,
+            cover_cache: self.cover_cache.clone(),
+            play_fn: Some(Box::new(|item: SongObject, _i, mpris_player| {
+                if let Some(item) = item.get_entry() {
+                    let mpris_player = mpris_player.clone();
+                    relm4::spawn_local(async move {
+                        let mpris_player = mpris_player.clone();
+                        mpris_player
+                            .imp()
+                            .send_res(mpris_player.imp().set_song(item).await);
+                    });
+                }
+            })),
+            click_fn: None,
+            load_items: ready(songs),
+            highlight: None,
+            filter: Some(self.custom_filter.clone().upcast()),
+            load_page: query.map(|query| PageLoader {
+                page_size: PAGE_SIZE,
+                load: Box::new(move |offset| {
+                    Box::pin(search_songs(song_cache.clone(), query.clone(), offset))
+                }),
+            }),
+        }, sender)
+    }
+
+    fn album_list(&self, albums: Vec<AlbumObject>, query: Option<String>, sender: &AsyncComponentSender<Self>, root: &adw::NavigationPage) -> gtk::ListView {
+        let album_cache = self.album_cache.clone();
+        self.launch_list(ItemListInit {
+            mpris_player: self.mpris_player.clone(),
+            cover_type: CoverType::Square,
+            cover_cache: self.cover_cache.clone(),
+            play_fn: Some(Box::new(|item: AlbumObject, _i, mpris_player| {
+                let mpris_player = mpris_player.clone();
+                relm4::spawn_local(async move {
+                    let mpris_player = mpris_player.clone();
+                    mpris_player.imp().send_res(
+                        mpris_player.imp().queue_album(item.id(), None, true).await,
+                    );
+                });
+            })),
+            click_fn: Some(Box::new(clone!(
+                #[weak]
+                root,
+                move |item, _i, _mpris_player| {
+                    let album = item
+                        .downcast::<AlbumObject>()
+                        .expect("Item should be AlbumObject");
+                    root.activate_action(
+                        "win.album",
+                        Some(&album.id().to_variant()),
+                    )
+                    .expect("Error executing action");
+                }
+            ))),
+            load_items: ready(albums),
+            highlight: None,
+            filter: Some(self.custom_filter.clone().upcast()),
+            load_page: query.map(|query| PageLoader {
+                page_size: PAGE_SIZE,
+                load: Box::new(move |offset| {
+                    Box::pin(search_albums(album_cache.clone(), query.clone(), offset))
+                }),
+            }),
+        }, sender)
+    }
+
+    fn artist_list(&self, artists: Vec<ArtistObject>, query: Option<String>, sender: &AsyncComponentSender<Self>, root: &adw::NavigationPage) -> gtk::ListView {
+        let artist_cache = self.artist_cache.clone();
+        self.launch_list(ItemListInit {
+            mpris_player: self.mpris_player.clone(),
+            cover_type: CoverType::Round,
+            cover_cache: self.cover_cache.clone(),
+            play_fn: None,
+            click_fn: Some(Box::new(clone!(
+                #[weak]
+                root,
+                move |artist: ArtistObject, _i, _mpris_player| {
+                    root.activate_action(
+                        "win.artist",
+                        Some(&artist.id().to_variant()),
+                    )
+                    .expect("Error executing action");
+                }
+            ))),
+            load_items: ready(artists),
+            highlight: None,
+            filter: Some(self.custom_filter.clone().upcast()),
+            load_page: query.map(|query| PageLoader {
+                page_size: PAGE_SIZE,
+                load: Box::new(move |offset| {
+                    Box::pin(search_artists(artist_cache.clone(), query.clone(), offset))
+                }),
+            }),
+        }, sender)
+    }
+}
+
+fn section_label(label: &str) -> gtk::Label {
+    let label = gtk::Label::new(Some(label));
+    label.add_css_class("t0");
+    label.add_css_class("bold");
+    label.set_halign(Align::Start);
+    label.set_justify(Justification::Left);
+    label
+}
+
+fn song_object(song: &Rc<crate::opensonic::types::Song>) -> SongObject {
+    SongObject::new(
+        (Uuid::from_u128(0), song.clone()).into(),
+        PositionState::Passed,
+    )
+}
+
+/// Shows the error and carries on without results
+fn or_report<T>(result: Result<Vec<T>, Box<dyn Error>>, mpris_player: &LocalServer<MprisPlayer>) -> Vec<T> {
+    result.unwrap_or_else(|err| {
+        mpris_player.imp().send_error(err);
+        Vec::new()
+    })
+}
+
+async fn search_songs(song_cache: SongCache, query: String, offset: u32) -> Result<Vec<SongObject>, Box<dyn Error>> {
+    Ok(song_cache.search(&query, PAGE_SIZE, Some(offset)).await?.iter().map(song_object).collect())
+}
+
+async fn search_albums(album_cache: AlbumCache, query: String, offset: u32) -> Result<Vec<AlbumObject>, Box<dyn Error>> {
+    album_cache.search(&query, PAGE_SIZE, Some(offset)).await
+}
+
+async fn search_artists(artist_cache: ArtistCache, query: String, offset: u32) -> Result<Vec<ArtistObject>, Box<dyn Error>> {
+    artist_cache.search(&query, PAGE_SIZE, Some(offset)).await
+}
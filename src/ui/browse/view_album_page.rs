@@ -2,6 +2,7 @@ use crate::dbus::player::MprisPlayer;
 use crate::opensonic::cache::{AlbumCache, ArtistCache, CoverCache, SongCache};
 use crate::ui::album_object::AlbumObject;
 use crate::ui::cover_picture::{CoverPicture, CoverSize, CoverType};
+use crate::ui::info_dialog;
 use crate::ui::song_object::{PositionState, SongObject};
 use crate::icon_names;
 use mpris_server::LocalServer;
@@ -143,6 +144,14 @@ impl AsyncComponent for ViewAlbumWidget {
                                     },
                                     add_css_class: "album-play-btn",
                                     set_tooltip: "Add to queue",
+                                },
+                                #[name = "menu_btn"]
+                                gtk::MenuButton {
+                                    set_valign: Align::Center,
+                                    set_halign: Align::Center,
+                                    set_icon_name: icon_names::shipped::MENU,
+                                    add_css_class: "flat",
+                                    set_tooltip: "More",
                                 }
                             }
                         },
@@ -195,10 +204,16 @@ impl AsyncComponent for ViewAlbumWidget {
                         .into_iter()
                         .map(|x| SongObject::new((Uuid::from_u128(0), x.clone()).into(), PositionState::Passed))
                 },
-                highlight: init.5
+                highlight: init.5,
+                load_page: None,
+                filter: None,
             });
 
         let widgets: Self::Widgets = view_output!();
+        // Built on every open so the star entry reflects the current state
+        widgets.menu_btn.set_create_popup_func(move |btn| {
+            btn.set_popover(Some(&info_dialog::make_popup_menu(&album, false)));
+        });
 
         AsyncComponentParts { model, widgets }
     }
@@ -208,6 +208,8 @@ impl AsyncComponent for ViewArtistWidget {
                     }
                 },
                 highlight: None,
+                load_page: None,
+                filter: None,
             });
 
         let widgets: Self::Widgets = view_output!();
@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use mpris_server::LocalServer;
+use relm4::adw::prelude::*;
+use relm4::gtk::glib::clone;
+use relm4::gtk::{Align, Orientation};
+use relm4::prelude::*;
+use relm4::{adw, gtk, AsyncComponentSender, RelmWidgetExt};
+
+use crate::dbus::player::MprisPlayer;
+use crate::icon_names;
+use crate::opensonic::cache::AlbumCache;
+use crate::opensonic::types::AlbumListType;
+
+/// Largest page size the Subsonic API allows for album lists
+const PAGE_SIZE: u32 = 500;
+const DECADE_RANDOM_SONGS: u32 = 50;
+
+pub struct YearsWidget {
+    album_cache: AlbumCache,
+    mpris_player: Rc<LocalServer<MprisPlayer>>,
+    loading: bool,
+    /// Album counts per year, grouped by decade
+    decades: BTreeMap<u32, BTreeMap<u32, u32>>,
+}
+
+#[derive(Debug)]
+pub enum YearsMsg {
+    PlayDecade(u32),
+}
+
+pub type YearsInit = (Rc<LocalServer<MprisPlayer>>, AlbumCache);
+
+#[relm4::component(pub async)]
+impl AsyncComponent for YearsWidget {
+    type CommandOutput = ();
+    type Input = YearsMsg;
+    type Output = ();
+    type Init = YearsInit;
+
+    view! {
+        adw::NavigationPage {
+            set_title: "Years",
+
+            gtk::ScrolledWindow {
+                set_hscrollbar_policy: gtk::PolicyType::Never,
+                set_vexpand: true,
+
+                adw::Clamp {
+                    gtk::Box {
+                        set_orientation: Orientation::Vertical,
+                        set_spacing: 10,
+                        add_css_class: "padded",
+
+                        adw::Spinner {
+                            set_halign: Align::Center,
+                            #[watch]
+                            set_visible: model.loading,
+                        },
+                        gtk::Label {
+                            set_label: "No albums with a release year",
+                            add_css_class: "dim-label",
+                            #[watch]
+                            set_visible: !model.loading && model.decades.is_empty(),
+                        },
+                        #[name = "decades_list"]
+                        gtk::ListBox {
+                            add_css_class: "boxed-list",
+                            set_selection_mode: gtk::SelectionMode::None,
+                            #[watch]
+                            set_visible: !model.decades.is_empty(),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    async fn init(
+        init: Self::Init,
+        root: Self::Root,
+        sender: AsyncComponentSender<Self>,
+    ) -> AsyncComponentParts<Self> {
+        let mut model = Self {
+            mpris_player: init.0,
+            album_cache: init.1,
+            loading: true,
+            decades: BTreeMap::new(),
+        };
+
+        let mut widgets: Self::Widgets = view_output!();
+
+        match model.count_years().await {
+            Ok(decades) => model.decades = decades,
+            Err(err) => model.mpris_player.imp().send_error(err),
+        }
+        model.loading = false;
+        for (decade, years) in model.decades.iter().rev() {
+            widgets.decades_list.append(&decade_row(*decade, years, &sender));
+        }
+        model.update_view(&mut widgets, sender);
+
+        AsyncComponentParts { model, widgets }
+    }
+
+    async fn update(
+        &mut self,
+        message: Self::Input,
+        _sender: AsyncComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match message {
+            YearsMsg::PlayDecade(decade) => {
+                let player = self.mpris_player.imp();
+                player.send_res(player
+                    .queue_random(DECADE_RANDOM_SONGS, None, Some(decade), Some(decade + 9), true)
+                    .await);
+            }
+        }
+    }
+}
+
+impl YearsWidget {
+    /// Goes through every album that has a year, there's no API for the counts directly
+    async fn count_years(&self) -> Result<BTreeMap<u32, BTreeMap<u32, u32>>, Box<dyn std::error::Error>> {
+        let mut decades: BTreeMap<u32, BTreeMap<u32, u32>> = BTreeMap::new();
+        let mut offset = 0;
+        loop {
+            let albums = self.album_cache
+                .get_album_list(AlbumListType::ByYear, Some(PAGE_SIZE), Some(offset), Some(1), Some(9999), None, None)
+                .await?;
+            for year in albums.iter().filter_map(|a| a.get_inner().and_then(|a| a.year)) {
+                *decades.entry(year / 10 * 10).or_default().entry(year).or_default() += 1;
+            }
+            if (albums.len() as u32) < PAGE_SIZE {
+                return Ok(decades);
+            }
+            offset += PAGE_SIZE;
+        }
+    }
+}
+
+fn decade_row(decade: u32, years: &BTreeMap<u32, u32>, sender: &AsyncComponentSender<YearsWidget>) -> adw::ExpanderRow {
+    let album_count: u32 = years.values().sum();
+    let row = adw::ExpanderRow::builder()
+        .title(format!("{}s", decade))
+        .subtitle(format!("{} albums", album_count))
+        .build();
+
+    let play_btn = gtk::Button::from_icon_name(icon_names::shipped::PLAY);
+    play_btn.add_css_class("flat");
+    play_btn.set_valign(Align::Center);
+    play_btn.set_tooltip(format!("Play random songs from the {}s", decade).as_str());
+    play_btn.connect_clicked(clone!(
+        #[strong]
+        sender,
+        move |_| sender.input(YearsMsg::PlayDecade(decade))
+    ));
+    row.add_suffix(&play_btn);
+
+    for (year, count) in years.iter().rev() {
+        let year_row = adw::ActionRow::builder()
+            .title(year.to_string())
+            .subtitle(format!("{} albums", count))
+            .activatable(true)
+            .build();
+        year_row.set_action_name(Some("win.year"));
+        year_row.set_action_target_value(Some(&year.to_string().to_variant()));
+        row.add_row(&year_row);
+    }
+    row
+}
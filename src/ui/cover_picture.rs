@@ -12,6 +12,7 @@ use std::cell::{Cell, RefCell};
 use color_thief::{Color, ColorFormat};
 use relm4::adw::gdk::{MemoryFormat, TextureDownloader};
 use crate::opensonic::cache::CoverCache;
+use tracing::warn;
 
 #[derive(Clone, Copy, Debug, glib::Enum, PartialEq, Default)]
 #[enum_type(name = "SanicCoverSize")]
@@ -393,7 +394,7 @@ impl CoverPicture {
                                 cover_widget.set_cover(Some(&resp));
                             }
                             Err(e) => {
-                                println!("Error getting cover image: {}", e);
+                                warn!("Error getting cover image: {}", e);
                                 cover_widget.queue_draw();
                                 cover_widget.notify("cover");
                             }
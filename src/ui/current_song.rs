@@ -1,6 +1,6 @@
 use crate::dbus::player::MprisPlayer;
 use crate::opensonic::cache::{LyricsCache, SongCache};
-use crate::opensonic::types::Song;
+use crate::opensonic::types::{LyricsList, Song};
 use crate::player::{SongEntry, MAX_PLAYBACK_RATE, MIN_PLAYBACK_RATE};
 use crate::ui::app::{Init, NextAction, PlayPauseAction, PreviousAction, ShowRandomSongsAction, ShowTracklistAction};
 use crate::ui::cover_picture::{CoverPicture, CoverSize};
@@ -22,7 +22,9 @@ use relm4::component::{AsyncComponent, AsyncComponentParts, AsyncComponentSender
 use relm4::gtk::glib::{clone, closure, Object};
 use relm4::gtk::{Justification, ListItem, ListScrollFlags, SignalListItemFactory, Widget};
 use relm4::prelude::*;
+use std::error::Error;
 use std::rc::Rc;
+use std::sync::Arc;
 use std::time::Duration;
 use gstreamer_play::PlayState;
 use uuid::Uuid;
@@ -50,6 +52,7 @@ pub enum CurrentSongMsg {
     ToggleLyrics,
     Update,
     ToggleStarred,
+    ImportLyrics,
 }
 
 #[derive(Debug)]
@@ -446,7 +449,7 @@ impl AsyncComponent for CurrentSong {
         widgets: &mut Self::Widgets,
         message: Self::Input,
         sender: AsyncComponentSender<Self>,
-        _root: &Self::Root,
+        root: &Self::Root,
     ) {
         let player = self.mpris_player.imp();
         match message {
@@ -463,24 +466,8 @@ impl AsyncComponent for CurrentSong {
                 self.song_info = match info {
                     None => None,
                     Some(i) => {
-                        let lyrics = self.lyrics_cache.get_lyrics(&i.song.id).await;
-                        match lyrics {
-                            Ok(l) => {
-                                if let Some(list) = l.get(0){
-                                    let lines = lyrics_line::from_list(list);
-                                    let lyrics_store = ListStore::from_iter(lines);
-                                    widgets.lyrics_list.set_model(Some(&gtk::NoSelection::new(Some(lyrics_store))));
-                                    self.synced_lyrics = l[0].synced;
-                                    self.has_lyrics = true;
-                                } else {
-                                    self.show_lyrics = false;
-                                }
-                            },
-                            Err(e) => {
-                                self.show_lyrics = false;
-                                player.send_error(e);
-                            },
-                        }
+                        let lyrics = self.lyrics_cache.get_lyrics(&i.song).await;
+                        self.set_lyrics(lyrics, &widgets.lyrics_list);
                         Some(i.song)
                     },
                 };
@@ -506,12 +493,68 @@ impl AsyncComponent for CurrentSong {
                     player.send_res(self.song_cache.toggle_starred(song).await);
                 }
             },
+            CurrentSongMsg::ImportLyrics => {
+                if let Some(song) = self.song_info.clone() {
+                    let filter = gtk::FileFilter::new();
+                    filter.set_name(Some("Lyrics files"));
+                    filter.add_suffix("lrc");
+                    filter.add_suffix("txt");
+                    let filters = ListStore::new::<gtk::FileFilter>();
+                    filters.append(&filter);
+                    let dialog = gtk::FileDialog::builder()
+                        .title("Import lyrics file")
+                        .filters(&filters)
+                        .default_filter(&filter)
+                        .build();
+                    let window = root.root().and_downcast::<gtk::Window>();
+                    // Dismissing the dialog also returns an error, so it's ignored
+                    if let Ok(file) = dialog.open_future(window.as_ref()).await && let Some(path) = file.path() {
+                        // A failed import leaves the lyrics that are showing alone
+                        match self.lyrics_cache.import_lyrics(&song, &path).await {
+                            Ok(lyrics) => {
+                                self.set_lyrics(Ok(lyrics), &widgets.lyrics_list);
+                                if self.has_lyrics {
+                                    self.show_lyrics = true;
+                                    self.update_lyrics(&widgets.lyrics_list);
+                                }
+                            },
+                            Err(err) => player.send_error(err),
+                        }
+                    }
+                } else {
+                    player.send_error("No song is playing".into());
+                }
+            },
         }
         self.update_view(widgets, sender);
     }
 }
 
 impl CurrentSong {
+    fn set_lyrics(
+        &mut self,
+        lyrics: Result<Arc<Vec<LyricsList>>, Box<dyn Error>>,
+        list_view: &gtk::ListView
+    ) {
+        match lyrics {
+            Ok(l) => {
+                if let Some(list) = l.get(0){
+                    let lines = lyrics_line::from_list(list);
+                    let lyrics_store = ListStore::from_iter(lines);
+                    list_view.set_model(Some(&gtk::NoSelection::new(Some(lyrics_store))));
+                    self.synced_lyrics = l[0].synced;
+                    self.has_lyrics = true;
+                } else {
+                    self.show_lyrics = false;
+                }
+            },
+            Err(e) => {
+                self.show_lyrics = false;
+                self.mpris_player.imp().send_error(e);
+            },
+        }
+    }
+
     fn update_lyrics(
         &mut self,
         list_view: &gtk::ListView
@@ -0,0 +1,171 @@
+use relm4::adw::glib::{clone, Object};
+use relm4::adw::prelude::*;
+use relm4::adw::{gdk, glib};
+use relm4::gtk;
+
+use crate::ui::album_object::AlbumObject;
+use crate::ui::artist_object::ArtistObject;
+use crate::ui::song_object::SongObject;
+
+/// Queue rows being dragged within the queue, by their index
+#[derive(Clone, Debug, glib::Boxed)]
+#[boxed_type(name = "SanicQueueRows")]
+pub struct QueueRows(pub Vec<u32>);
+
+/// What was dropped on the queue
+#[derive(Debug)]
+pub enum Dropped {
+    QueueRows(Vec<u32>),
+    Uris(Vec<String>),
+}
+
+/// `sanic://` URI of a song, album or artist object
+pub fn item_uri(item: &Object) -> Option<String> {
+    let uri_type = if item.is::<SongObject>() {
+        "song"
+    } else if item.is::<AlbumObject>() {
+        "album"
+    } else if item.is::<ArtistObject>() {
+        "artist"
+    } else {
+        return None;
+    };
+    let id: String = item.property("id");
+    Some(format!("sanic://{}/{}", uri_type, id))
+}
+
+/// `sanic://` URIs in dropped text, one per line as in text/uri-list
+pub fn parse_uris(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("sanic://"))
+        .map(String::from)
+        .collect()
+}
+
+/// Items to drag from a row: the whole selection if the row is part of a bigger one, otherwise just its item
+pub fn row_items(list_item: &gtk::ListItem, selection: Option<gtk::SelectionModel>) -> Vec<Object> {
+    let position = list_item.position();
+    if let Some(selection) = selection && selection.is_selected(position) {
+        let bitset = selection.selection();
+        if bitset.size() > 1 {
+            return (0..bitset.size() as u32)
+                .filter_map(|i| selection.item(bitset.nth(i)))
+                .collect();
+        }
+    }
+    list_item.item().into_iter().collect()
+}
+
+fn uri_content(uris: &[String]) -> gdk::ContentProvider {
+    let text = uris.join("\r\n");
+    gdk::ContentProvider::new_union(&[
+        gdk::ContentProvider::for_bytes("text/uri-list", &glib::Bytes::from_owned(format!("{}\r\n", text))),
+        gdk::ContentProvider::for_value(&text.to_value()),
+    ])
+}
+
+/// Lets the items returned by `items` be dragged from `widget` as `sanic://` URIs
+pub fn add_drag_source<F: Fn() -> Vec<Object> + 'static>(widget: &impl IsA<gtk::Widget>, items: F) {
+    let widget = widget.upcast_ref::<gtk::Widget>().clone();
+    let source = gtk::DragSource::new();
+    source.set_actions(gdk::DragAction::COPY);
+    source.connect_prepare(move |_, _, _| {
+        let uris: Vec<String> = items().iter().filter_map(item_uri).collect();
+        (!uris.is_empty()).then(|| uri_content(&uris))
+    });
+    source.connect_drag_begin(clone!(
+        #[weak]
+        widget,
+        move |source, _| {
+            source.set_icon(Some(&gtk::WidgetPaintable::new(Some(&widget))), 0, 0);
+        }
+    ));
+    widget.add_controller(source);
+}
+
+/// Lets queue rows be dragged, `rows` returns the dragged rows' indexes
+pub fn add_queue_drag_source<F: Fn() -> Vec<u32> + 'static>(widget: &impl IsA<gtk::Widget>, rows: F) {
+    let widget = widget.upcast_ref::<gtk::Widget>().clone();
+    let source = gtk::DragSource::new();
+    source.set_actions(gdk::DragAction::MOVE);
+    source.connect_prepare(move |_, _, _| {
+        let rows = rows();
+        (!rows.is_empty()).then(|| gdk::ContentProvider::for_value(&QueueRows(rows).to_value()))
+    });
+    source.connect_drag_begin(clone!(
+        #[weak]
+        widget,
+        move |source, _| {
+            source.set_icon(Some(&gtk::WidgetPaintable::new(Some(&widget))), 0, 0);
+        }
+    ));
+    widget.add_controller(source);
+}
+
+/// Drop target for the queue accepting its own rows and `sanic://` URIs, from this app or others.
+/// `on_drop` gets the drop and its y coordinate in the widget.
+pub fn queue_drop_target<F: Fn(Dropped, f64) -> bool + 'static>(on_drop: F) -> gtk::DropTarget {
+    let target = gtk::DropTarget::new(glib::Type::INVALID, gdk::DragAction::COPY | gdk::DragAction::MOVE);
+    // text/uri-list is deserialized into a file list, which is preferred over plain text
+    target.set_types(&[QueueRows::static_type(), gdk::FileList::static_type(), String::static_type()]);
+    target.connect_drop(move |_, value, _, y| {
+        if let Ok(rows) = value.get::<QueueRows>() {
+            on_drop(Dropped::QueueRows(rows.0), y)
+        } else if let Ok(files) = value.get::<gdk::FileList>() {
+            let uris: Vec<String> = files.files().iter()
+                .map(|file| file.uri().to_string())
+                .filter(|uri| uri.starts_with("sanic://"))
+                .collect();
+            !uris.is_empty() && on_drop(Dropped::Uris(uris), y)
+        } else if let Ok(text) = value.get::<String>() {
+            let uris = parse_uris(&text);
+            !uris.is_empty() && on_drop(Dropped::Uris(uris), y)
+        } else {
+            false
+        }
+    });
+    target
+}
+
+/// Whether a drop at `y` goes after the row rather than before it
+pub fn drops_below(widget: &impl IsA<gtk::Widget>, y: f64) -> bool {
+    y > widget.height() as f64 / 2.0
+}
+
+/// Shows a line where a drop on the row would go, above or below it
+pub fn add_drop_indicator(widget: &impl IsA<gtk::Widget>) {
+    let widget = widget.upcast_ref::<gtk::Widget>().clone();
+    let motion = gtk::DropControllerMotion::new();
+    motion.connect_enter(clone!(
+        #[weak]
+        widget,
+        move |_, _, y| show_drop_indicator(&widget, y)
+    ));
+    motion.connect_motion(clone!(
+        #[weak]
+        widget,
+        move |_, _, y| show_drop_indicator(&widget, y)
+    ));
+    motion.connect_leave(clone!(
+        #[weak]
+        widget,
+        move |_| clear_drop_indicator(&widget)
+    ));
+    widget.add_controller(motion);
+}
+
+fn show_drop_indicator(widget: &gtk::Widget, y: f64) {
+    if drops_below(widget, y) {
+        widget.remove_css_class("drop-above");
+        widget.add_css_class("drop-below");
+    } else {
+        widget.remove_css_class("drop-below");
+        widget.add_css_class("drop-above");
+    }
+}
+
+pub fn clear_drop_indicator(widget: &impl IsA<gtk::Widget>) {
+    widget.remove_css_class("drop-above");
+    widget.remove_css_class("drop-below");
+}
@@ -0,0 +1,341 @@
+use std::time::Duration;
+
+use relm4::adw::gtk;
+use relm4::adw::prelude::*;
+use relm4::gtk::glib::Object;
+use relm4::gtk::Orientation;
+use relm4::prelude::*;
+
+use crate::opensonic::types::{Album, Genre, Song};
+use crate::ui::album_object::AlbumObject;
+use crate::ui::artist_object::ArtistObject;
+use crate::ui::song_object::SongObject;
+
+const LOSSLESS_SUFFIXES: [&str; 9] = ["flac", "alac", "wav", "aif", "aiff", "ape", "wv", "dsf", "dff"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ExplicitFilter {
+    #[default]
+    Any,
+    Explicit,
+    Clean,
+}
+
+/// Client side filter over results that have already been loaded
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResultFilter {
+    pub year_from: Option<u32>,
+    pub year_to: Option<u32>,
+    pub genre: Option<String>,
+    pub starred_only: bool,
+    pub min_rating: Option<u8>,
+    pub explicit: ExplicitFilter,
+    pub lossless_only: bool,
+    pub min_duration: Option<Duration>,
+    pub max_duration: Option<Duration>,
+}
+
+impl ResultFilter {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Checks a `SongObject`, `AlbumObject` or `ArtistObject`. Filters that don't apply to an item type are ignored.
+    pub fn matches(&self, item: &Object) -> bool {
+        if let Some(song) = item.downcast_ref::<SongObject>() {
+            song.get_entry().is_none_or(|s| self.matches_song(&s))
+        } else if let Some(album) = item.downcast_ref::<AlbumObject>() {
+            album.get_inner().is_none_or(|a| self.matches_album(&a))
+        } else if let Some(artist) = item.downcast_ref::<ArtistObject>() {
+            !self.starred_only || artist.starred()
+        } else {
+            true
+        }
+    }
+
+    pub fn matches_song(&self, song: &Song) -> bool {
+        self.matches_year(song.year.and_then(|y| u32::try_from(y).ok()))
+            && self.matches_genre(song.genre.as_deref(), song.genres.as_deref())
+            && (!self.starred_only || song.is_starred())
+            && self.min_rating.is_none_or(|r| song.user_rating.unwrap_or(0) >= r)
+            && self.matches_explicit(song.explicit_status.as_deref())
+            && (!self.lossless_only || is_lossless(song))
+            && self.matches_duration(song.duration)
+    }
+
+    pub fn matches_album(&self, album: &Album) -> bool {
+        self.matches_year(album.year)
+            && self.matches_genre(album.genre.as_deref(), album.genres.as_deref())
+            && (!self.starred_only || album.is_starred())
+            && self.min_rating.is_none_or(|r| album.user_rating.unwrap_or(0) >= r)
+            && self.matches_explicit(album.explicit_status.as_deref())
+            // Albums only carry file info when their songs were loaded
+            && (!self.lossless_only || album.songs.as_ref().is_none_or(|s| s.iter().any(is_lossless)))
+            && self.matches_duration(Some(album.duration))
+    }
+
+    fn matches_year(&self, year: Option<u32>) -> bool {
+        if self.year_from.is_none() && self.year_to.is_none() {
+            return true;
+        }
+        let Some(year) = year else {
+            return false;
+        };
+        self.year_from.is_none_or(|from| year >= from) && self.year_to.is_none_or(|to| year <= to)
+    }
+
+    fn matches_genre(&self, genre: Option<&str>, genres: Option<&[Genre]>) -> bool {
+        let Some(wanted) = &self.genre else {
+            return true;
+        };
+        genre.is_some_and(|g| g.eq_ignore_ascii_case(wanted))
+            || genres.is_some_and(|gs| gs.iter().any(|g| g.name.eq_ignore_ascii_case(wanted)))
+    }
+
+    fn matches_explicit(&self, status: Option<&str>) -> bool {
+        match self.explicit {
+            ExplicitFilter::Any => true,
+            ExplicitFilter::Explicit => status == Some("explicit"),
+            ExplicitFilter::Clean => status == Some("clean"),
+        }
+    }
+
+    fn matches_duration(&self, duration: Option<Duration>) -> bool {
+        if self.min_duration.is_none() && self.max_duration.is_none() {
+            return true;
+        }
+        let Some(duration) = duration else {
+            return false;
+        };
+        self.min_duration.is_none_or(|min| duration >= min) && self.max_duration.is_none_or(|max| duration <= max)
+    }
+}
+
+fn is_lossless(song: &Song) -> bool {
+    match &song.suffix {
+        Some(suffix) => LOSSLESS_SUFFIXES.contains(&suffix.to_lowercase().as_str()),
+        None => song.bit_depth.is_some_and(|d| d > 0),
+    }
+}
+
+fn range_label<T: std::fmt::Display>(name: &str, from: Option<T>, to: Option<T>) -> String {
+    match (from, to) {
+        (Some(from), Some(to)) => format!("{} {}–{}", name, from, to),
+        (Some(from), None) => format!("{} ≥ {}", name, from),
+        (None, Some(to)) => format!("{} ≤ {}", name, to),
+        (None, None) => name.to_string(),
+    }
+}
+
+pub struct FilterBar {
+    filter: ResultFilter,
+}
+
+#[derive(Debug)]
+pub enum FilterBarMsg {
+    Changed,
+    Clear,
+}
+
+#[derive(Debug)]
+pub enum FilterBarOut {
+    Changed(ResultFilter),
+}
+
+#[relm4::component(pub async)]
+impl AsyncComponent for FilterBar {
+    type CommandOutput = ();
+    type Input = FilterBarMsg;
+    type Output = FilterBarOut;
+    type Init = ();
+
+    view! {
+        gtk::ScrolledWindow {
+            set_vscrollbar_policy: gtk::PolicyType::Never,
+            set_hscrollbar_policy: gtk::PolicyType::Automatic,
+
+            gtk::Box {
+                set_orientation: Orientation::Horizontal,
+                set_spacing: 5,
+                add_css_class: "padded",
+
+                #[name = "starred"]
+                gtk::ToggleButton {
+                    set_label: "Starred",
+                    connect_toggled => FilterBarMsg::Changed,
+                },
+                #[name = "lossless"]
+                gtk::ToggleButton {
+                    set_label: "Lossless",
+                    connect_toggled => FilterBarMsg::Changed,
+                },
+                gtk::MenuButton {
+                    #[watch]
+                    set_label: &range_label("Year", model.filter.year_from, model.filter.year_to),
+
+                    #[wrap(Some)]
+                    set_popover = &gtk::Popover {
+                        gtk::Box {
+                            set_orientation: Orientation::Horizontal,
+                            set_spacing: 5,
+
+                            gtk::Label {
+                                set_label: "From",
+                            },
+                            #[name = "year_from"]
+                            gtk::SpinButton::with_range(0.0, 9999.0, 1.0) {
+                                set_tooltip: "0 for any",
+                                connect_value_changed => FilterBarMsg::Changed,
+                            },
+                            gtk::Label {
+                                set_label: "to",
+                            },
+                            #[name = "year_to"]
+                            gtk::SpinButton::with_range(0.0, 9999.0, 1.0) {
+                                set_tooltip: "0 for any",
+                                connect_value_changed => FilterBarMsg::Changed,
+                            },
+                        }
+                    }
+                },
+                gtk::MenuButton {
+                    #[watch]
+                    set_label: &model.filter.genre.as_ref().map(|g| format!("Genre: {}", g)).unwrap_or("Genre".to_string()),
+
+                    #[wrap(Some)]
+                    set_popover = &gtk::Popover {
+                        #[name = "genre"]
+                        gtk::Entry {
+                            set_placeholder_text: Some("Any genre"),
+                            connect_changed => FilterBarMsg::Changed,
+                        }
+                    }
+                },
+                #[name = "min_rating"]
+                gtk::DropDown {
+                    set_enable_search: false,
+                    set_model: Some(&gtk::StringList::new(&["Any rating", "1+ stars", "2+ stars", "3+ stars", "4+ stars", "5 stars"])),
+                    connect_selected_notify => FilterBarMsg::Changed,
+                },
+                #[name = "explicit"]
+                gtk::DropDown {
+                    set_enable_search: false,
+                    set_model: Some(&gtk::StringList::new(&["Explicit or clean", "Explicit", "Clean"])),
+                    connect_selected_notify => FilterBarMsg::Changed,
+                },
+                gtk::MenuButton {
+                    #[watch]
+                    set_label: &range_label(
+                        "Minutes",
+                        model.filter.min_duration.map(|d| d.as_secs() / 60),
+                        model.filter.max_duration.map(|d| d.as_secs() / 60)
+                    ),
+
+                    #[wrap(Some)]
+                    set_popover = &gtk::Popover {
+                        gtk::Box {
+                            set_orientation: Orientation::Horizontal,
+                            set_spacing: 5,
+
+                            gtk::Label {
+                                set_label: "From",
+                            },
+                            #[name = "min_duration"]
+                            gtk::SpinButton::with_range(0.0, 600.0, 1.0) {
+                                set_tooltip: "0 for any",
+                                connect_value_changed => FilterBarMsg::Changed,
+                            },
+                            gtk::Label {
+                                set_label: "to",
+                            },
+                            #[name = "max_duration"]
+                            gtk::SpinButton::with_range(0.0, 600.0, 1.0) {
+                                set_tooltip: "0 for any",
+                                connect_value_changed => FilterBarMsg::Changed,
+                            },
+                        }
+                    }
+                },
+                gtk::Button {
+                    set_label: "Clear filters",
+                    add_css_class: "flat",
+                    #[watch]
+                    set_visible: !model.filter.is_empty(),
+                    connect_clicked => FilterBarMsg::Clear,
+                },
+            }
+        }
+    }
+
+    async fn init(
+        _init: Self::Init,
+        root: Self::Root,
+        sender: AsyncComponentSender<Self>,
+    ) -> AsyncComponentParts<Self> {
+        let model = Self {
+            filter: ResultFilter::default(),
+        };
+
+        let widgets: Self::Widgets = view_output!();
+
+        AsyncComponentParts { model, widgets }
+    }
+
+    async fn update_with_view(
+        &mut self,
+        widgets: &mut Self::Widgets,
+        message: Self::Input,
+        sender: AsyncComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match message {
+            FilterBarMsg::Changed => {
+                let filter = read_filter(widgets);
+                if filter != self.filter {
+                    self.filter = filter;
+                    sender.output(FilterBarOut::Changed(self.filter.clone())).expect("Error sending message out");
+                }
+            }
+            FilterBarMsg::Clear => {
+                // Every reset widget sends Changed, those find the filter already cleared and refilter nothing
+                if !self.filter.is_empty() {
+                    self.filter = ResultFilter::default();
+                    sender.output(FilterBarOut::Changed(self.filter.clone())).expect("Error sending message out");
+                }
+                widgets.starred.set_active(false);
+                widgets.lossless.set_active(false);
+                widgets.year_from.set_value(0.0);
+                widgets.year_to.set_value(0.0);
+                widgets.genre.set_text("");
+                widgets.min_rating.set_selected(0);
+                widgets.explicit.set_selected(0);
+                widgets.min_duration.set_value(0.0);
+                widgets.max_duration.set_value(0.0);
+            }
+        }
+        self.update_view(widgets, sender);
+    }
+}
+
+fn read_filter(widgets: &FilterBarWidgets) -> ResultFilter {
+    let non_zero = |v: f64| if v >= 1.0 { Some(v as u32) } else { None };
+    let genre = widgets.genre.text().trim().to_string();
+    ResultFilter {
+        year_from: non_zero(widgets.year_from.value()),
+        year_to: non_zero(widgets.year_to.value()),
+        genre: if genre.is_empty() { None } else { Some(genre) },
+        starred_only: widgets.starred.is_active(),
+        min_rating: match widgets.min_rating.selected() {
+            0 => None,
+            r => Some(r as u8),
+        },
+        explicit: match widgets.explicit.selected() {
+            1 => ExplicitFilter::Explicit,
+            2 => ExplicitFilter::Clean,
+            _ => ExplicitFilter::Any,
+        },
+        lossless_only: widgets.lossless.is_active(),
+        min_duration: non_zero(widgets.min_duration.value()).map(|m| Duration::from_secs(m as u64 * 60)),
+        max_duration: non_zero(widgets.max_duration.value()).map(|m| Duration::from_secs(m as u64 * 60)),
+    }
+}
@@ -1,13 +1,14 @@
 use crate::dbus::player::MprisPlayer;
 use crate::opensonic::types::{duration_display_str, Song};
 use crate::ui::album_object::AlbumObject;
-use crate::ui::app::{PlayAlbum, PlaySong, QueueAlbum, QueueSong, ViewAlbumInfo, ViewArtistInfo, ViewSongInfo};
+use crate::ui::app::{CopyLink, PlayAlbum, PlayArtist, PlayArtistRadio, PlayNextAlbum, PlayNextSong, PlaySong, QueueAlbum, QueueSong, ShowInQueue, ShuffleArtist, StarAlbum, StarArtist, StarSong, StartRadio, ViewAlbumArtist, ViewAlbumInfo, ViewArtistAction, ViewArtistInfo, ViewSongAction, ViewSongArtist, ViewSongInfo};
 use crate::ui::artist_object::ArtistObject;
-use crate::ui::item_list::ItemType;
+use crate::ui::song_object::SongObject;
 use mpris_server::LocalServer;
 use relm4::actions::ActionName;
 use relm4::adw;
 use relm4::adw::gio;
+use relm4::adw::glib;
 use relm4::adw::gtk;
 use relm4::adw::prelude::*;
 use relm4::gtk::{Align, Orientation};
@@ -27,27 +28,58 @@ pub enum InfoDialogUpdate {
 
 pub type InfoDialogInit = Rc<LocalServer<MprisPlayer>>;
 
-pub fn make_popup_menu(item_type: ItemType, id: String) -> gtk::PopoverMenu {
-    let menu = gio::Menu::new();
-    let (view_info_action, play_action, queue_action) = match item_type {
-        ItemType::Song => (ViewSongInfo::action_name(), Some(PlaySong::action_name()), Some(QueueSong::action_name())),
-        ItemType::Album => (ViewAlbumInfo::action_name(), Some(PlayAlbum::action_name()), Some(QueueAlbum::action_name())),
-        ItemType::Artist => (ViewArtistInfo::action_name(), None, None),
+/// Context menu for a song, album or artist object. `in_queue` leaves out "Show in queue"
+pub fn make_popup_menu(item: &impl IsA<glib::Object>, in_queue: bool) -> gtk::PopoverMenu {
+    let item = item.upcast_ref::<glib::Object>();
+    let id: String = item.property("id");
+    let starred: bool = item.property("starred");
+    let id_item = |label: &str, action: String| {
+        let menu_item = gio::MenuItem::new(Some(label), None);
+        menu_item.set_action_and_target_value(Some(action.as_str()), Some(&id.to_variant()));
+        menu_item
     };
-    if let Some(play_action) = play_action {
-        let play_item = gio::MenuItem::new(Some("Play"), Some(play_action.as_str()));
-        play_item.set_action_and_target_value(Some(play_action.as_str()), Some(&id.to_variant()));
-        menu.append_item(&play_item);
-    }
-    if let Some(queue_action) = queue_action {
-        let queue_item = gio::MenuItem::new(Some("Add to queue"), Some(queue_action.as_str()));
-        queue_item.set_action_and_target_value(Some(queue_action.as_str()), Some(&id.to_variant()));
-        menu.append_item(&queue_item);
-    }
-    let info_item = gio::MenuItem::new(Some("View info"), Some(view_info_action.as_str()));
-    info_item.set_action_and_target_value(Some(view_info_action.as_str()), Some(&id.to_variant()));
-    menu.append_item(&info_item);
 
+    let play_section = gio::Menu::new();
+    let go_section = gio::Menu::new();
+    let item_section = gio::Menu::new();
+    let (uri_type, star_action, info_action) = if item.is::<SongObject>() {
+        play_section.append_item(&id_item("Play", PlaySong::action_name()));
+        play_section.append_item(&id_item("Play next", PlayNextSong::action_name()));
+        play_section.append_item(&id_item("Add to queue", QueueSong::action_name()));
+        go_section.append_item(&id_item("Go to album", ViewSongAction::action_name()));
+        go_section.append_item(&id_item("Go to artist", ViewSongArtist::action_name()));
+        go_section.append_item(&id_item("Start radio from this", StartRadio::action_name()));
+        if !in_queue {
+            item_section.append_item(&id_item("Show in queue", ShowInQueue::action_name()));
+        }
+        ("song", StarSong::action_name(), ViewSongInfo::action_name())
+    } else if item.is::<AlbumObject>() {
+        play_section.append_item(&id_item("Play", PlayAlbum::action_name()));
+        play_section.append_item(&id_item("Play next", PlayNextAlbum::action_name()));
+        play_section.append_item(&id_item("Add to queue", QueueAlbum::action_name()));
+        go_section.append_item(&id_item("Go to artist", ViewAlbumArtist::action_name()));
+        go_section.append_item(&id_item("Start radio from this", StartRadio::action_name()));
+        ("album", StarAlbum::action_name(), ViewAlbumInfo::action_name())
+    } else {
+        play_section.append_item(&id_item("Play all", PlayArtist::action_name()));
+        play_section.append_item(&id_item("Shuffle", ShuffleArtist::action_name()));
+        play_section.append_item(&id_item("Artist radio", PlayArtistRadio::action_name()));
+        go_section.append_item(&id_item("Go to artist", ViewArtistAction::action_name()));
+        ("artist", StarArtist::action_name(), ViewArtistInfo::action_name())
+    };
+    item_section.append_item(&id_item(if starred { "Unstar" } else { "Star" }, star_action));
+    let copy_item = gio::MenuItem::new(Some("Copy link"), None);
+    copy_item.set_action_and_target_value(
+        Some(CopyLink::action_name().as_str()),
+        Some(&format!("sanic://{}/{}", uri_type, id).to_variant())
+    );
+    item_section.append_item(&copy_item);
+    item_section.append_item(&id_item("View info", info_action));
+
+    let menu = gio::Menu::new();
+    menu.append_section(None, &play_section);
+    menu.append_section(None, &go_section);
+    menu.append_section(None, &item_section);
     gtk::PopoverMenu::from_model_full(&menu, gtk::PopoverMenuFlags::NESTED)
 }
 
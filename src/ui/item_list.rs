@@ -1,5 +1,7 @@
-use std::fmt::{Debug};
+use std::cell::{Cell, RefCell};
+use std::error::Error;
 use std::marker::PhantomData;
+use std::pin::Pin;
 use std::rc::Rc;
 use mpris_server::LocalServer;
 use relm4::adw::gio::ListStore;
@@ -13,23 +15,58 @@ use relm4::gtk::pango::EllipsizeMode;
 use crate::dbus::player::MprisPlayer;
 use crate::icon_names;
 use crate::opensonic::cache::CoverCache;
-use crate::ui::album_object::AlbumObject;
+use crate::ui::batch_actions;
+use crate::ui::drag_and_drop;
 use crate::ui::cover_picture::{CoverPicture, CoverSize, CoverType};
 use crate::ui::info_dialog;
-use crate::ui::song_object::SongObject;
 
-#[derive(Debug)]
 pub struct ItemListWidget<I, F, T>
 where
     T: IsA<Object> + ObjectType,
     I: IntoIterator<Item = T>,
     F: Future<Output = I>
 {
-    phantom_t: PhantomData<T>,
+    list_store: ListStore,
+    page_loader: Option<PageLoader<T>>,
+    offset: u32,
+    page_state: Rc<Cell<PageState>>,
+    mpris_player: Rc<LocalServer<MprisPlayer>>,
     phantom_i: PhantomData<I>,
     phantom_f: PhantomData<F>,
 }
 
+/// Loads further pages of items when the list is scrolled to the bottom
+pub struct PageLoader<T> {
+    pub page_size: u32,
+    /// Called with the offset (number of items already loaded)
+    pub load: Box<dyn Fn(u32) -> Pin<Box<dyn Future<Output = Result<Vec<T>, Box<dyn Error>>>>>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageState {
+    Idle,
+    Loading,
+    End,
+    /// The last page couldn't be loaded, loading it again is allowed
+    Failed,
+}
+
+impl PageState {
+    pub fn can_load(self) -> bool {
+        matches!(self, PageState::Idle | PageState::Failed)
+    }
+}
+
+#[derive(Debug)]
+pub enum ItemListMsg {
+    LoadNextPage,
+}
+
+#[derive(Debug)]
+pub enum ItemListOut {
+    PageState(PageState),
+}
+
 pub struct ItemListInit<I, F, T>
 where
     T: IsA<Object> + ObjectType,
@@ -43,21 +80,17 @@ where
     pub mpris_player: Rc<LocalServer<MprisPlayer>>,
     pub cover_type: CoverType,
     pub highlight: Option<u32>,
-}
-
-#[derive(Copy, Clone)]
-pub enum ItemType {
-    Song,
-    Album,
-    Artist
+    pub load_page: Option<PageLoader<T>>,
+    /// Hides items without removing them, so filtering survives loading further pages
+    pub filter: Option<gtk::Filter>,
 }
 
 #[relm4::component(pub async)]
 impl<T: IsA<Object> + ObjectType, I: IntoIterator<Item = T> + 'static, F: 'static + Future<Output = I>> AsyncComponent for ItemListWidget<I, F, T> {
 
     type CommandOutput = ();
-    type Input = ();
-    type Output = ();
+    type Input = ItemListMsg;
+    type Output = ItemListOut;
     type Init = ItemListInit<I, F, T>;
 
     view! {
@@ -67,13 +100,14 @@ impl<T: IsA<Object> + ObjectType, I: IntoIterator<Item = T> + 'static, F: 'stati
             add_css_class: "no-bg",
             set_vexpand: true,
             set_vexpand_set: true,
+            set_enable_rubberband: true,
         }
     }
 
     async fn init(
         init: Self::Init,
         root: Self::Root,
-        _sender: AsyncComponentSender<Self>,
+        sender: AsyncComponentSender<Self>,
     ) -> AsyncComponentParts<Self> {
         let factory = SignalListItemFactory::new();
         let Self::Init{
@@ -84,6 +118,8 @@ impl<T: IsA<Object> + ObjectType, I: IntoIterator<Item = T> + 'static, F: 'stati
             mpris_player,
             cover_type,
             highlight,
+            load_page,
+            filter,
         } = init;
         let play_fn = play_fn.and_then(|f| Some(Rc::new(f)));
         let click_fn = click_fn.and_then(|f| Some(Rc::new(f)));
@@ -92,17 +128,6 @@ impl<T: IsA<Object> + ObjectType, I: IntoIterator<Item = T> + 'static, F: 'stati
 
         let mut iter = load_items.await.into_iter().peekable();
         let first = iter.peek();
-        let item_type = if let Some(first) = first {
-            if first.is::<SongObject>() {
-                ItemType::Song
-            } else if first.is::<AlbumObject>() {
-                ItemType::Album
-            } else {
-                ItemType::Artist
-            }
-        } else {
-            ItemType::Artist
-        };
         let has_duration = first.and_then(|f| Some(f.has_property_with_type("duration", String::static_type()))).unwrap_or(false);
         let has_filetype = first.and_then(|f| Some(f.has_property_with_type("filetype", String::static_type()))).unwrap_or(false);
 
@@ -115,6 +140,8 @@ impl<T: IsA<Object> + ObjectType, I: IntoIterator<Item = T> + 'static, F: 'stati
             play_fn,
             #[strong]
             mpris_player,
+            #[weak(rename_to = list)]
+            root,
             move |_, list_item| {
                 let hbox = gtk::CenterBox::builder()
                     .orientation(Orientation::Horizontal)
@@ -151,16 +178,36 @@ impl<T: IsA<Object> + ObjectType, I: IntoIterator<Item = T> + 'static, F: 'stati
                     list_item,
                     #[weak]
                     hbox,
+                    #[weak]
+                    list,
+                    #[strong]
+                    mpris_player,
                     move |_controller, _btn, x, y| {
-                        let item = list_item.item().expect("Expected ListItem to have item");
-                        let id: String = item.property("id");
-                        let menu = info_dialog::make_popup_menu(item_type, id);
+                        let selected = list.model()
+                            .and_downcast::<gtk::MultiSelection>()
+                            .filter(|s| s.is_selected(list_item.position()))
+                            .map(|s| s.selection().size())
+                            .unwrap_or(0);
+                        let menu = if selected > 1 {
+                            batch_actions::make_popup_menu(selected as u32, &mpris_player, false)
+                        } else {
+                            let item = list_item.item().expect("Expected ListItem to have item");
+                            info_dialog::make_popup_menu(&item, false)
+                        };
                         menu.set_parent(&hbox);
                         menu.set_pointing_to(Some(&gdk::Rectangle::new(x as i32, y as i32, 1, 1)));
                         menu.popup();
                     }
                 ));
                 hbox.add_controller(ctrl);
+                drag_and_drop::add_drag_source(&hbox, clone!(
+                    #[weak]
+                    list_item,
+                    #[weak]
+                    list,
+                    #[upgrade_or_default]
+                    move || drag_and_drop::row_items(&list_item, list.model())
+                ));
 
                 if let Some(play_fn) = &play_fn {
                     let play_btn = gtk::Button::builder()
@@ -195,7 +242,11 @@ impl<T: IsA<Object> + ObjectType, I: IntoIterator<Item = T> + 'static, F: 'stati
                         click_fn,
                         #[strong]
                         mpris_player,
-                        move |_this, _n: i32, _x: f64, _y: f64| {
+                        move |this, _n: i32, _x: f64, _y: f64| {
+                            // Modifier clicks only change the selection
+                            if this.current_event_state().intersects(gdk::ModifierType::CONTROL_MASK | gdk::ModifierType::SHIFT_MASK) {
+                                return;
+                            }
                             let item = list_item.item().expect("Expected ListItem to have item");
                             click_fn(item.downcast::<T>().expect("Unexpected type"), list_item.position(), mpris_player.clone());
                         }
@@ -256,9 +307,120 @@ impl<T: IsA<Object> + ObjectType, I: IntoIterator<Item = T> + 'static, F: 'stati
         ));
 
         let list_store = ListStore::from_iter(iter);
-        root.set_model(Some(&gtk::NoSelection::new(Some(list_store))));
+        match filter {
+            Some(filter) => root.set_model(Some(&gtk::MultiSelection::new(Some(gtk::FilterListModel::new(Some(list_store.clone()), Some(filter)))))),
+            None => root.set_model(Some(&gtk::MultiSelection::new(Some(list_store.clone())))),
+        }
+        batch_actions::install(&root, &mpris_player, false);
 
-        let model = Self { phantom_t: Default::default(), phantom_i: Default::default(), phantom_f: Default::default() };
+        let page_state = Rc::new(Cell::new(PageState::End));
+        if let Some(loader) = &load_page {
+            if list_store.n_items() >= loader.page_size {
+                page_state.set(PageState::Idle);
+            }
+            // The list has no scrolling of its own, so listen for the bottom of whatever it's placed in
+            let handlers: RefCell<Option<(gtk::ScrolledWindow, glib::SignalHandlerId, gtk::Adjustment, glib::SignalHandlerId)>> = RefCell::new(None);
+            root.connect_parent_notify(clone!(
+                #[strong]
+                sender,
+                #[strong]
+                page_state,
+                move |list| {
+                    if let Some((scrolled, edge_handler, adjustment, size_handler)) = handlers.take() {
+                        scrolled.disconnect(edge_handler);
+                        adjustment.disconnect(size_handler);
+                    }
+                    if let Some(scrolled) = list.parent().and_downcast::<gtk::ScrolledWindow>() {
+                        let edge_handler = scrolled.connect_edge_reached(clone!(
+                            #[strong]
+                            sender,
+                            #[strong]
+                            page_state,
+                            move |_, pos| {
+                                if pos == gtk::PositionType::Bottom && page_state.get().can_load() {
+                                    sender.input(ItemListMsg::LoadNextPage);
+                                }
+                            }
+                        ));
+                        // Without a scrollbar there's no bottom to reach, which happens when a filter hides most items
+                        let adjustment = scrolled.vadjustment();
+                        let size_handler = adjustment.connect_changed(clone!(
+                            #[strong]
+                            sender,
+                            #[strong]
+                            page_state,
+                            #[weak]
+                            scrolled,
+                            move |_| {
+                                if page_state.get() == PageState::Idle && needs_more(&scrolled) {
+                                    sender.input(ItemListMsg::LoadNextPage);
+                                }
+                            }
+                        ));
+                        handlers.replace(Some((scrolled, edge_handler, adjustment, size_handler)));
+                    }
+                }
+            ));
+            sender.output(ItemListOut::PageState(page_state.get())).expect("Error sending message out");
+        }
+
+        let model = Self {
+            offset: list_store.n_items(),
+            list_store,
+            page_loader: load_page,
+            page_state,
+            mpris_player,
+            phantom_i: Default::default(),
+            phantom_f: Default::default()
+        };
         AsyncComponentParts { model, widgets }
     }
+
+    async fn update(
+        &mut self,
+        message: Self::Input,
+        sender: AsyncComponentSender<Self>,
+        root: &Self::Root,
+    ) {
+        match message {
+            ItemListMsg::LoadNextPage => {
+                let Some(loader) = &self.page_loader else {
+                    return;
+                };
+                if !self.page_state.get().can_load() {
+                    return;
+                }
+                self.set_page_state(PageState::Loading, &sender);
+                match (loader.load)(self.offset).await {
+                    Ok(items) => {
+                        let state = if (items.len() as u32) < loader.page_size { PageState::End } else { PageState::Idle };
+                        self.offset += items.len() as u32;
+                        self.list_store.extend_from_slice(&items);
+                        self.set_page_state(state, &sender);
+                        // If the filter hid the whole page the view doesn't change size, so check here as well
+                        if state == PageState::Idle && root.parent().and_downcast::<gtk::ScrolledWindow>().is_some_and(|s| needs_more(&s)) {
+                            sender.input(ItemListMsg::LoadNextPage);
+                        }
+                    }
+                    Err(err) => {
+                        self.set_page_state(PageState::Failed, &sender);
+                        self.mpris_player.imp().send_error(err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<T: IsA<Object> + ObjectType, I: IntoIterator<Item = T> + 'static, F: 'static + Future<Output = I>> ItemListWidget<I, F, T> {
+    fn set_page_state(&self, state: PageState, sender: &AsyncComponentSender<Self>) {
+        self.page_state.set(state);
+        sender.output(ItemListOut::PageState(state)).expect("Error sending message out");
+    }
+}
+
+/// Whether the list is shown but doesn't fill its scrolled window
+fn needs_more(scrolled: &gtk::ScrolledWindow) -> bool {
+    let adjustment = scrolled.vadjustment();
+    scrolled.is_mapped() && adjustment.page_size() > 0.0 && adjustment.upper() <= adjustment.page_size()
 }
@@ -13,4 +13,8 @@ pub mod bottom_bar;
 mod header_bar;
 pub mod artist_object;
 mod item_list;
+mod filter_bar;
+mod shelves;
 mod info_dialog;
+mod batch_actions;
+mod drag_and_drop;
@@ -1,26 +1,42 @@
 use std::collections::HashMap;
 
-use libsecret::{password_store_future, Schema};
+use libsecret::{password_clear_future, password_store_future, Schema};
 use relm4::adw::prelude::PreferencesPageExt;
 use relm4::adw;
 use relm4::adw::prelude::*;
 use relm4::gtk::gio::Settings;
-use relm4::gtk::glib::Variant;
-use relm4::gtk::{Editable};
+use relm4::gtk::glib::{StaticVariantType, Variant};
+use relm4::gtk::glib::clone;
+use relm4::gtk::{Align, Editable};
 use relm4::adw::gtk;
 use relm4::prelude::*;
+use tracing::error;
 
-use crate::icon_names;
+use crate::{icon_names, listenbrainz, logging};
+use crate::opensonic::http::{self, HttpOptions};
+use crate::opensonic::tls::TlsOptions;
+use crate::ui::shelves::{Shelf, ShelfKind};
 
 pub struct PreferencesWidget {
     settings: Settings,
     schema: Schema,
     requires_restart: bool,
+    shelves: Vec<Shelf>,
+    shelf_rows: Vec<adw::ActionRow>,
 }
 
 #[derive(Debug)]
 pub enum PreferencesMsg {
     AuthChanged{pass: bool},
+    LyricsDirChanged,
+    TlsChanged,
+    ProxyChanged{pass: bool},
+    ListenBrainzChanged{token: bool},
+    ShelfVisibilityChanged(usize, bool),
+    MoveShelf(usize, isize),
+    RemoveShelf(usize),
+    AddGenreShelf,
+    AddDecadeShelf,
     Closed,
 }
 
@@ -54,6 +70,13 @@ impl AsyncComponent for PreferencesWidget {
                         set_model = &gtk::StringList::new(&["None", "Track", "Album"]),
                         set_title: "Replay gain mode"
                     },
+                    #[name = "shuffle_mode"]
+                    adw::ComboRow {
+                        #[wrap(Some)]
+                        set_model = &gtk::StringList::new(&["Random", "Spread out artists", "Albums", "Weighted"]),
+                        set_title: "Shuffle mode",
+                        set_subtitle: "Weighted favours starred, highly rated and not recently played songs"
+                    },
                     #[name = "open_in_bg"]
                     adw::SwitchRow {
                         set_title: "Remain open in background"
@@ -63,11 +86,104 @@ impl AsyncComponent for PreferencesWidget {
                         set_title: "Should scrobble",
                         set_subtitle: "Should the client report playback of the current song to the server"
                     },
+                    #[name = "scrobble_percent"]
+                    adw::SpinRow::with_range(1.0, 100.0, 5.0) {
+                        set_title: "Scrobble after percentage",
+                        set_subtitle: "How much of a song has to be played before it counts as played"
+                    },
+                    #[name = "scrobble_after"]
+                    adw::SpinRow::with_range(30.0, 1200.0, 30.0) {
+                        set_title: "Scrobble after seconds",
+                        set_subtitle: "Longer songs count as played after this long"
+                    },
+                    #[name = "crossfade"]
+                    adw::SpinRow::with_range(0.0, 12.0, 1.0) {
+                        set_title: "Crossfade seconds",
+                        set_subtitle: "How long songs fade into each other, 0 turns it off. Consecutive tracks of an album aren't crossfaded"
+                    },
                     #[name = "continuous_play"]
                     adw::SwitchRow {
                         set_title: "Continuous play",
                         set_subtitle: "Automatically add similar songs to play queue when the end is reached"
                     },
+                },
+                adw::PreferencesGroup {
+                    set_title: "Lyrics",
+
+                    #[name = "prefer_local_lyrics"]
+                    adw::SwitchRow {
+                        set_title: "Prefer local lyrics",
+                        set_subtitle: "Look for .lrc files in the lyrics directory before asking the server"
+                    },
+                    #[name = "lyrics_dir"]
+                    adw::EntryRow {
+                        set_show_apply_button: true,
+                        set_title: "Lyrics directory",
+                        connect_apply => PreferencesMsg::LyricsDirChanged,
+                    },
+                },
+                adw::PreferencesGroup {
+                    set_title: "ListenBrainz",
+                    set_description: Some("Submit listens straight to ListenBrainz or a compatible service, besides the server"),
+
+                    #[name = "listenbrainz_enabled"]
+                    adw::SwitchRow {
+                        set_title: "Submit listens",
+                    },
+                    #[name = "listenbrainz_url"]
+                    adw::EntryRow {
+                        set_show_apply_button: true,
+                        set_title: "API URL",
+                        connect_apply => PreferencesMsg::ListenBrainzChanged{token: false},
+                    },
+                    #[name = "listenbrainz_token"]
+                    adw::PasswordEntryRow {
+                        set_show_apply_button: true,
+                        set_title: "User token",
+                        connect_apply => PreferencesMsg::ListenBrainzChanged{token: true},
+                    },
+                },
+                adw::PreferencesGroup {
+                    set_title: "Troubleshooting",
+                    set_description: Some("(requires restart)"),
+
+                    #[name = "log_to_file"]
+                    adw::SwitchRow {
+                        set_title: "Write log files",
+                        set_subtitle: &format!("Logs are kept in {}", logging::log_dir().unwrap_or_default()),
+                    },
+                }
+            },
+            add = &adw::PreferencesPage {
+                set_title: "Home",
+                set_icon_name: Some(icon_names::shipped::LIST),
+
+                #[name = "shelves_group"]
+                adw::PreferencesGroup {
+                    set_title: "Shelves",
+                    set_description: Some("Sections shown on the browse page, in order"),
+                },
+                adw::PreferencesGroup {
+                    set_title: "Add shelf",
+
+                    #[name = "genre_shelf"]
+                    adw::EntryRow {
+                        set_show_apply_button: true,
+                        set_title: "Genre",
+                        connect_apply => PreferencesMsg::AddGenreShelf,
+                    },
+                    #[name = "decade_shelf"]
+                    adw::SpinRow::with_range(1900.0, 2090.0, 10.0) {
+                        set_title: "Decade",
+                        set_value: 1990.0,
+                        add_suffix = &gtk::Button {
+                            set_icon_name: icon_names::shipped::ADD_REGULAR,
+                            set_tooltip: "Add decade shelf",
+                            set_valign: Align::Center,
+                            add_css_class: "flat",
+                            connect_clicked => PreferencesMsg::AddDecadeShelf,
+                        }
+                    },
                 }
             },
             add = &adw::PreferencesPage {
@@ -97,6 +213,58 @@ impl AsyncComponent for PreferencesWidget {
                         connect_apply => PreferencesMsg::AuthChanged{pass: true},
                     }
                 },
+                adw::PreferencesGroup {
+                    set_title: "Reverse proxy",
+                    set_description: Some("(requires restart)"),
+
+                    #[name = "extra_headers"]
+                    adw::EntryRow {
+                        set_show_apply_button: true,
+                        set_title: "Extra headers (Name: value; Other-Name: value)",
+                        connect_apply => PreferencesMsg::ProxyChanged{pass: false},
+                    },
+                    #[name = "basic_auth_username"]
+                    adw::EntryRow {
+                        set_show_apply_button: true,
+                        set_title: "Basic auth username",
+                        connect_apply => PreferencesMsg::ProxyChanged{pass: false},
+                    },
+                    #[name = "basic_auth_password"]
+                    adw::PasswordEntryRow {
+                        set_show_apply_button: true,
+                        set_title: "Basic auth password",
+                        connect_apply => PreferencesMsg::ProxyChanged{pass: true},
+                    }
+                },
+                adw::PreferencesGroup {
+                    set_title: "TLS",
+                    set_description: Some("(requires restart)"),
+
+                    #[name = "tls_ca_file"]
+                    adw::EntryRow {
+                        set_show_apply_button: true,
+                        set_title: "CA certificate file",
+                        connect_apply => PreferencesMsg::TlsChanged,
+                    },
+                    #[name = "tls_fingerprint"]
+                    adw::EntryRow {
+                        set_show_apply_button: true,
+                        set_title: "Pinned certificate fingerprint (SHA-256)",
+                        connect_apply => PreferencesMsg::TlsChanged,
+                    },
+                    #[name = "tls_client_cert"]
+                    adw::EntryRow {
+                        set_show_apply_button: true,
+                        set_title: "Client certificate file",
+                        connect_apply => PreferencesMsg::TlsChanged,
+                    },
+                    #[name = "tls_client_key"]
+                    adw::EntryRow {
+                        set_show_apply_button: true,
+                        set_title: "Client key file",
+                        connect_apply => PreferencesMsg::TlsChanged,
+                    },
+                },
                 adw::PreferencesGroup {
                     #[name = "cache_albums"]
                     adw::SwitchRow {
@@ -112,22 +280,42 @@ impl AsyncComponent for PreferencesWidget {
         root: adw::PreferencesDialog,
         sender: AsyncComponentSender<Self>,
     ) -> AsyncComponentParts<Self> {
-        let model = Self {
+        let mut model = Self {
+            shelves: Shelf::load_all(&init.0),
             settings: init.0,
             requires_restart: false,
-            schema: init.1
+            schema: init.1,
+            shelf_rows: Vec::new(),
         };
 
         let widgets: PreferencesWidgetWidgets = view_output!();
 
         set_text_from_setting(&widgets.server_url, "server-url", &model.settings);
         set_text_from_setting(&widgets.username, "username", &model.settings);
+        set_text_from_setting(&widgets.lyrics_dir, "lyrics-directory", &model.settings);
+        set_text_from_setting(&widgets.basic_auth_username, "basic-auth-username", &model.settings);
+        widgets.extra_headers.set_text(http::format_headers(&HttpOptions::load(&model.settings, None).extra_headers).as_str());
+        set_text_from_setting(&widgets.tls_ca_file, "tls-ca-file", &model.settings);
+        set_text_from_setting(&widgets.tls_fingerprint, "tls-pinned-fingerprint", &model.settings);
+        set_text_from_setting(&widgets.tls_client_cert, "tls-client-cert", &model.settings);
+        set_text_from_setting(&widgets.tls_client_key, "tls-client-key", &model.settings);
 
         model.settings.bind("should-cache-covers", &widgets.cache_albums, "active").build();
         widgets.replay_gain.set_selected(model.settings.value("replay-gain-mode").get::<u8>().unwrap() as u32);
+        widgets.shuffle_mode.set_selected(model.settings.value("shuffle-mode").get::<u8>().unwrap() as u32);
         model.settings.bind("stay-in-background", &widgets.open_in_bg, "active").build();
         model.settings.bind("should-scrobble", &widgets.scrobble, "active").build();
+        model.settings.bind("listenbrainz-enabled", &widgets.listenbrainz_enabled, "active").build();
+        widgets.listenbrainz_url.set_text(model.settings.string("listenbrainz-url").as_str());
+        model.settings.bind("scrobble-percent", &widgets.scrobble_percent, "value").build();
+        model.settings.bind("scrobble-after-seconds", &widgets.scrobble_after, "value").build();
+        widgets.scrobble.bind_property("active", &widgets.scrobble_percent, "sensitive").sync_create().build();
+        widgets.scrobble.bind_property("active", &widgets.scrobble_after, "sensitive").sync_create().build();
+        model.settings.bind("crossfade-seconds", &widgets.crossfade, "value").build();
         model.settings.bind("continuous-play", &widgets.continuous_play, "active").build();
+        model.settings.bind("prefer-local-lyrics", &widgets.prefer_local_lyrics, "active").build();
+        model.settings.bind("log-to-file", &widgets.log_to_file, "active").build();
+        model.fill_shelf_rows(&widgets.shelves_group, &sender);
 
         AsyncComponentParts { model, widgets }
     }
@@ -137,7 +325,7 @@ impl AsyncComponent for PreferencesWidget {
         widgets: &mut Self::Widgets,
         message: Self::Input,
         sender: AsyncComponentSender<Self>,
-        _root: &adw::PreferencesDialog,
+        root: &adw::PreferencesDialog,
     ) {
         match message {
             PreferencesMsg::AuthChanged{pass} => {
@@ -166,12 +354,124 @@ impl AsyncComponent for PreferencesWidget {
                        "OpenSubsoncic password",
                        password.as_str())
                     .await
-                    .expect("Error storing password in secret store");
+                    .unwrap_or_else(|err| report_secret_error(root, err));
+                }
+                self.requires_restart = true;
+            },
+            PreferencesMsg::ListenBrainzChanged{token} => {
+                let url = listenbrainz::normalize_url(&widgets.listenbrainz_url.text());
+                self.settings.set_string("listenbrainz-url", &url).expect("Error setting ListenBrainz URL");
+                widgets.listenbrainz_url.set_text(&url);
+
+                if token {
+                    let token = widgets.listenbrainz_token.text();
+
+                    password_store_future(
+                        Some(&listenbrainz::token_schema()),
+                        HashMap::from([("url", url.as_str())]),
+                        Some(&libsecret::COLLECTION_DEFAULT),
+                        "ListenBrainz user token",
+                        token.trim())
+                    .await
+                    .unwrap_or_else(|err| report_secret_error(root, err));
                 }
+            },
+            PreferencesMsg::LyricsDirChanged => {
+                let dir = widgets.lyrics_dir.text();
+                let value = if dir.is_empty() {
+                    Variant::from_none(&String::static_variant_type())
+                } else {
+                    Variant::from_some(&Variant::from(dir.as_str()))
+                };
+                self.settings
+                    .set_value("lyrics-directory", &value)
+                    .expect("Error setting lyrics directory setting");
+            },
+            PreferencesMsg::TlsChanged => {
+                let options = TlsOptions {
+                    ca_file: non_empty_text(&widgets.tls_ca_file),
+                    pinned_fingerprint: non_empty_text(&widgets.tls_fingerprint),
+                    client_cert: non_empty_text(&widgets.tls_client_cert),
+                    client_key: non_empty_text(&widgets.tls_client_key),
+                };
+                options.save(&self.settings).expect("Error saving TLS settings");
                 self.requires_restart = true;
             },
+            PreferencesMsg::ProxyChanged{pass} => {
+                // Nothing is saved until the headers are valid
+                match http::parse_headers(widgets.extra_headers.text().as_str()) {
+                    Err(_) => widgets.extra_headers.add_css_class("error"),
+                    Ok(extra_headers) => {
+                        widgets.extra_headers.remove_css_class("error");
+
+                        let server = self.settings.value("server-url")
+                            .as_maybe()
+                            .and_then(|v| v.get::<String>())
+                            .unwrap_or_default();
+                        let old_username = HttpOptions::load(&self.settings, None).basic_auth.map(|(u, _)| u);
+                        let username = non_empty_text(&widgets.basic_auth_username);
+                        let options = HttpOptions {
+                            extra_headers,
+                            basic_auth: username.clone().map(|u| (u, String::new())),
+                        };
+                        options.save(&self.settings).expect("Error saving HTTP settings");
+
+                        // The password is stored per username, so the old one isn't needed anymore
+                        if let Some(old_username) = old_username && username.as_ref() != Some(&old_username) {
+                            let _ = password_clear_future(Some(&http::basic_auth_schema()), http::basic_auth_attributes(&server, &old_username)).await;
+                        }
+                        if let Some(username) = username && pass {
+                            let password = widgets.basic_auth_password.text();
+
+                            password_store_future(
+                                Some(&http::basic_auth_schema()),
+                                http::basic_auth_attributes(&server, &username),
+                                Some(&libsecret::COLLECTION_DEFAULT),
+                                "OpenSubsonic reverse proxy password",
+                                password.as_str())
+                            .await
+                            .unwrap_or_else(|err| report_secret_error(root, err));
+                        }
+                        self.requires_restart = true;
+                    },
+                }
+            },
+            PreferencesMsg::ShelfVisibilityChanged(index, visible) => {
+                if let Some(shelf) = self.shelves.get_mut(index) {
+                    shelf.visible = visible;
+                    self.save_shelves();
+                }
+            },
+            PreferencesMsg::MoveShelf(index, by) => {
+                let target = index.saturating_add_signed(by);
+                if index < self.shelves.len() && target < self.shelves.len() {
+                    self.shelves.swap(index, target);
+                    self.save_shelves();
+                    self.fill_shelf_rows(&widgets.shelves_group, &sender);
+                }
+            },
+            PreferencesMsg::RemoveShelf(index) => {
+                if index < self.shelves.len() {
+                    self.shelves.remove(index);
+                    self.save_shelves();
+                    self.fill_shelf_rows(&widgets.shelves_group, &sender);
+                }
+            },
+            PreferencesMsg::AddGenreShelf => {
+                if let Some(genre) = non_empty_text(&widgets.genre_shelf) {
+                    self.add_shelf(ShelfKind::Genre(genre.trim().to_string()));
+                    widgets.genre_shelf.set_text("");
+                    self.fill_shelf_rows(&widgets.shelves_group, &sender);
+                }
+            },
+            PreferencesMsg::AddDecadeShelf => {
+                let decade = widgets.decade_shelf.value() as u32 / 10 * 10;
+                self.add_shelf(ShelfKind::Decade(decade));
+                self.fill_shelf_rows(&widgets.shelves_group, &sender);
+            },
             PreferencesMsg::Closed => {
                 self.settings.set("replay-gain-mode", Variant::from(widgets.replay_gain.selected() as u8)).expect("Error setting replay gain");
+                self.settings.set("shuffle-mode", Variant::from(widgets.shuffle_mode.selected() as u8)).expect("Error setting shuffle mode");
 
                 sender.output(PreferencesOut::ReloadPlayer).expect("Error sending message out");
                 if self.requires_restart {
@@ -183,6 +483,88 @@ impl AsyncComponent for PreferencesWidget {
     }
 }
 
+impl PreferencesWidget {
+    fn add_shelf(&mut self, kind: ShelfKind) {
+        if !self.shelves.iter().any(|s| s.kind == kind) {
+            self.shelves.push(Shelf { kind, visible: true });
+            self.save_shelves();
+        }
+    }
+
+    fn save_shelves(&self) {
+        Shelf::save_all(&self.shelves, &self.settings).expect("Error saving home shelves");
+    }
+
+    fn fill_shelf_rows(&mut self, group: &adw::PreferencesGroup, sender: &AsyncComponentSender<Self>) {
+        for row in self.shelf_rows.drain(..) {
+            group.remove(&row);
+        }
+        let count = self.shelves.len();
+        for (index, shelf) in self.shelves.iter().enumerate() {
+            let row = adw::ActionRow::builder()
+                .title(shelf.kind.title())
+                .build();
+            match shelf.kind {
+                ShelfKind::Genre(_) => row.set_subtitle("Genre"),
+                ShelfKind::Decade(_) => row.set_subtitle("Decade"),
+                _ => {},
+            }
+
+            let up_btn = gtk::Button::from_icon_name(icon_names::shipped::UP_SMALL);
+            up_btn.add_css_class("flat");
+            up_btn.set_tooltip("Move up");
+            up_btn.set_valign(Align::Center);
+            up_btn.set_sensitive(index > 0);
+            up_btn.connect_clicked(clone!(
+                #[strong]
+                sender,
+                move |_| sender.input(PreferencesMsg::MoveShelf(index, -1))
+            ));
+            let down_btn = gtk::Button::from_icon_name(icon_names::shipped::DOWN_SMALL);
+            down_btn.add_css_class("flat");
+            down_btn.set_tooltip("Move down");
+            down_btn.set_valign(Align::Center);
+            down_btn.set_sensitive(index + 1 < count);
+            down_btn.connect_clicked(clone!(
+                #[strong]
+                sender,
+                move |_| sender.input(PreferencesMsg::MoveShelf(index, 1))
+            ));
+            row.add_suffix(&up_btn);
+            row.add_suffix(&down_btn);
+
+            if shelf.kind.is_custom() {
+                let del_btn = gtk::Button::from_icon_name(icon_names::shipped::CROSS_SMALL_CIRCLE_OUTLINE);
+                del_btn.add_css_class("flat");
+                del_btn.set_tooltip("Remove shelf");
+                del_btn.set_valign(Align::Center);
+                del_btn.connect_clicked(clone!(
+                    #[strong]
+                    sender,
+                    move |_| sender.input(PreferencesMsg::RemoveShelf(index))
+                ));
+                row.add_suffix(&del_btn);
+            }
+
+            let switch = gtk::Switch::builder()
+                .active(shelf.visible)
+                .valign(Align::Center)
+                .tooltip_text("Show on browse page")
+                .build();
+            switch.connect_active_notify(clone!(
+                #[strong]
+                sender,
+                move |switch| sender.input(PreferencesMsg::ShelfVisibilityChanged(index, switch.is_active()))
+            ));
+            row.add_suffix(&switch);
+            row.set_activatable_widget(Some(&switch));
+
+            group.add(&row);
+            self.shelf_rows.push(row);
+        }
+    }
+}
+
 fn set_text_from_setting<T: IsA<Editable>>(widget: &T, setting: &str, settings: &Settings) {
     match settings.value(setting).as_maybe() {
         Some(val) => {
@@ -192,3 +574,18 @@ fn set_text_from_setting<T: IsA<Editable>>(widget: &T, setting: &str, settings:
         None => {},
     }
 }
+
+fn non_empty_text<T: IsA<Editable>>(widget: &T) -> Option<String> {
+    let text = widget.text();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.to_string())
+    }
+}
+
+/// The secret service can be missing or locked, which shouldn't take the app down
+fn report_secret_error(dialog: &adw::PreferencesDialog, err: gtk::glib::Error) {
+    error!(%err, "Couldn't store secret");
+    dialog.add_toast(adw::Toast::new("Couldn't save to the keyring"));
+}
@@ -1,9 +1,10 @@
 use std::collections::HashMap;
+use std::error::Error;
 
 use async_channel::Sender;
-use libsecret::password_store_future;
+use libsecret::{password_clear_future, password_lookup_future, password_store_future};
 use libsecret::Schema;
-use relm4::gtk::gio::Settings;
+use relm4::gtk::gio::{self, Settings};
 use relm4::gtk::glib::Variant;
 use relm4::gtk::{Align, IconSize, Orientation};
 use relm4::prelude::*;
@@ -16,18 +17,22 @@ use relm4::adw::glib as glib;
 use crate::APP_ID;
 use crate::opensonic::client;
 use crate::opensonic::client::{Credentials, OpenSubsonicClient};
+use crate::opensonic::http::{self, HttpOptions};
+use crate::opensonic::tls::{self, TlsOptions};
 
 
 pub struct SetupWidget {
     sender: Sender<OpenSubsonicClient>,
     settings: Settings,
     schema: Schema,
+    pinned_fingerprint: Option<String>,
 }
 
 #[derive(Debug)]
 pub enum SetupMsg {
     Test,
     Save,
+    TrustCertificate{fingerprint: String, save: bool},
 }
 
 pub type SetupOut = OpenSubsonicClient;
@@ -120,6 +125,61 @@ impl AsyncComponent for SetupWidget {
                             },
                         },
                     },
+                    gtk::Expander {
+                        set_label: Some("Advanced connection options"),
+
+                        #[wrap(Some)]
+                        set_child = &gtk::Box {
+                            set_orientation: Orientation::Vertical,
+                            set_spacing: 10,
+                            set_margin_top: 10,
+
+                            gtk::Label {
+                                set_label: "CA certificate file",
+                                add_css_class: "bold"
+                            },
+                            #[name = "ca_file"]
+                            gtk::Entry {
+                                set_placeholder_text: Some("/path/to/ca.pem")
+                            },
+                            gtk::Label {
+                                set_label: "Client certificate",
+                                add_css_class: "bold"
+                            },
+                            #[name = "client_cert"]
+                            gtk::Entry {
+                                set_placeholder_text: Some("/path/to/client.pem")
+                            },
+                            gtk::Label {
+                                set_label: "Client key",
+                                add_css_class: "bold"
+                            },
+                            #[name = "client_key"]
+                            gtk::Entry {
+                                set_placeholder_text: Some("/path/to/client.key (if not in certificate file)")
+                            },
+                            gtk::Label {
+                                set_label: "Extra HTTP headers",
+                                add_css_class: "bold"
+                            },
+                            #[name = "extra_headers"]
+                            gtk::Entry {
+                                set_placeholder_text: Some("Header-Name: value; Other-Header: value")
+                            },
+                            gtk::Label {
+                                set_label: "HTTP basic authentication",
+                                add_css_class: "bold"
+                            },
+                            #[name = "basic_auth_username"]
+                            gtk::Entry {
+                                set_placeholder_text: Some("Username")
+                            },
+                            #[name = "basic_auth_password"]
+                            gtk::PasswordEntry {
+                                set_placeholder_text: Some("Password")
+                            },
+                        }
+                    },
                     gtk::Box {
                         set_orientation: Orientation::Horizontal,
                         set_valign: Align::Center,
@@ -150,14 +210,25 @@ impl AsyncComponent for SetupWidget {
         root: adw::ApplicationWindow,
         sender: AsyncComponentSender<Self>,
     ) -> AsyncComponentParts<Self> {
+        let tls = TlsOptions::load(&init.0);
         let model = Self {
             settings: init.0,
             sender: init.1,
             schema: init.2,
+            pinned_fingerprint: tls.pinned_fingerprint,
         };
 
         let widgets: SetupWidgetWidgets = view_output!();
 
+        widgets.ca_file.set_text(tls.ca_file.as_deref().unwrap_or(""));
+        widgets.client_cert.set_text(tls.client_cert.as_deref().unwrap_or(""));
+        widgets.client_key.set_text(tls.client_key.as_deref().unwrap_or(""));
+        let http = HttpOptions::load(&model.settings, None);
+        widgets.extra_headers.set_text(http::format_headers(&http.extra_headers).as_str());
+        if let Some((username, _)) = &http.basic_auth {
+            widgets.basic_auth_username.set_text(username);
+        }
+
         if let Some(host) = model.settings.value("server-url")
             .as_maybe().and_then(|s| s.get::<String>()) {
             widgets.server_url.set_text(&host);
@@ -215,59 +286,175 @@ impl AsyncComponent for SetupWidget {
                 key: widgets.api_key.text().to_string(),
             }
         };
+        let tls = TlsOptions {
+            ca_file: entry_text(&widgets.ca_file),
+            pinned_fingerprint: self.pinned_fingerprint.clone(),
+            client_cert: entry_text(&widgets.client_cert),
+            client_key: entry_text(&widgets.client_key),
+        };
+        let extra_headers = match http::parse_headers(widgets.extra_headers.text().as_str()) {
+            Ok(h) => h,
+            Err(e) => {
+                widgets.status.set_label(format!("Invalid headers: {}", e).as_str());
+                widgets.status.set_css_classes(&["error"]);
+                self.update_view(widgets, sender);
+                return;
+            }
+        };
+        let host = widgets.server_url.text();
+        // The password field starts out empty, leaving it that way keeps the stored password
+        let new_basic_password = Some(widgets.basic_auth_password.text().to_string()).filter(|p| !p.is_empty());
+        let basic_auth = match entry_text(&widgets.basic_auth_username) {
+            Some(username) => {
+                let password = match &new_basic_password {
+                    Some(password) => password.clone(),
+                    None => password_lookup_future(Some(&http::basic_auth_schema()), http::basic_auth_attributes(&host, &username))
+                        .await
+                        .ok()
+                        .flatten()
+                        .map(|p| p.to_string())
+                        .unwrap_or_default(),
+                };
+                Some((username, password))
+            }
+            None => None,
+        };
+        let http = HttpOptions { extra_headers, basic_auth };
         match message {
             SetupMsg::Test => {
-                let client = OpenSubsonicClient::new(widgets.server_url.text().as_str(),
-                    credentials, "Sanic-rs", None);
-                if let Err(e) = client.init().await {
-                    widgets.status.set_label(format!("Error while creating client: {:?}", e).as_str());
-                    widgets.status.set_css_classes(&["error"]);
+                let result = match OpenSubsonicClient::new(host.as_str(), credentials, "Sanic-rs", None, tls.clone(), http) {
+                    Ok(client) => client.init().await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    self.show_error(e, &tls, false, widgets, root, &sender).await;
                 } else {
                     widgets.status.set_label("Success");
                     widgets.status.set_css_classes(&["success"]);
                 }
             },
             SetupMsg::Save => {
-                let host = widgets.server_url.text();
                 let client = OpenSubsonicClient::new(
                     host.as_str(),
                     credentials.clone(),
                     "Sanic-rs",
-                    if self.settings.boolean("should-cache-covers") {client::get_default_cache_dir()} else {None}
+                    if self.settings.boolean("should-cache-covers") {client::get_default_cache_dir()} else {None},
+                    tls.clone(),
+                    http.clone()
                 );
-                if let Err(e) = client.init().await {
-                    widgets.status.set_label(format!("Error while creating client: {:?}", e).as_str());
-                    widgets.status.set_css_classes(&["error"]);
-                } else {
-                    widgets.status.set_label("Success");
-                    widgets.status.set_css_classes(&["success"]);
+                let client = match client {
+                    Ok(client) => client.init().await.and(Ok(client)),
+                    Err(e) => Err(e),
+                };
+                match client {
+                    Err(e) => self.show_error(e, &tls, true, widgets, root, &sender).await,
+                    Ok(client) => {
+                        widgets.status.set_label("Success");
+                        widgets.status.set_css_classes(&["success"]);
 
-                    self.settings.set_value("server-url", &Variant::from_some(&Variant::from(host.as_str()))).expect("Error setting server url setting");
-                    
-                    let password = match credentials {
-                        Credentials::UsernamePassword { username, password } => {
-                            self.settings.set_value("username", &Variant::from_some(&Variant::from(username.as_str()))).expect("Error setting username setting");
-                            password
+                        self.settings.set_value("server-url", &Variant::from_some(&Variant::from(host.as_str()))).expect("Error setting server url setting");
+                        tls.save(&self.settings).expect("Error saving TLS settings");
+                        http.save(&self.settings).expect("Error saving HTTP settings");
+                        match (&http.basic_auth, &new_basic_password) {
+                            (Some((username, _)), Some(basic_password)) => {
+                                password_store_future(
+                                    Some(&http::basic_auth_schema()),
+                                    http::basic_auth_attributes(&host, username),
+                                    Some(&libsecret::COLLECTION_DEFAULT),
+                                    "OpenSubsonic reverse proxy password",
+                                    basic_password.as_str())
+                                    .await
+                                    .expect("Error storing password in secret store");
+                            }
+                            (Some(_), None) => {}
+                            (None, _) => {
+                                // No basic auth anymore, so the stored password isn't needed
+                                let _ = password_clear_future(Some(&http::basic_auth_schema()), HashMap::from([("server", host.as_str())])).await;
+                            }
                         }
-                        Credentials::ApiKey { key } => {
-                            key
-                        }
-                    };
-                    password_store_future(
-                        Some(&self.schema),
-                        HashMap::new(),
-                        Some(&libsecret::COLLECTION_DEFAULT),
-                        "OpenSubsoncic password",
-                        password.as_str())
-                        .await
-                        .expect("Error storing password in secret store");
 
-                    self.sender.send(client).await.expect("Error sending created client");
+                        let password = match credentials {
+                            Credentials::UsernamePassword { username, password } => {
+                                self.settings.set_value("username", &Variant::from_some(&Variant::from(username.as_str()))).expect("Error setting username setting");
+                                password
+                            }
+                            Credentials::ApiKey { key } => {
+                                key
+                            }
+                        };
+                        password_store_future(
+                            Some(&self.schema),
+                            HashMap::new(),
+                            Some(&libsecret::COLLECTION_DEFAULT),
+                            "OpenSubsoncic password",
+                            password.as_str())
+                            .await
+                            .expect("Error storing password in secret store");
 
-                    root.close();
+                        self.sender.send(client).await.expect("Error sending created client");
+
+                        root.close();
+                    }
                 }
             },
+            SetupMsg::TrustCertificate { fingerprint, save } => {
+                self.pinned_fingerprint = Some(fingerprint);
+                sender.input(if save { SetupMsg::Save } else { SetupMsg::Test });
+            },
         }
         self.update_view(widgets, sender);
     }
 }
+
+impl SetupWidget {
+    async fn show_error(
+        &mut self,
+        error: Box<dyn Error>,
+        tls: &TlsOptions,
+        save: bool,
+        widgets: &SetupWidgetWidgets,
+        root: &adw::ApplicationWindow,
+        sender: &AsyncComponentSender<Self>,
+    ) {
+        widgets.status.set_label(format!("Error while creating client: {:?}", error).as_str());
+        widgets.status.set_css_classes(&["error"]);
+
+        // The server's certificate may have changed since it was pinned, so the pin is dropped
+        // and the certificate is offered to be trusted again
+        self.pinned_fingerprint = None;
+        let tls = TlsOptions { pinned_fingerprint: None, ..tls.clone() };
+        let host = widgets.server_url.text();
+        if let Some(fingerprint) = tls::probe_untrusted_certificate(host.as_str(), &tls).await {
+            let dialog = adw::AlertDialog::new(
+                Some("Untrusted certificate"),
+                Some(format!(
+                    "The server presented a certificate that isn't trusted. This is expected for self-signed certificates.\n\n\
+                    SHA-256 fingerprint:\n{}\n\nOnly trust it if the fingerprint matches the certificate of your server.",
+                    fingerprint
+                ).as_str())
+            );
+            dialog.add_responses(&[("cancel", "Cancel"), ("trust", "Trust certificate")]);
+            dialog.set_default_response(Some("cancel"));
+            dialog.set_close_response("cancel");
+            dialog.set_response_appearance("trust", adw::ResponseAppearance::Destructive);
+            dialog.choose(Some(root), None::<&gio::Cancellable>, clone!(
+                #[strong]
+                sender,
+                move |response| {
+                    if response == "trust" {
+                        sender.input(SetupMsg::TrustCertificate { fingerprint, save });
+                    }
+                }
+            ));
+        }
+    }
+}
+
+fn entry_text(entry: &gtk::Entry) -> Option<String> {
+    let text = entry.text();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.to_string())
+    }
+}
@@ -0,0 +1,155 @@
+use std::error::Error;
+
+use relm4::gtk::gio::Settings;
+use relm4::gtk::prelude::*;
+
+use crate::opensonic::cache::AlbumCache;
+use crate::opensonic::types::AlbumListType;
+use crate::ui::album_object::AlbumObject;
+
+pub const SHELF_PAGE_SIZE: u32 = 20;
+const LIST_TYPES: [AlbumListType; 8] = [
+    AlbumListType::Newest,
+    AlbumListType::Frequent,
+    AlbumListType::Random,
+    AlbumListType::Recent,
+    AlbumListType::Highest,
+    AlbumListType::Starred,
+    AlbumListType::AlphabeticalByName,
+    AlbumListType::AlphabeticalByArtist,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShelfKind {
+    /// Large cards of random albums at the top of the page
+    Carousel,
+    List(AlbumListType),
+    Genre(String),
+    /// First year of the decade, e.g. 1990
+    Decade(u32),
+    StarredSongs,
+    StarredAlbums,
+    StarredArtists,
+}
+
+/// Sections that aren't album lists. Before they could be configured they were always shown,
+/// so they're added visible when missing from the setting.
+const SECTIONS: [ShelfKind; 4] = [
+    ShelfKind::Carousel,
+    ShelfKind::StarredSongs,
+    ShelfKind::StarredAlbums,
+    ShelfKind::StarredArtists,
+];
+
+/// Section of the browse page, as stored in the `home-shelves` setting
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shelf {
+    pub kind: ShelfKind,
+    pub visible: bool,
+}
+
+impl ShelfKind {
+    pub fn title(&self) -> String {
+        match self {
+            ShelfKind::Carousel => "Featured albums".to_string(),
+            ShelfKind::List(list_type) => match list_type {
+                AlbumListType::Newest => "Newest",
+                AlbumListType::Frequent => "Most played",
+                AlbumListType::Random => "Explore",
+                AlbumListType::Recent => "Recently played",
+                AlbumListType::Highest => "Highest rated",
+                AlbumListType::Starred => "Starred albums",
+                AlbumListType::AlphabeticalByName => "By name",
+                AlbumListType::AlphabeticalByArtist => "By artist",
+                AlbumListType::ByYear => "By year",
+                AlbumListType::ByGenre => "By genre",
+            }.to_string(),
+            ShelfKind::Genre(genre) => genre.clone(),
+            ShelfKind::Decade(decade) => format!("{}s", decade),
+            ShelfKind::StarredSongs => "Starred songs".to_string(),
+            ShelfKind::StarredAlbums => "Starred albums".to_string(),
+            ShelfKind::StarredArtists => "Starred artists".to_string(),
+        }
+    }
+
+    /// Whether the shelf was added by the user, as opposed to the built in list types
+    pub fn is_custom(&self) -> bool {
+        matches!(self, ShelfKind::Genre(_) | ShelfKind::Decade(_))
+    }
+
+    pub fn is_starred(&self) -> bool {
+        matches!(self, ShelfKind::StarredSongs | ShelfKind::StarredAlbums | ShelfKind::StarredArtists)
+    }
+
+    fn key(&self) -> String {
+        match self {
+            ShelfKind::Carousel => "carousel".to_string(),
+            ShelfKind::List(list_type) => Into::<&str>::into(*list_type).to_string(),
+            ShelfKind::Genre(genre) => format!("genre:{}", genre),
+            ShelfKind::Decade(decade) => format!("decade:{}", decade),
+            ShelfKind::StarredSongs => "starred-songs".to_string(),
+            ShelfKind::StarredAlbums => "starred-albums".to_string(),
+            ShelfKind::StarredArtists => "starred-artists".to_string(),
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        if let Some(genre) = key.strip_prefix("genre:") {
+            Some(ShelfKind::Genre(genre.to_string()))
+        } else if let Some(decade) = key.strip_prefix("decade:") {
+            decade.parse().ok().map(ShelfKind::Decade)
+        } else if let Some(section) = SECTIONS.into_iter().find(|s| s.key() == key) {
+            Some(section)
+        } else {
+            LIST_TYPES.into_iter()
+                .find(|t| Into::<&str>::into(*t) == key)
+                .map(ShelfKind::List)
+        }
+    }
+
+    /// Loads a page of an album shelf, other sections fill themselves
+    pub async fn load(&self, album_cache: &AlbumCache, offset: u32) -> Result<Vec<AlbumObject>, Box<dyn Error>> {
+        let (list_type, from_year, to_year, genre) = match self {
+            ShelfKind::List(list_type) => (*list_type, None, None, None),
+            ShelfKind::Genre(genre) => (AlbumListType::ByGenre, None, None, Some(genre.clone())),
+            ShelfKind::Decade(decade) => (AlbumListType::ByYear, Some(*decade), Some(decade + 9), None),
+            _ => return Err(format!("{} is not an album shelf", self.title()).into()),
+        };
+        album_cache.get_album_list(list_type, Some(SHELF_PAGE_SIZE), Some(offset), from_year, to_year, genre, None).await
+    }
+}
+
+impl Shelf {
+    pub fn load_all(settings: &Settings) -> Vec<Shelf> {
+        let mut shelves: Vec<Shelf> = settings.value("home-shelves")
+            .get::<Vec<(String, bool)>>()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(key, visible)| ShelfKind::from_key(&key).map(|kind| Shelf { kind, visible }))
+            .collect();
+        for section in SECTIONS {
+            if !shelves.iter().any(|s| s.kind == section) {
+                let shelf = Shelf { kind: section.clone(), visible: true };
+                match section {
+                    ShelfKind::Carousel => shelves.insert(0, shelf),
+                    _ => shelves.push(shelf),
+                }
+            }
+        }
+        // List types missing from the setting are still offered, just hidden
+        for list_type in LIST_TYPES {
+            if !shelves.iter().any(|s| s.kind == ShelfKind::List(list_type)) {
+                shelves.push(Shelf { kind: ShelfKind::List(list_type), visible: false });
+            }
+        }
+        shelves
+    }
+
+    pub fn save_all(shelves: &[Shelf], settings: &Settings) -> Result<(), Box<dyn Error>> {
+        let value: Vec<(String, bool)> = shelves.iter()
+            .map(|s| (s.kind.key(), s.visible))
+            .collect();
+        settings.set_value("home-shelves", &value.to_variant())?;
+        Ok(())
+    }
+}
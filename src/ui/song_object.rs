@@ -68,7 +68,7 @@ impl SongObject {
 mod imp {
     use crate::player::SongEntry;
     use crate::ui::song_object::PositionState;
-    use relm4::adw::glib::{ParamSpec, ParamSpecEnum, ParamSpecString, Value};
+    use relm4::adw::glib::{ParamSpec, ParamSpecBoolean, ParamSpecEnum, ParamSpecString, Value};
     use relm4::adw::gtk::glib;
     use relm4::adw::gtk::prelude::*;
     use relm4::adw::gtk::subclass::prelude::*;
@@ -110,6 +110,7 @@ mod imp {
                     ParamSpecString::builder("duration").build(),
                     ParamSpecEnum::builder::<PositionState>("position-state").build(),
                     ParamSpecString::builder("filetype").build(),
+                    ParamSpecBoolean::builder("starred").build(),
                 ]
             });
             PROPERTIES.as_ref()
@@ -151,6 +152,7 @@ mod imp {
                             None
                         }
                     }).to_value(),
+                    "starred" => song.is_starred().to_value(),
                     _ => unimplemented!(),
                 }
             } else {
@@ -163,6 +165,7 @@ mod imp {
                     "cover-art-id" => None::<String>.to_value(),
                     "position-state" => self.position_state.get().to_value(),
                     "filetype" => None::<String>.to_value(),
+                    "starred" => false.to_value(),
                     _ => unimplemented!(),
                 }
             }
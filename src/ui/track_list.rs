@@ -1,6 +1,11 @@
 use crate::dbus::player::MprisPlayer;
+use crate::opensonic::cache::CoverCache;
+use crate::player::TrackList;
 use crate::ui::app::Init;
+use crate::ui::batch_actions;
+use crate::ui::drag_and_drop::{self, Dropped};
 use crate::ui::cover_picture::{CoverPicture, CoverSize};
+use crate::ui::info_dialog;
 use crate::ui::song_object::{PositionState, SongObject};
 use crate::icon_names;
 use mpris_server::LocalServer;
@@ -8,7 +13,7 @@ use relm4::adw::gio::ListStore;
 use relm4::adw::glib::{clone, closure, Object};
 use relm4::adw::gtk::{Align, ListItem, Orientation, SignalListItemFactory, Widget};
 use relm4::adw::prelude::*;
-use relm4::adw::{glib, gtk};
+use relm4::adw::{gdk, glib, gtk};
 use relm4::gtk::pango::EllipsizeMode;
 use relm4::prelude::*;
 use std::rc::Rc;
@@ -18,6 +23,10 @@ pub struct TrackListWidget {
     mpris_player: Rc<LocalServer<MprisPlayer>>,
 
     factory: SignalListItemFactory,
+    up_next_factory: SignalListItemFactory,
+    up_next_store: ListStore,
+    /// 1 while an up next song is playing, as it's shown first in the up next list
+    up_next_offset: u32,
 }
 
 #[derive(Debug)]
@@ -29,11 +38,18 @@ pub enum MoveDirection{
 #[derive(Debug)]
 pub enum TrackListMsg {
     TrackActivated(usize),
-    TrackChanged(Option<usize>),
+    TrackChanged,
     ReloadList,
     MoveItem{index: u32, direction: MoveDirection},
     ClearList,
     RemoveTrack(u32),
+    /// Scrolls to and selects the next occurrence of the song with this id
+    ShowSong(String),
+    /// Something was dropped on the queue, before the entry at `index` or at the end
+    Dropped { dropped: Dropped, index: Option<u32> },
+    UpNextActivated(u32),
+    RemoveUpNext(u32),
+    ClearUpNext,
 }
 
 #[relm4::component(pub async)]
@@ -79,6 +95,49 @@ impl AsyncComponent for TrackListWidget {
             gtk::Separator {
                 set_orientation: Orientation::Horizontal,
             },
+            gtk::Box {
+                set_orientation: Orientation::Vertical,
+                #[watch]
+                set_visible: model.up_next_store.n_items() != 0,
+
+                gtk::CenterBox {
+                    set_orientation: Orientation::Horizontal,
+                    add_css_class: "padded",
+
+                    #[wrap(Some)]
+                    set_start_widget = &gtk::Label {
+                        set_label: "Up next",
+                        add_css_class: "bold",
+                    },
+                    #[wrap(Some)]
+                    set_end_widget = &gtk::Button {
+                        set_label: "Clear",
+                        add_css_class: "flat",
+                        set_tooltip: "Clear up next",
+                        connect_clicked => TrackListMsg::ClearUpNext,
+                    },
+                },
+                gtk::ScrolledWindow {
+                    set_hscrollbar_policy: gtk::PolicyType::Never,
+                    set_propagate_natural_height: true,
+                    set_max_content_height: 240,
+                    add_css_class: "no-bg",
+
+                    gtk::ListView {
+                        set_factory: Some(&model.up_next_factory),
+                        set_model: Some(&gtk::NoSelection::new(Some(model.up_next_store.clone()))),
+                        set_single_click_activate: true,
+                        add_css_class: "no-bg",
+
+                        connect_activate[sender] => move |_, index| {
+                            sender.input(TrackListMsg::UpNextActivated(index));
+                        }
+                    }
+                },
+                gtk::Separator {
+                    set_orientation: Orientation::Horizontal,
+                },
+            },
             gtk::ScrolledWindow {
                 set_hscrollbar_policy: gtk::PolicyType::Never,
                 set_min_content_width: 360,
@@ -90,9 +149,10 @@ impl AsyncComponent for TrackListWidget {
                 #[name = "list"]
                 gtk::ListView {
                     set_factory: Some(&model.factory),
-                    set_single_click_activate: true,
+                    set_enable_rubberband: true,
                     add_css_class: "no-bg",
 
+                    // Double click or Enter, single clicks only select
                     connect_activate[sender] => move |_, index| {
                         sender.input(TrackListMsg::TrackActivated(index as usize));
                     }
@@ -108,10 +168,24 @@ impl AsyncComponent for TrackListWidget {
     ) -> AsyncComponentParts<Self> {
         let model = TrackListWidget {
             factory: SignalListItemFactory::new(),
+            up_next_factory: SignalListItemFactory::new(),
+            up_next_store: ListStore::new::<SongObject>(),
+            up_next_offset: 0,
             mpris_player: init.6,
         };
+        setup_up_next_factory(&model.up_next_factory, &init.0, &sender);
         model.mpris_player.imp().tl_sender.replace(Some(sender.clone()));
         let widgets: Self::Widgets = view_output!();
+        batch_actions::install(&widgets.list, &model.mpris_player, true);
+        // Drops below the last row go to the end of the queue
+        widgets.list.add_controller(drag_and_drop::queue_drop_target(clone!(
+            #[strong]
+            sender,
+            move |dropped, _| {
+                sender.input(TrackListMsg::Dropped { dropped, index: None });
+                true
+            }
+        )));
 
         model.factory.connect_setup(clone!(
             #[strong(rename_to = cover_cache)]
@@ -120,6 +194,8 @@ impl AsyncComponent for TrackListWidget {
             widgets.list,
             #[strong]
             sender,
+            #[strong(rename_to = mpris_player)]
+            model.mpris_player,
             move |_, list_item| {
             let center_box = gtk::CenterBox::builder()
                 .orientation(Orientation::Horizontal)
@@ -201,6 +277,77 @@ impl AsyncComponent for TrackListWidget {
             list_item
                 .set_child(Some(&center_box));
 
+            let menu_click = gtk::GestureClick::builder()
+                .button(3)
+                .build();
+            menu_click.connect_pressed(clone!(
+                #[weak]
+                list_item,
+                #[weak]
+                center_box,
+                #[weak]
+                list,
+                #[strong]
+                mpris_player,
+                move |_, _, x, y| {
+                    let Some(selection) = list.model().and_downcast::<gtk::MultiSelection>() else {
+                        return;
+                    };
+                    let menu = if selection.is_selected(list_item.position()) && selection.selection().size() > 1 {
+                        batch_actions::make_popup_menu(selection.selection().size() as u32, &mpris_player, true)
+                    } else {
+                        let Some(song) = list_item.item().and_downcast::<SongObject>() else {
+                            return;
+                        };
+                        info_dialog::make_popup_menu(&song, true)
+                    };
+                    menu.set_parent(&center_box);
+                    menu.set_pointing_to(Some(&gdk::Rectangle::new(x as i32, y as i32, 1, 1)));
+                    menu.popup();
+                }
+            ));
+            center_box.add_controller(menu_click);
+
+            drag_and_drop::add_queue_drag_source(&center_box, clone!(
+                #[weak]
+                list_item,
+                #[weak]
+                list,
+                #[upgrade_or_default]
+                move || {
+                    let position = list_item.position();
+                    match list.model() {
+                        Some(selection) if selection.is_selected(position) && selection.selection().size() > 1 => {
+                            let bitset = selection.selection();
+                            (0..bitset.size() as u32).map(|i| bitset.nth(i)).collect()
+                        },
+                        _ => vec![position],
+                    }
+                }
+            ));
+            let drop_target = drag_and_drop::queue_drop_target(clone!(
+                #[strong]
+                sender,
+                #[weak]
+                list_item,
+                #[weak]
+                center_box,
+                #[upgrade_or]
+                false,
+                move |dropped, y| {
+                    drag_and_drop::clear_drop_indicator(&center_box);
+                    let index = if drag_and_drop::drops_below(&center_box, y) {
+                        list_item.position() + 1
+                    } else {
+                        list_item.position()
+                    };
+                    sender.input(TrackListMsg::Dropped { dropped, index: Some(index) });
+                    true
+                }
+            ));
+            center_box.add_controller(drop_target);
+            drag_and_drop::add_drop_indicator(&center_box);
+
             del_btn.connect_clicked(clone!(
                 #[strong]
                 sender,
@@ -301,57 +448,42 @@ impl AsyncComponent for TrackListWidget {
         sender: AsyncComponentSender<Self>,
         _root: &Self::Root,
     ) {
-        let player = self.mpris_player.imp();
+        let mpris_player = self.mpris_player.clone();
+        let player = mpris_player.imp();
         match message {
             TrackListMsg::TrackActivated(i) => {
                 player.send_res(player.goto(i).await);
             },
-            TrackListMsg::TrackChanged(pos) => {
-                let pos = match pos {
-                    Some(p) => p,
-                    None => player.track_list().borrow().current_index().unwrap_or(0),
-                };
+            TrackListMsg::TrackChanged => {
+                let guard = player.track_list().borrow();
+                self.fill_up_next(&guard);
+                let states = guard.position_states();
 
-                let model = widgets.list.model();
-                if let Some(model) = model {
-                    model.iter::<Object>().enumerate().for_each(|x| {
-                        if let (i, Ok(song)) = x {
+                if let Some(model) = widgets.list.model() {
+                    model.iter::<Object>().zip(states).for_each(|(song, state)| {
+                        if let Ok(song) = song {
                             song
                                 .downcast::<SongObject>()
                                 .expect("Must be SongObject.")
-                                .set_position_state(
-                                    if i < pos {
-                                        PositionState::Passed
-                                    } else if i > pos {
-                                        PositionState::Upcoming
-                                    } else {
-                                        PositionState::Current
-                                    }
-                                );
+                                .set_position_state(state);
                         }
                     });
                 }
             },
             TrackListMsg::ReloadList => {
                 let guard = player.track_list().borrow();
-                let pos = guard.current_index().unwrap_or(0);
+                self.fill_up_next(&guard);
                 let songs = guard.get_songs();
                 if songs.len() == 0 {
                     if let Some(model) = widgets.list.model() {
-                        if let Some(model) = model.downcast::<gtk::NoSelection>().expect("Should be no selection").model(){
+                        if let Some(model) = model.downcast::<gtk::MultiSelection>().expect("Should be multi selection").model(){
                             model.downcast::<ListStore>().expect("Should be ListStore").remove_all();
                         }
                     }
                     widgets.duration.set_label("");
                 } else {
-                    let list_store = ListStore::from_iter(songs.iter().enumerate().map(|x1| {
-                        SongObject::new(x1.1.clone(), if x1.0 < pos {
-                            PositionState::Passed
-                        } else if x1.0 > pos {
-                            PositionState::Upcoming
-                        } else {
-                            PositionState::Current
-                        })
+                    let list_store = ListStore::from_iter(songs.iter().zip(guard.position_states()).map(|(entry, state)| {
+                        SongObject::new(entry.clone(), state)
                     }));
                     let mut secs: u64 = songs.iter().map(|x| x.song.duration.unwrap_or(Duration::ZERO).as_secs()).sum();
                     let mut mins = secs / 60;
@@ -372,12 +504,12 @@ impl AsyncComponent for TrackListWidget {
                     str.push_str("s");
                     widgets.duration.set_label(&str);
 
-                    widgets.list.set_model(Some(&gtk::NoSelection::new(Some(list_store))));
+                    widgets.list.set_model(Some(&gtk::MultiSelection::new(Some(list_store))));
                 }
             },
             TrackListMsg::MoveItem { index, direction } => {
                 if let Some(model) = widgets.list.model() {
-                    let model = model.downcast::<gtk::NoSelection>().expect("Model should be NoSelection");
+                    let model = model.downcast::<gtk::MultiSelection>().expect("Model should be MultiSelection");
                     if let Some(model) = model.model() {
                         let model = model.downcast::<ListStore>().expect("Model should be ListStore");
                         if let Some(item) = model.item(index) {
@@ -396,8 +528,161 @@ impl AsyncComponent for TrackListWidget {
             },
             TrackListMsg::RemoveTrack(index) => {
                 player.send_res(player.remove(index as usize).await);
+            },
+            TrackListMsg::ShowSong(id) => {
+                let index = {
+                    let guard = player.track_list().borrow();
+                    let current = guard.current_index().unwrap_or(0);
+                    let songs = guard.get_songs();
+                    songs.iter().skip(current).position(|s| s.song.id == id).map(|i| i + current)
+                        .or_else(|| songs.iter().position(|s| s.song.id == id))
+                };
+                if let Some(index) = index {
+                    widgets.list.scroll_to(index as u32, gtk::ListScrollFlags::FOCUS | gtk::ListScrollFlags::SELECT, None);
+                }
+            },
+            TrackListMsg::Dropped { dropped, index } => {
+                let index = index.map(|i| i as usize);
+                match dropped {
+                    Dropped::QueueRows(rows) => {
+                        let rows = rows.into_iter().map(|i| i as usize).collect();
+                        player.send_res(player.move_items(rows, index.unwrap_or(usize::MAX)).await);
+                    },
+                    Dropped::Uris(uris) => {
+                        player.send_res(player.insert_uris(uris, index).await);
+                    },
+                }
+            },
+            TrackListMsg::UpNextActivated(index) => {
+                if let Some(index) = index.checked_sub(self.up_next_offset) {
+                    player.send_res(player.goto_up_next(index as usize).await);
+                }
+            },
+            TrackListMsg::RemoveUpNext(index) => {
+                if let Some(index) = index.checked_sub(self.up_next_offset) {
+                    player.send_res(player.remove_up_next(index as usize));
+                }
+            },
+            TrackListMsg::ClearUpNext => {
+                player.send_res(player.clear_up_next().await);
             }
         };
         self.update_view(widgets, sender);
     }
 }
+
+impl TrackListWidget {
+    fn fill_up_next(&mut self, track_list: &TrackList) {
+        let playing = track_list.playing_up_next()
+            .map(|e| SongObject::new(e.clone(), PositionState::Current));
+        self.up_next_offset = playing.is_some() as u32;
+        let upcoming = track_list.up_next()
+            .iter()
+            .map(|e| SongObject::new(e.clone(), PositionState::Upcoming));
+        let items: Vec<SongObject> = playing.into_iter().chain(upcoming).collect();
+        self.up_next_store.splice(0, self.up_next_store.n_items(), &items);
+    }
+}
+
+fn setup_up_next_factory(factory: &SignalListItemFactory, cover_cache: &CoverCache, sender: &AsyncComponentSender<TrackListWidget>) {
+    factory.connect_setup(clone!(
+        #[strong]
+        cover_cache,
+        #[strong]
+        sender,
+        move |_, list_item| {
+            let center_box = gtk::CenterBox::builder()
+                .orientation(Orientation::Horizontal)
+                .hexpand(true)
+                .build();
+            let hbox = gtk::Box::builder()
+                .orientation(Orientation::Horizontal)
+                .spacing(10)
+                .build();
+            let vbox = gtk::Box::builder()
+                .orientation(Orientation::Vertical)
+                .valign(Align::Center)
+                .build();
+
+            let title = gtk::Label::new(None);
+            title.set_halign(Align::Start);
+            title.add_css_class("bold");
+            title.set_max_width_chars(30);
+            title.set_ellipsize(EllipsizeMode::End);
+            vbox.append(&title);
+            let artist = gtk::Label::new(None);
+            artist.set_halign(Align::Start);
+            artist.set_max_width_chars(30);
+            artist.set_ellipsize(EllipsizeMode::End);
+            artist.set_use_markup(true);
+            artist.connect_activate_link(move |this, url| {
+                this.activate_action("win.artist", Some(&url.to_variant())).expect("Error executing action");
+                glib::Propagation::Stop
+            });
+            vbox.append(&artist);
+
+            let picture = CoverPicture::new(cover_cache.clone(), CoverSize::Small);
+            hbox.append(&picture);
+            hbox.append(&vbox);
+
+            let del_btn = gtk::Button::from_icon_name(icon_names::shipped::CROSS_SMALL_CIRCLE_OUTLINE);
+            del_btn.add_css_class("destructive-action");
+            del_btn.add_css_class("osd");
+            del_btn.add_css_class("circular");
+            del_btn.set_tooltip("Remove from up next");
+            del_btn.set_valign(Align::Center);
+
+            center_box.set_start_widget(Some(&hbox));
+            center_box.set_end_widget(Some(&del_btn));
+
+            let list_item = list_item
+                .downcast_ref::<ListItem>()
+                .expect("Needs to be ListItem");
+            list_item.set_child(Some(&center_box));
+
+            del_btn.connect_clicked(clone!(
+                #[strong]
+                sender,
+                #[weak]
+                list_item,
+                move |_| {
+                    sender.input(TrackListMsg::RemoveUpNext(list_item.position()));
+                }
+            ));
+
+            list_item
+                .property_expression("item")
+                .chain_property::<SongObject>("title")
+                .bind(&title, "label", Widget::NONE);
+            list_item
+                .property_expression("item")
+                .chain_property::<SongObject>("artist")
+                .bind(&artist, "label", Widget::NONE);
+            list_item
+                .property_expression("item")
+                .chain_property::<SongObject>("cover-art-id")
+                .bind(&picture, "cover-id", Widget::NONE);
+            list_item
+                .property_expression("item")
+                .chain_property::<SongObject>("position-state")
+                .chain_closure::<Vec<String>>(closure!(
+                    move |_: Option<Object>, position_state: PositionState| {
+                        match position_state {
+                            PositionState::Current => vec!["track-list-item".to_string(), "current".to_string()],
+                            _ => vec!["track-list-item".to_string(), "upcoming".to_string()],
+                        }
+                }))
+                .bind(&center_box, "css-classes", Widget::NONE);
+            // The playing song can't be removed from up next, only skipped
+            list_item
+                .property_expression("item")
+                .chain_property::<SongObject>("position-state")
+                .chain_closure::<bool>(closure!(
+                    move |_: Option<Object>, position_state: PositionState| {
+                        position_state != PositionState::Current
+                    }
+                ))
+                .bind(&del_btn, "visible", Widget::NONE);
+        }
+    ));
+}